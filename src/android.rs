@@ -1,11 +1,22 @@
-#[allow(non_snake_case)]
 use crate::ENGINE_STATE;
 use jni::JNIEnv;
 use jni::objects::{GlobalRef, JClass, JObject, JString, JValue};
-use jni::sys::{jint, jobjectArray, jsize, jstring, jintArray};
+use jni::sys::{jint, jlongArray, jobject, jobjectArray, jsize, jstring, jintArray};
 use jni::JavaVM;
 use log::{info, Log, Metadata, Record};
-use std::sync::OnceLock;
+use crate::discovery::DiscoveryEvent;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+// 最近一次失败的错误，供 Java 侧决定重试、重新搜索设备还是提示用户
+static LAST_ERROR: Mutex<Option<crate::Error>> = Mutex::new(None);
+
+fn record_error(e: crate::Error) {
+    log::warn!("操作失败: {}", e);
+    if let Ok(mut last) = LAST_ERROR.lock() {
+        *last = Some(e);
+    }
+}
 
 // 1. 日志初始化
 #[allow(non_snake_case)]
@@ -130,29 +141,43 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_searchDevices(
 ) -> jobjectArray {
     let rt = tokio::runtime::Runtime::new().unwrap();
     let dlna_devices = rt.block_on(crate::discover_devices_core());
+    let items: Vec<(&str, &str)> = dlna_devices
+        .iter()
+        .map(|d| (d.friendly_name.as_str(), d.location.as_str()))
+        .collect();
+    new_device_item_array(&mut env, &items)
+}
 
+// 构造 DlnaDeviceItem[]，元素为 (名称, 描述文件地址)
+fn new_device_item_array(env: &mut JNIEnv, items: &[(&str, &str)]) -> jobjectArray {
     let cls = env
         .find_class("zju/bangdream/ktv/casting/DlnaDeviceItem")
         .unwrap();
     let array = env
-        .new_object_array(dlna_devices.len() as jsize, &cls, JObject::null())
+        .new_object_array(items.len() as jsize, &cls, JObject::null())
         .unwrap();
-    for (i, d) in dlna_devices.iter().enumerate() {
-        let name = env.new_string(&d.friendly_name).unwrap();
-        let loc = env.new_string(&d.location).unwrap();
-        let item = env
-            .new_object(
-                &cls,
-                "(Ljava/lang/String;Ljava/lang/String;)V",
-                &[(&name).into(), (&loc).into()],
-            )
-            .unwrap();
+    for (i, (friendly_name, location)) in items.iter().enumerate() {
+        let item = new_device_item(env, friendly_name, location);
         env.set_object_array_element(&array, i as jsize, item)
             .unwrap();
     }
     array.into_raw()
 }
 
+fn new_device_item<'local>(env: &mut JNIEnv<'local>, friendly_name: &str, location: &str) -> JObject<'local> {
+    let cls = env
+        .find_class("zju/bangdream/ktv/casting/DlnaDeviceItem")
+        .unwrap();
+    let name = env.new_string(friendly_name).unwrap();
+    let loc = env.new_string(location).unwrap();
+    env.new_object(
+        &cls,
+        "(Ljava/lang/String;Ljava/lang/String;)V",
+        &[(&name).into(), (&loc).into()],
+    )
+    .unwrap()
+}
+
 // 3. 核心初始化接口 (支持重复调用以更换设备)
 #[allow(non_snake_case)]
 #[unsafe(no_mangle)]
@@ -181,6 +206,7 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_startEngine(
             // 使用 match 代替 expect，防止 panic 污染全局锁
             if let Err(e) = crate::start_engine_core(base_url_str, room_id_str, loc_str, rt).await {
                 log::error!("Failed to start engine: {}", e);
+                record_error(e);
             }
         });
     });
@@ -203,14 +229,10 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryProgress(
     env: JNIEnv,
     _class: JClass,
 ) -> jintArray {
-    let (current, total) = if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            ctx.rt.block_on(crate::get_current_progress())
-        } else {
-            (-1, -1)
-        }
-    } else {
-        (-1, -1)
+    // get_current_progress 内部还会读取全局状态，先取出引擎再 block_on，不在持锁时等待
+    let (current, total) = match crate::engine_context() {
+        Ok(ctx) => ctx.rt.block_on(crate::get_current_progress()),
+        Err(_) => (-1, -1),
     };
 
     let result_array = env.new_int_array(2).expect("无法创建 Java 数组");
@@ -223,6 +245,43 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryProgress(
     result_array.into_raw()
 }
 
+// 6. 数据接口：获取完整播放状态
+// 返回 [当前秒, 总时长秒(未知为 -1), 时长来源(0 未知/1 设备/2 歌曲来源/3 mp4探测), 设备状态]
+// 设备状态：0 未知, 1 播放中, 2 暂停, 3 停止, 4 缓冲中, 5 无媒体
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryPlaybackPosition(
+    env: JNIEnv,
+    _class: JClass,
+) -> jintArray {
+    use crate::dlna_controller::TransportState;
+
+    let position = crate::engine_context()
+        .ok()
+        .and_then(|ctx| ctx.rt.block_on(crate::get_playback_position()));
+
+    let data: [jint; 4] = match position {
+        Some(p) => [
+            p.position_secs as jint,
+            p.duration_secs.map(|d| d as jint).unwrap_or(-1),
+            p.duration_source.map(|s| s.code()).unwrap_or(0),
+            match p.state {
+                Some(TransportState::Playing) => 1,
+                Some(TransportState::PausedPlayback) => 2,
+                Some(TransportState::Stopped) => 3,
+                Some(TransportState::Transitioning) => 4,
+                Some(TransportState::NoMediaPresent) => 5,
+                _ => 0,
+            },
+        ],
+        None => [-1, -1, 0, 0],
+    };
+
+    let result_array = env.new_int_array(4).expect("无法创建 Java 数组");
+    env.set_int_array_region(&result_array, 0, &data).expect("无法填充数组数据");
+    result_array.into_raw()
+}
+
 // 7. 控制接口：切歌
 #[allow(non_snake_case)]
 #[unsafe(no_mangle)]
@@ -238,21 +297,26 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_togglePause(
     _env: JNIEnv,
     _class: JClass,
 ) -> jint {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            return match ctx.rt.block_on(crate::toggle_pause_core()) {
-                Ok(is_playing) => {
-                    if is_playing {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                Err(_) => -1,
-            };
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::toggle_pause_core()) {
+        Ok(is_playing) => {
+            if is_playing {
+                1
+            } else {
+                0
+            }
+        }
+        Err(e) => {
+            record_error(e);
+            -1
         }
     }
-    -1
 }
 
 // 9. 控制接口：音量调节
@@ -264,15 +328,20 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setVolume(
     _class: JClass,
     volume: jint,
 ) -> jint {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            return match ctx.rt.block_on(crate::set_volume_core(volume as u32)) {
-                Ok(v) => v as jint,
-                Err(_) => -1,
-            };
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::set_volume_core(volume as u32)) {
+        Ok(v) => v as jint,
+        Err(e) => {
+            record_error(e);
+            -1
         }
     }
-    -1
 }
 
 // 10. 控制接口：获取当前音量
@@ -283,15 +352,20 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_getVolume(
     _env: JNIEnv,
     _class: JClass,
 ) -> jint {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            return match ctx.rt.block_on(crate::get_volume_core()) {
-                Ok(v) => v as jint,
-                Err(_) => -1,
-            };
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::get_volume_core()) {
+        Ok(v) => v as jint,
+        Err(e) => {
+            record_error(e);
+            -1
         }
     }
-    -1
 }
 
 
@@ -304,15 +378,20 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_jumpToSecs(
     _class: JClass,
     target_secs: jint,
 ) -> jint {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            return match ctx.rt.block_on(crate::jump_to_secs(target_secs as u32)) {
-                Ok(_) => 1,
-                Err(_) => -1
-            };
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::jump_to_secs(target_secs as u32)) {
+        Ok(_) => 1,
+        Err(e) => {
+            record_error(e);
+            -1
         }
     }
-    -1
 }
 
 // 12. 数据接口：获取当前歌曲标题
@@ -339,3 +418,467 @@ pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_getCurrentSongTitle(
         .expect("Couldn't create java string!")
         .into_raw()
 }
+
+// 13. 数据接口：获取最近一次错误的分类
+// 返回 [错误分类, 是否值得重试(0/1), 是否需要重新搜索设备(0/1)]；没有错误时为 [0, 0, 0]
+// 错误分类：1 网络, 2 HTTP 状态码, 3 SOAP Fault, 4 设备不支持服务, 5 设备不支持格式,
+//          6 上游解析, 7 歌单服务, 8 引擎状态, 9 解析失败, 10 其他 UPnP 错误,
+//          11 Seek 未生效, 12 浏览器播放器执行失败, 13 媒体服务器端口不可用
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryLastError(
+    env: JNIEnv,
+    _class: JClass,
+) -> jintArray {
+    let data: [jint; 3] = match LAST_ERROR.lock().ok().as_deref() {
+        Some(Some(e)) => [
+            e.kind_code(),
+            e.is_retryable() as jint,
+            e.needs_rediscovery() as jint,
+        ],
+        _ => [0, 0, 0],
+    };
+
+    let result_array = env.new_int_array(3).expect("无法创建 Java 数组");
+    env.set_int_array_region(&result_array, 0, &data).expect("无法填充数组数据");
+    result_array.into_raw()
+}
+
+// 14. 数据接口：获取最近一次错误的提示文字，没有错误时返回空串
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_getLastErrorMessage(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let message = match LAST_ERROR.lock().ok().as_deref() {
+        Some(Some(e)) => e.to_string(),
+        _ => String::new(),
+    };
+
+    env.new_string(message)
+        .expect("Couldn't create java string!")
+        .into_raw()
+}
+
+// 15. 派对模式：把设备加入当前投屏组（同步当前歌曲与进度）
+// 返回 1 表示成功，-1 表示失败
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_addRenderer(
+    mut env: JNIEnv,
+    _class: JClass,
+    target_location: JString,
+) -> jint {
+    let loc_str: String = env.get_string(&target_location).unwrap().into();
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::add_renderer_core(loc_str)) {
+        Ok(_) => 1,
+        Err(e) => {
+            record_error(e);
+            -1
+        }
+    }
+}
+
+// 16. 派对模式：把设备移出投屏组（主设备不能移除）
+// 返回 1 表示成功，-1 表示失败
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_removeRenderer(
+    mut env: JNIEnv,
+    _class: JClass,
+    target_location: JString,
+) -> jint {
+    let loc_str: String = env.get_string(&target_location).unwrap().into();
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::remove_renderer_core(loc_str)) {
+        Ok(_) => 1,
+        Err(e) => {
+            record_error(e);
+            -1
+        }
+    }
+}
+
+// 17. 数据接口：当前投屏组内的设备，主设备在前
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_listRenderers(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jobjectArray {
+    let status = crate::renderer_status();
+    let items: Vec<(&str, &str)> = status
+        .iter()
+        .map(|s| (s.friendly_name.as_str(), s.location.as_str()))
+        .collect();
+    new_device_item_array(&mut env, &items)
+}
+
+// 18. 数据接口：投屏组内各设备的健康状态，顺序与 listRenderers 一致
+// 每个元素：0 正常, 1 最近有失败, 2 离线
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryRendererHealth(
+    env: JNIEnv,
+    _class: JClass,
+) -> jintArray {
+    let data: Vec<jint> = crate::renderer_status()
+        .iter()
+        .map(|s| s.health.code())
+        .collect();
+
+    let result_array = env.new_int_array(data.len() as jsize).expect("无法创建 Java 数组");
+    env.set_int_array_region(&result_array, 0, &data).expect("无法填充数组数据");
+    result_array.into_raw()
+}
+
+// 19. 配置接口：设置持久化目录（传入 Context.getFilesDir()），应在 startEngine 之前调用
+// 设备兼容配置 renderer_quirks.json / renderer_profiles.json 存放在该目录
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setDataDir(
+    mut env: JNIEnv,
+    _class: JClass,
+    dir: JString,
+) {
+    let dir_str: String = env.get_string(&dir).unwrap().into();
+    crate::set_data_dir(dir_str);
+}
+
+// 20. 搜索接口：持续监听设备上下线（只需调用一次）
+// 设备出现时回调 RustEngine.onDeviceFound(name, location)，离线时回调 RustEngine.onDeviceLost(location)；
+// 调用时已在线的设备也会立即回调一次
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_watchDevices(
+    mut env: JNIEnv,
+    _class: JClass,
+) {
+    static WATCHING: AtomicBool = AtomicBool::new(false);
+    if let Err(e) = init_jni_log_bridge(&mut env) {
+        log::error!("初始化 JNI 回调失败: {}", e);
+        return;
+    }
+    if WATCHING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let service = crate::discovery::service();
+    let mut events = service.subscribe();
+    let existing = service.devices();
+    service.search_now();
+
+    std::thread::spawn(move || {
+        let Some(bridge) = LOG_BRIDGE.get() else { return; };
+        let Ok(mut env) = bridge.java_vm.attach_current_thread() else { return; };
+        let cls = <&JClass>::from(bridge.rust_engine_class.as_obj());
+
+        for device in existing {
+            notify_device_found(&mut env, cls, &device.friendly_name, &device.location);
+        }
+        loop {
+            match events.blocking_recv() {
+                Ok(DiscoveryEvent::Added(device)) => {
+                    notify_device_found(&mut env, cls, &device.friendly_name, &device.location);
+                }
+                Ok(DiscoveryEvent::Removed { location, .. }) => {
+                    let Ok(loc) = env.new_string(&location) else { continue; };
+                    let _ = env.call_static_method(
+                        cls,
+                        "onDeviceLost",
+                        "(Ljava/lang/String;)V",
+                        &[JValue::from(&loc)],
+                    );
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        WATCHING.store(false, Ordering::SeqCst);
+    });
+}
+
+fn notify_device_found(env: &mut JNIEnv, cls: &JClass, friendly_name: &str, location: &str) {
+    let Ok(name) = env.new_string(friendly_name) else { return; };
+    let Ok(loc) = env.new_string(location) else { return; };
+    let _ = env.call_static_method(
+        cls,
+        "onDeviceFound",
+        "(Ljava/lang/String;Ljava/lang/String;)V",
+        &[JValue::from(&name), JValue::from(&loc)],
+    );
+}
+
+// 21. 搜索接口：按 IP / 主机名手动添加设备（组播不通时使用）
+// 返回 DlnaDeviceItem，可直接把 location 传给 startEngine；找不到时返回 null
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_addDeviceByHost<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    host: JString<'local>,
+) -> jobject {
+    let host_str: String = env.get_string(&host).unwrap().into();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    match rt.block_on(crate::add_device_by_host_core(host_str)) {
+        Ok(device) => new_device_item(&mut env, &device.friendly_name, &device.location).into_raw(),
+        Err(e) => {
+            record_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// 22. 配置接口：只在指定网卡上搜索设备（名称如 "wlan0" 或该网卡的 IP），传空串表示所有网卡
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setInterface(
+    mut env: JNIEnv,
+    _class: JClass,
+    interface: JString,
+) {
+    let interface_str: String = env.get_string(&interface).unwrap().into();
+    crate::interfaces::set_preferred(Some(interface_str));
+}
+
+// 23. 数据接口：上次保存的会话 [baseUrl, roomId, 设备名称, 设备描述文件地址]，没有时返回 null
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_getSavedSession(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jobjectArray {
+    let Some(saved) = crate::session::load() else {
+        return std::ptr::null_mut();
+    };
+    let fields = [
+        saved.base_url.as_str(),
+        saved.room_id.as_str(),
+        saved.device_name.as_str(),
+        saved.device_location.as_str(),
+    ];
+    let cls = env.find_class("java/lang/String").unwrap();
+    let array = env
+        .new_object_array(fields.len() as jsize, &cls, JObject::null())
+        .unwrap();
+    for (i, field) in fields.iter().enumerate() {
+        let value = env.new_string(field).unwrap();
+        env.set_object_array_element(&array, i as jsize, value).unwrap();
+    }
+    array.into_raw()
+}
+
+// 24. 核心初始化接口：恢复上次的会话（重新发现设备、连接房间并从保存的进度继续）
+// 失败原因通过 queryLastError 获取
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_resumeSession(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    std::thread::spawn(move || {
+        crate::reset_engine();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.handle().clone();
+        handle.block_on(async {
+            if let Err(e) = crate::resume_session_core(rt).await {
+                log::error!("Failed to resume session: {}", e);
+                record_error(e);
+            }
+        });
+    });
+}
+
+// 25. 配置接口：清除保存的会话（用户选择重新输入房间/设备时调用）
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_clearSavedSession(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    crate::session::clear();
+}
+
+// 26. 配置接口：音量策略。fadeMs 为切歌淡出/淡入时长（0 关闭）；startVolume / maxVolume 传 -1 表示不设置；
+// quietHours 形如 "22:00-07:00"，传空串表示没有安静时段，此时段内音量不超过 quietMaxVolume
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setVolumePolicy(
+    mut env: JNIEnv,
+    _class: JClass,
+    fade_ms: jint,
+    start_volume: jint,
+    max_volume: jint,
+    quiet_hours: JString,
+    quiet_max_volume: jint,
+) {
+    let quiet_hours_str: String = env.get_string(&quiet_hours).unwrap().into();
+    let volume = |v: jint| (v >= 0).then(|| (v as u32).min(100));
+    crate::volume_policy::set_policy(Some(crate::volume_policy::VolumePolicy {
+        fade_ms: fade_ms.max(0) as u32,
+        start_volume: volume(start_volume),
+        max_volume: volume(max_volume),
+        quiet_hours: crate::volume_policy::QuietHours::parse(&quiet_hours_str, quiet_max_volume.max(0) as u32),
+    }));
+}
+
+// 27. 控制接口：相对跳转 deltaSecs 秒（负数为后退）
+// 返回跳转的目标秒数，失败返回 -1
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_seekRelative(
+    _env: JNIEnv,
+    _class: JClass,
+    delta_secs: jint,
+) -> jint {
+    let ctx = match crate::engine_context() {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            record_error(e);
+            return -1;
+        }
+    };
+    match ctx.rt.block_on(crate::seek_relative_core(delta_secs)) {
+        Ok(target) => target as jint,
+        Err(e) => {
+            record_error(e);
+            -1
+        }
+    }
+}
+
+// 28. 数据接口：已打开播放页（getMediaServerUrl() + "/player"）的浏览器
+// 返回 DlnaDeviceItem[]，location 形如 browser://<id>，可传给 startEngine / addRenderer；
+// 传 "browser://" 给 startEngine 表示使用最近连上的浏览器（还没有时等第一个连上的）
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_listBrowserPlayers(
+    mut env: JNIEnv,
+    _class: JClass,
+) -> jobjectArray {
+    let players = crate::browser_players();
+    let items: Vec<(&str, &str)> = players
+        .iter()
+        .map(|(name, location)| (name.as_str(), location.as_str()))
+        .collect();
+    new_device_item_array(&mut env, &items)
+}
+
+// 29. 配置接口：媒体服务器端口，传 0 由系统分配，传 -1 恢复默认（8080）；下次 startEngine 时生效
+// 端口被占用时自动改用之后的端口，实际地址通过 getMediaServerUrl 获取
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setMediaPort(
+    _env: JNIEnv,
+    _class: JClass,
+    port: jint,
+) {
+    crate::media_server::set_port(u16::try_from(port).ok());
+}
+
+// 30. 数据接口：媒体服务器实际使用的地址（如 "http://192.168.1.5:8081"），引擎未启动时返回 null
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_getMediaServerUrl(
+    env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    let Some(url) = crate::media_server_url() else {
+        return std::ptr::null_mut();
+    };
+    env.new_string(url)
+        .expect("Couldn't create java string!")
+        .into_raw()
+}
+
+// 31. 配置接口：本地媒体缓存的大小上限（MB，0 表示不缓存）与预取数量（0 表示不预取），传 -1 恢复默认（2048 MB / 2 首）
+// 缓存位于 setDataDir 目录下的 media_cache，下次 startEngine 时开始按新配置预取
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setCacheConfig(
+    _env: JNIEnv,
+    _class: JClass,
+    max_mb: jint,
+    prefetch_count: jint,
+) {
+    crate::media_cache::set_max_mb(u64::try_from(max_mb).ok());
+    crate::media_cache::set_prefetch_count(usize::try_from(prefetch_count).ok());
+}
+
+// 32. 数据接口：本地媒体缓存状态 [已用字节, 上限字节, 已缓存歌曲数, 正在下载的已下载字节, 正在下载的总字节]
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_queryCacheStatus(
+    env: JNIEnv,
+    _class: JClass,
+) -> jlongArray {
+    let status = crate::media_cache_status();
+    let complete = status.songs.iter().filter(|s| s.complete).count();
+    let (downloaded, total) = status
+        .songs
+        .iter()
+        .filter(|s| !s.complete)
+        .fold((0, 0), |(d, t), s| (d + s.downloaded, t + s.total));
+    let data: Vec<i64> = [status.used_bytes, status.max_bytes, complete as u64, downloaded, total]
+        .iter()
+        .map(|&v| v.min(i64::MAX as u64) as i64)
+        .collect();
+
+    let result_array = env.new_long_array(data.len() as jsize).expect("无法创建 Java 数组");
+    env.set_long_array_region(&result_array, 0, &data).expect("无法填充数组数据");
+    result_array.into_raw()
+}
+
+// 33. 配置接口：HLS 主播放列表的处理方式，传 "highest" / "lowest" / 码率上限（kbit/s，如 "3000"）时只给设备一个码率，
+// 传 "master" 或空字符串交给设备自己选（默认）；设备播放多码率直播卡住或报错时使用
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setHlsVariant(
+    mut env: JNIEnv,
+    _class: JClass,
+    policy: JString,
+) {
+    let policy_str: String = env.get_string(&policy).unwrap().into();
+    crate::hls::set_variant_policy(crate::hls::VariantPolicy::parse(&policy_str));
+}
+
+// 34. 配置接口：签名地址的鉴权传递规则（JSON 数组，格式同 signed_url_rules.json），传空字符串恢复默认
+// （数据目录下的 signed_url_rules.json，不存在时用内置的 CloudFront / Akamai 规则）
+// 返回 0 成功，-1 规则无法解析（详见 queryLastError，分类 9），此时保持原有规则
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+pub extern "C" fn Java_zju_bangdream_ktv_casting_RustEngine_setSignedUrlRules(
+    mut env: JNIEnv,
+    _class: JClass,
+    rules: JString,
+) -> jint {
+    let rules_str: String = env.get_string(&rules).unwrap().into();
+    if rules_str.trim().is_empty() {
+        crate::signed_url::set_rules(None);
+        return 0;
+    }
+    match crate::signed_url::parse_rules(&rules_str) {
+        Ok(rules) => {
+            crate::signed_url::set_rules(Some(rules));
+            0
+        }
+        Err(e) => {
+            record_error(e);
+            -1
+        }
+    }
+}
@@ -1,6 +1,16 @@
+use crate::error::{Error, Result};
 use reqwest::Client;
 use serde_json::Value;
 
+/// 解析得到的 BiliBili 媒体信息
+#[derive(Debug, Clone)]
+pub struct BilibiliMedia {
+    /// 视频直链
+    pub url: String,
+    /// 分P信息中的时长（秒），直链输入时为 None
+    pub duration_secs: Option<u32>,
+}
+
 /// 获取BiliBili视频直链
 ///
 /// # Arguments
@@ -8,25 +18,84 @@ use serde_json::Value;
 /// * `page` - 分P页码，默认为1
 ///
 /// # Returns
-/// * `Result<String, String>` - 返回直链URL或错误信息
-pub async fn get_bilibili_direct_link(bv_id: &str, page: Option<u32>) -> Result<String, String> {
+/// * `Result<String>` - 返回直链URL，失败时为 `Error::Upstream`
+pub async fn get_bilibili_direct_link(bv_id: &str, page: Option<u32>) -> Result<String> {
+    get_bilibili_media(bv_id, page).await.map(|m| m.url)
+}
+
+/// 获取BiliBili视频直链及分P时长
+///
+/// # Arguments
+/// * `bv_id` - 视频BV号（例如："BV1AP411x7YW"）
+/// * `page` - 分P页码，默认为1
+///
+/// # Returns
+/// * `Result<BilibiliMedia>` - 返回直链与时长，失败时为 `Error::Upstream`
+pub async fn get_bilibili_media(bv_id: &str, page: Option<u32>) -> Result<BilibiliMedia> {
     let client = Client::new();
     let page = page.unwrap_or(0);
 
     //如果bv_id本来就是一个URL，直接返回
     if bv_id.starts_with("http") {
-        return Ok(bv_id.to_string());
+        return Ok(BilibiliMedia {
+            url: bv_id.to_string(),
+            duration_secs: None,
+        });
     }
 
     // 第一步：获取CID
-    let cid = get_video_cid(&client, bv_id, page).await?;
+    let (cid, duration_secs) = get_video_cid(&client, bv_id, page).await?;
 
     // 第二步：获取视频直链
-    get_video_url(&client, bv_id, &cid).await
+    let url = get_video_url(&client, bv_id, &cid).await?;
+    Ok(BilibiliMedia { url, duration_secs })
 }
 
-/// 获取视频的CID（分集ID）
-async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String, String> {
+/// 获取BiliBili视频封面地址
+///
+/// # Arguments
+/// * `bv_id` - 视频BV号（例如："BV1AP411x7YW"）
+///
+/// # Returns
+/// * `Result<String>` - 返回封面图片URL，失败时为 `Error::Upstream`
+pub async fn get_bilibili_cover(bv_id: &str) -> Result<String> {
+    let client = Client::new();
+    let url = format!("https://api.bilibili.com/x/web-interface/view?bvid={}", bv_id);
+
+    let response = client
+        .get(&url)
+        .header("User-Agent", "Mozilla/5.0")
+        .send()
+        .await
+        .map_err(|e| Error::upstream(None, format!("请求视频信息失败: {}", e)))?;
+
+    let json: Value = response
+        .json()
+        .await
+        .map_err(|e| Error::upstream(None, format!("解析JSON失败: {}", e)))?;
+
+    check_api_code(&json)?;
+
+    let pic = json
+        .get("data")
+        .and_then(|d| d.get("pic"))
+        .and_then(|p| p.as_str())
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| Error::upstream(None, "无法获取视频封面"))?;
+
+    // 接口偶尔返回 http 或协议相对地址
+    Ok(match pic.strip_prefix("//") {
+        Some(rest) => format!("https://{}", rest),
+        None => pic.replacen("http://", "https://", 1),
+    })
+}
+
+/// 获取视频的CID（分集ID）及该分P的时长（秒）
+async fn get_video_cid(
+    client: &Client,
+    bv_id: &str,
+    page: u32,
+) -> Result<(String, Option<u32>)> {
     let url = format!("https://api.bilibili.com/x/player/pagelist?bvid={}", bv_id);
 
     let response = client
@@ -34,40 +103,36 @@ async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String
         .header("User-Agent", "Mozilla/5.0")
         .send()
         .await
-        .map_err(|e| format!("请求CID失败: {}", e))?;
+        .map_err(|e| Error::upstream(None, format!("请求CID失败: {}", e)))?;
 
     let json: Value = response
         .json()
         .await
-        .map_err(|e| format!("解析JSON失败: {}", e))?;
+        .map_err(|e| Error::upstream(None, format!("解析JSON失败: {}", e)))?;
 
     // 检查API返回状态
-    if json["code"].as_i64() != Some(0) {
-        return Err(format!(
-            "API错误:  {}",
-            json.get("message")
-                .and_then(|v| v.as_str())
-                .unwrap_or("未知错误")
-        ));
-    }
+    check_api_code(&json)?;
 
     // 检查分P是否存在
     let data = json
         .get("data")
         .and_then(|d| d.as_array())
-        .ok_or_else(|| "无效的数据格式".to_string())?;
+        .ok_or_else(|| Error::upstream(None, "无效的数据格式"))?;
 
     if data.is_empty() {
-        return Err("该视频没有可用的分P数据".to_string());
+        return Err(Error::upstream(None, "该视频没有可用的分P数据"));
     }
 
     let idx = page as usize;
     if idx >= data.len() {
-        return Err(format!(
-            "无效的分P: page={}, 有效范围: 0..{}, 总分P数: {}",
-            page,
-            data.len(),
-            data.len()
+        return Err(Error::upstream(
+            None,
+            format!(
+                "无效的分P: page={}, 有效范围: 0..{}, 总分P数: {}",
+                page,
+                data.len(),
+                data.len()
+            ),
         ));
     }
 
@@ -75,13 +140,18 @@ async fn get_video_cid(client: &Client, bv_id: &str, page: u32) -> Result<String
     let cid = data[idx]
         .get("cid")
         .and_then(|c| c.as_u64())
-        .ok_or_else(|| "无法获取CID".to_string())?;
-
-    Ok(cid.to_string())
+        .ok_or_else(|| Error::upstream(None, "无法获取CID"))?;
+    let duration = data[idx]
+        .get("duration")
+        .and_then(|d| d.as_u64())
+        .map(|d| d as u32)
+        .filter(|d| *d > 0);
+
+    Ok((cid.to_string(), duration))
 }
 
 /// 获取视频播放链接
-async fn get_video_url(client: &Client, bv_id: &str, cid: &str) -> Result<String, String> {
+async fn get_video_url(client: &Client, bv_id: &str, cid: &str) -> Result<String> {
     let url = format!(
         "https://api.bilibili.com/x/player/playurl?bvid={}&cid={}&qn=116&type=&otype=json&platform=html5&high_quality=1",
         bv_id, cid
@@ -92,22 +162,15 @@ async fn get_video_url(client: &Client, bv_id: &str, cid: &str) -> Result<String
         .header("User-Agent", "Mozilla/5.0")
         .send()
         .await
-        .map_err(|e| format!("请求视频链接失败: {}", e))?;
+        .map_err(|e| Error::upstream(None, format!("请求视频链接失败: {}", e)))?;
 
     let json: Value = response
         .json()
         .await
-        .map_err(|e| format!("解析JSON失败:  {}", e))?;
+        .map_err(|e| Error::upstream(None, format!("解析JSON失败: {}", e)))?;
 
     // 检查API返回状态
-    if json["code"].as_i64() != Some(0) {
-        return Err(format!(
-            "API错误: {}",
-            json.get("message")
-                .and_then(|v| v.as_str())
-                .unwrap_or("未知错误")
-        ));
-    }
+    check_api_code(&json)?;
 
     // 提取直链
     let video_url = json
@@ -116,11 +179,27 @@ async fn get_video_url(client: &Client, bv_id: &str, cid: &str) -> Result<String
         .and_then(|d| d.get(0))
         .and_then(|d| d.get("url"))
         .and_then(|u| u.as_str())
-        .ok_or_else(|| "无法获取视频链接".to_string())?;
+        .ok_or_else(|| Error::upstream(None, "无法获取视频链接"))?;
 
     Ok(video_url.to_string())
 }
 
+/// B 站接口统一用 `code` 表示结果，0 为成功
+fn check_api_code(json: &Value) -> Result<()> {
+    match json["code"].as_i64() {
+        Some(0) => Ok(()),
+        code => Err(Error::upstream(
+            code,
+            format!(
+                "API错误: {}",
+                json.get("message")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("未知错误")
+            ),
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,544 @@
+use crate::SharedState;
+use crate::dlna_controller::{EventService, TrackPosition, TransportState};
+use crate::dlna_events::{RendererEvent, StateChange};
+use crate::error::{Error, Result};
+use crate::renderer::{MediaLoad, Renderer, Subscription};
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use actix_ws::Message;
+use futures::future::BoxFuture;
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, broadcast, mpsc, oneshot};
+
+/// 浏览器播放器的地址：`browser://<id>`；不带 id 时使用最近连上的浏览器
+pub const LOCATION_PREFIX: &str = "browser://";
+/// 引擎启动时等待浏览器打开播放页的时间
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+const PLAYER_HTML: &str = include_str!("player.html");
+// 等待浏览器执行命令（load 要等到拿到媒体元数据）
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(15);
+// 浏览器断线后等待播放页自动重连的时间
+const RECONNECT_WAIT: Duration = Duration::from_secs(10);
+
+/// 解析浏览器播放器地址，返回其中的 id（可能为空）；不是浏览器地址时返回 None
+pub fn parse_location(location: &str) -> Option<&str> {
+    location.strip_prefix(LOCATION_PREFIX).map(|id| id.trim_end_matches('/'))
+}
+
+// 播放页上报的状态
+#[derive(Debug, Clone, Default)]
+struct PlayerStatus {
+    state: Option<TransportState>,
+    position_secs: u32,
+    duration_secs: Option<u32>,
+    src: String,
+    volume: u32,
+}
+
+impl PlayerStatus {
+    fn from_json(value: &Value) -> Self {
+        let secs = |k: &str| value.get(k).and_then(Value::as_f64).filter(|s| s.is_finite() && *s >= 0.0);
+        Self {
+            state: value.get("state").and_then(Value::as_str).map(TransportState::parse),
+            position_secs: secs("position").unwrap_or(0.0) as u32,
+            duration_secs: secs("duration").map(|s| s as u32).filter(|s| *s > 0),
+            src: value.get("src").and_then(Value::as_str).unwrap_or_default().to_string(),
+            volume: value.get("volume").and_then(Value::as_u64).unwrap_or(0).min(100) as u32,
+        }
+    }
+
+    // 与上一次相比变化的状态，作为事件上报
+    fn changes_since(&self, old: &PlayerStatus) -> Vec<StateChange> {
+        let mut changes = Vec::new();
+        if let Some(state) = &self.state
+            && old.state.as_ref() != Some(state)
+        {
+            changes.push(StateChange::TransportState(state.clone()));
+        }
+        if !self.src.is_empty() && self.src != old.src {
+            changes.push(StateChange::CurrentTrackUri(self.src.clone()));
+        }
+        if self.volume != old.volume {
+            changes.push(StateChange::Volume(self.volume));
+        }
+        changes
+    }
+}
+
+/// 打开了 `/player` 的浏览器（一次 WebSocket 连接），作为播放目标接收
+/// 与 DLNA 设备相同的 load/play/pause/seek/volume 命令，并通过 WebSocket 上报进度
+pub struct BrowserRenderer {
+    player_id: String,
+    name: String,
+    location: String,
+    connected_at: Instant,
+    connected: Arc<AtomicBool>,
+    commands: mpsc::UnboundedSender<String>,
+    next_seq: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<()>>>>,
+    status: Mutex<PlayerStatus>,
+    // 作为主设备被订阅时，状态变化以 RendererEvent 转发给监控
+    subscribed: Arc<AtomicBool>,
+    event_seq: AtomicU32,
+}
+
+impl BrowserRenderer {
+    fn new(id: String, name: String, commands: mpsc::UnboundedSender<String>) -> Self {
+        Self {
+            location: format!("{}{}", LOCATION_PREFIX, id),
+            player_id: id,
+            name,
+            connected_at: Instant::now(),
+            connected: Arc::new(AtomicBool::new(true)),
+            commands,
+            next_seq: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            status: Mutex::new(PlayerStatus::default()),
+            subscribed: Arc::new(AtomicBool::new(false)),
+            event_seq: AtomicU32::new(0),
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn disconnected(&self) -> Error {
+        Error::Network(format!("浏览器 {} 已断开", self.name))
+    }
+
+    // 发送命令并等待播放页确认
+    async fn command(&self, action: &str, mut payload: Value) -> Result<()> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        payload["cmd"] = json!(action);
+        payload["seq"] = json!(seq);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(seq, tx);
+        debug!("发送给浏览器 {}: {}", self.name, payload);
+        if self.commands.send(payload.to_string()).is_err() {
+            self.pending.lock().unwrap().remove(&seq);
+            return Err(self.disconnected());
+        }
+        match tokio::time::timeout(COMMAND_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            // 连接关闭时未完成的命令被丢弃
+            Ok(Err(_)) => Err(self.disconnected()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&seq);
+                Err(Error::Network(format!("浏览器 {} 没有响应 {}", self.name, action)))
+            }
+        }
+    }
+
+    fn on_message(&self, text: &str, renderer_events: &broadcast::Sender<RendererEvent>) {
+        let Ok(value) = serde_json::from_str::<Value>(text) else {
+            debug!("浏览器消息不是 JSON: {}", text);
+            return;
+        };
+        match value.get("type").and_then(Value::as_str) {
+            Some("ack") => {
+                let Some(seq) = value.get("seq").and_then(Value::as_u64) else {
+                    return;
+                };
+                let Some(tx) = self.pending.lock().unwrap().remove(&seq) else {
+                    return;
+                };
+                let result = if value.get("ok").and_then(Value::as_bool).unwrap_or(false) {
+                    Ok(())
+                } else {
+                    Err(Error::Player {
+                        action: value.get("cmd").and_then(Value::as_str).unwrap_or("命令").to_string(),
+                        message: value.get("error").and_then(Value::as_str).unwrap_or("未知错误").to_string(),
+                    })
+                };
+                let _ = tx.send(result);
+            }
+            Some("status") => {
+                let status = PlayerStatus::from_json(&value);
+                let changes = {
+                    let mut current = self.status.lock().unwrap();
+                    let changes = status.changes_since(&current);
+                    *current = status;
+                    changes
+                };
+                if !changes.is_empty() && self.subscribed.load(Ordering::SeqCst) {
+                    debug!("浏览器 {} 状态变化: {:?}", self.name, changes);
+                    let _ = renderer_events.send(RendererEvent {
+                        service: EventService::AVTransport,
+                        sid: self.location.clone(),
+                        seq: self.event_seq.fetch_add(1, Ordering::Relaxed),
+                        changes,
+                    });
+                }
+            }
+            other => debug!("忽略浏览器消息类型 {:?}", other),
+        }
+    }
+
+    // 连接关闭：未完成的命令立即失败
+    fn close(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+struct PlayerSubscription {
+    // 状态事件的 SID 为播放器地址
+    location: String,
+    connected: Arc<AtomicBool>,
+    subscribed: Arc<AtomicBool>,
+}
+
+impl Subscription for PlayerSubscription {
+    fn is_active(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    fn owns(&self, sid: &str) -> bool {
+        sid == self.location
+    }
+}
+
+impl Drop for PlayerSubscription {
+    fn drop(&mut self) {
+        self.subscribed.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Renderer for BrowserRenderer {
+    fn id(&self) -> &str {
+        &self.location
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn udn(&self) -> &str {
+        &self.player_id
+    }
+
+    // 浏览器能否播放由它自己决定，失败时在 load 的确认里报告
+    fn negotiate(&self, _content_type: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    // 播放页与媒体服务器同源，地址只发路径，不受本机 IP 选择影响
+    fn load<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>> {
+        let cover = media
+            .metadata
+            .album_art_url
+            .as_deref()
+            .and_then(|u| url::Url::parse(u).ok())
+            .map(|u| u.path().to_string());
+        Box::pin(self.command(
+            "load",
+            json!({
+                "src": format!("/{}", media.uri_path),
+                "title": media.metadata.title,
+                "artist": media.metadata.artist,
+                "cover": cover,
+            }),
+        ))
+    }
+
+    fn load_next<'a>(&'a self, _media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Err(Error::UnsupportedService("SetNextAVTransportURI")) })
+    }
+
+    fn play(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.command("play", json!({})))
+    }
+
+    fn pause(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.command("pause", json!({})))
+    }
+
+    fn stop(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.command("stop", json!({})))
+    }
+
+    // 播放页在 seeked 事件后才确认
+    fn seek(&self, secs: u32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.command("seek", json!({ "secs": secs })))
+    }
+
+    fn set_volume(&self, volume: u32) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.command("volume", json!({ "volume": volume.min(100) })))
+    }
+
+    fn get_volume(&self) -> BoxFuture<'_, Result<u32>> {
+        Box::pin(async move {
+            if !self.is_connected() {
+                return Err(self.disconnected());
+            }
+            Ok(self.status.lock().unwrap().volume)
+        })
+    }
+
+    fn position(&self) -> BoxFuture<'_, Result<TrackPosition>> {
+        Box::pin(async move {
+            if !self.is_connected() {
+                return Err(self.disconnected());
+            }
+            let status = self.status.lock().unwrap();
+            Ok(TrackPosition {
+                position_secs: status.position_secs,
+                duration_secs: status.duration_secs,
+                track_uri: status.src.clone(),
+            })
+        })
+    }
+
+    fn transport_state(&self) -> BoxFuture<'_, Result<TransportState>> {
+        Box::pin(async move {
+            if !self.is_connected() {
+                return Err(self.disconnected());
+            }
+            Ok(self
+                .status
+                .lock()
+                .unwrap()
+                .state
+                .clone()
+                .unwrap_or(TransportState::NoMediaPresent))
+        })
+    }
+
+    fn subscribe<'a>(&'a self, _callback_base: &'a str) -> BoxFuture<'a, Result<Box<dyn Subscription>>> {
+        Box::pin(async move {
+            if !self.is_connected() {
+                return Err(self.disconnected());
+            }
+            self.subscribed.store(true, Ordering::SeqCst);
+            Ok(Box::new(PlayerSubscription {
+                location: self.location.clone(),
+                connected: self.connected.clone(),
+                subscribed: self.subscribed.clone(),
+            }) as Box<dyn Subscription>)
+        })
+    }
+
+    // 播放页断线后会用同一个 id 自动重连
+    fn reconnect(&self) -> BoxFuture<'_, Result<Arc<dyn Renderer>>> {
+        Box::pin(async move {
+            match hub().wait_for(&self.player_id, RECONNECT_WAIT).await {
+                Some(player) => Ok(player as Arc<dyn Renderer>),
+                None => Err(Error::Network(format!("浏览器 {} 没有重新连接", self.name))),
+            }
+        })
+    }
+}
+
+/// 已连接的浏览器播放器
+pub struct BrowserHub {
+    players: Mutex<HashMap<String, Arc<BrowserRenderer>>>,
+    joined: Notify,
+}
+
+static HUB: OnceLock<BrowserHub> = OnceLock::new();
+
+/// 全局的浏览器播放器列表
+pub fn hub() -> &'static BrowserHub {
+    HUB.get_or_init(|| BrowserHub {
+        players: Mutex::new(HashMap::new()),
+        joined: Notify::new(),
+    })
+}
+
+impl BrowserHub {
+    /// 当前连接着的浏览器，元素为 (名称, 地址)，地址可直接用于 start_engine_core / add_renderer_core
+    pub fn players(&self) -> Vec<(String, String)> {
+        let mut players: Vec<_> = self
+            .players
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| p.is_connected())
+            .cloned()
+            .collect();
+        players.sort_by_key(|p| p.connected_at);
+        players.into_iter().map(|p| (p.name.clone(), p.location.clone())).collect()
+    }
+
+    fn register(&self, player: Arc<BrowserRenderer>) {
+        info!("浏览器播放器已连接: {} ({})", player.name, player.location);
+        self.players.lock().unwrap().insert(player.player_id.clone(), player);
+        self.joined.notify_waiters();
+    }
+
+    fn unregister(&self, player: &Arc<BrowserRenderer>) {
+        info!("浏览器播放器已断开: {}", player.name);
+        let mut players = self.players.lock().unwrap();
+        // 同一 id 已经重连时保留新的连接
+        if players.get(&player.player_id).is_some_and(|p| Arc::ptr_eq(p, player)) {
+            players.remove(&player.player_id);
+        }
+    }
+
+    // id 为空时取最近连上的浏览器
+    fn find(&self, id: &str) -> Option<Arc<BrowserRenderer>> {
+        let players = self.players.lock().unwrap();
+        players
+            .values()
+            .filter(|p| p.is_connected() && (id.is_empty() || p.player_id == id))
+            .max_by_key(|p| p.connected_at)
+            .cloned()
+    }
+
+    /// 等待指定的浏览器（id 为空时任意一个）连上，超时返回 None
+    pub async fn wait_for(&self, id: &str, timeout: Duration) -> Option<Arc<BrowserRenderer>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // 先登记等待再检查，避免错过检查之后的连接
+            let joined = self.joined.notified();
+            if let Some(player) = self.find(id) {
+                return Some(player);
+            }
+            if tokio::time::timeout_at(deadline, joined).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+/// 按 `browser://<id>` 地址等待浏览器连接，返回对应的播放目标
+pub async fn connect(location: &str, timeout: Duration) -> Result<Arc<dyn Renderer>> {
+    let id = parse_location(location).ok_or_else(|| Error::Parse(format!("不是浏览器播放器地址: {}", location)))?;
+    match hub().wait_for(id, timeout).await {
+        Some(player) => Ok(player as Arc<dyn Renderer>),
+        None => Err(Error::EngineState("等待浏览器打开播放页超时")),
+    }
+}
+
+/// 播放页，必须注册在 proxy_handler 之前
+#[get("/player")]
+pub async fn player_page() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(PLAYER_HTML)
+}
+
+/// 播放页的控制通道：第一条消息为 hello（id 与名称），之后服务端下发命令、播放页回 ack 并定时上报状态
+#[get("/player/ws")]
+pub async fn player_ws(
+    req: HttpRequest,
+    body: web::Payload,
+    shared_state: web::Data<SharedState>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let renderer_events = shared_state.renderer_events.clone();
+    actix_web::rt::spawn(run_socket(session, stream, renderer_events));
+    Ok(response)
+}
+
+async fn run_socket(
+    mut session: actix_ws::Session,
+    mut stream: actix_ws::MessageStream,
+    renderer_events: broadcast::Sender<RendererEvent>,
+) {
+    let Some((id, name)) = wait_hello(&mut session, &mut stream).await else {
+        let _ = session.close(None).await;
+        return;
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let player = Arc::new(BrowserRenderer::new(id, name, tx));
+    hub().register(player.clone());
+
+    loop {
+        tokio::select! {
+            Some(command) = rx.recv() => {
+                if session.text(command).await.is_err() {
+                    break;
+                }
+            }
+            message = stream.recv() => match message {
+                Some(Ok(Message::Text(text))) => player.on_message(&text, &renderer_events),
+                Some(Ok(Message::Ping(bytes))) => {
+                    let _ = session.pong(&bytes).await;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Err(e)) => {
+                    warn!("浏览器连接出错: {}", e);
+                    break;
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    player.close();
+    hub().unregister(&player);
+    let _ = session.close(None).await;
+}
+
+async fn wait_hello(
+    session: &mut actix_ws::Session,
+    stream: &mut actix_ws::MessageStream,
+) -> Option<(String, String)> {
+    loop {
+        match stream.recv().await? {
+            Ok(Message::Text(text)) => {
+                let value: Value = serde_json::from_str(&text).ok()?;
+                if value.get("type").and_then(Value::as_str) != Some("hello") {
+                    continue;
+                }
+                let id = value.get("id").and_then(Value::as_str).map(str::trim).filter(|s| !s.is_empty())?;
+                let name = value
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or("浏览器播放器");
+                return Some((id.to_string(), name.to_string()));
+            }
+            Ok(Message::Ping(bytes)) => {
+                session.pong(&bytes).await.ok()?;
+            }
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_status_changes() {
+        assert_eq!(parse_location("browser://a1b2c3"), Some("a1b2c3"));
+        assert_eq!(parse_location("browser://"), Some(""));
+        assert_eq!(parse_location("http://192.168.1.5:49152/desc.xml"), None);
+
+        let status = PlayerStatus::from_json(&json!({
+            "type": "status",
+            "state": "PLAYING",
+            "position": 12.7,
+            "duration": null,
+            "src": "http://192.168.1.2:8080/BV1xx411c7mD-p1",
+            "volume": 80
+        }));
+        assert_eq!(status.position_secs, 12);
+        assert_eq!(status.duration_secs, None);
+        let changes = status.changes_since(&PlayerStatus::default());
+        assert_eq!(
+            changes,
+            [
+                StateChange::TransportState(TransportState::Playing),
+                StateChange::CurrentTrackUri("http://192.168.1.2:8080/BV1xx411c7mD-p1".to_string()),
+                StateChange::Volume(80),
+            ]
+        );
+        // 只有进度变化时不产生事件
+        let later = PlayerStatus {
+            position_secs: 13,
+            ..status.clone()
+        };
+        assert!(later.changes_since(&status).is_empty());
+    }
+}
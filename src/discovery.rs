@@ -0,0 +1,781 @@
+use crate::dlna_controller::{AV_TRANSPORT, DlnaDevice};
+use crate::error::{Error, Result};
+use crate::interfaces::{self, NetInterface};
+use futures::stream::StreamExt;
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+const SSDP_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+// IPv6 链路范围的 SSDP 组播地址
+const SSDP_MULTICAST_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+const SSDP_PORT: u16 = 1900;
+const AV_TRANSPORT_ST: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+// 主动 M-SEARCH 的间隔与每次等待响应的时长
+const SEARCH_INTERVAL: Duration = Duration::from_secs(60);
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+// 设备没有给出 CACHE-CONTROL 时的默认有效期
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(1800);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 设备列表的增减
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    Added(Box<DlnaDevice>),
+    /// 收到 ssdp:byebye 或超过 max-age 未再出现
+    Removed { udn: String, location: String, friendly_name: String },
+}
+
+/// 注册表中的一台渲染器
+#[derive(Debug, Clone)]
+pub struct DiscoveredRenderer {
+    pub udn: String,
+    pub device: DlnaDevice,
+    pub last_seen: Instant,
+    pub max_age: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SsdpKind {
+    Alive,
+    ByeBye,
+    /// M-SEARCH 的单播响应
+    Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SsdpMessage {
+    kind: SsdpKind,
+    udn: String,
+    location: Option<String>,
+    max_age: Option<Duration>,
+}
+
+// 解析 NOTIFY / M-SEARCH 响应，其他报文（如别人发的 M-SEARCH）返回 None
+fn parse_ssdp_message(text: &str) -> Option<SsdpMessage> {
+    let mut lines = text.lines();
+    let start_line = lines.next()?.trim();
+    let mut headers = HashMap::new();
+    for line in lines {
+        if let Some((k, v)) = line.split_once(':') {
+            headers.insert(k.trim().to_ascii_uppercase(), v.trim().to_string());
+        }
+    }
+
+    let kind = if start_line.starts_with("NOTIFY") {
+        match headers.get("NTS").map(|v| v.to_ascii_lowercase()) {
+            Some(nts) if nts == "ssdp:alive" => SsdpKind::Alive,
+            Some(nts) if nts == "ssdp:byebye" => SsdpKind::ByeBye,
+            _ => return None,
+        }
+    } else if start_line.starts_with("HTTP/1.1 200") {
+        SsdpKind::Response
+    } else {
+        return None;
+    };
+
+    // USN 形如 "uuid:xxxx::urn:schemas-upnp-org:device:MediaRenderer:1"
+    let usn = headers.get("USN")?;
+    let udn = usn.split("::").next()?.trim().to_string();
+    if !udn.starts_with("uuid:") {
+        return None;
+    }
+    let max_age = headers.get("CACHE-CONTROL").and_then(|v| {
+        v.split(',')
+            .filter_map(|part| part.trim().split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("max-age"))
+            .and_then(|(_, secs)| secs.trim().parse().ok())
+            .map(Duration::from_secs)
+    });
+
+    Some(SsdpMessage {
+        kind,
+        udn,
+        location: headers.get("LOCATION").cloned(),
+        max_age,
+    })
+}
+
+/// 后台设备发现：监听 NOTIFY ssdp:alive / ssdp:byebye，并定期 M-SEARCH，
+/// 维护一个带最后出现时间的渲染器注册表，增减通过 `subscribe` 推送。
+pub struct DiscoveryService {
+    registry: RwLock<HashMap<String, DiscoveredRenderer>>,
+    // 已确认不是渲染器的设备，不再重复拉取描述文件
+    ignored: Mutex<HashSet<String>>,
+    // 正在拉取描述文件的设备
+    pending: Mutex<HashSet<String>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    rt: tokio::runtime::Runtime,
+}
+
+static SERVICE: OnceLock<Arc<DiscoveryService>> = OnceLock::new();
+
+fn start_service() -> Arc<DiscoveryService> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("ktv-discovery")
+        .enable_all()
+        .build()
+        .expect("无法创建设备发现 Runtime");
+    let (events, _) = broadcast::channel(64);
+    let service = Arc::new(DiscoveryService {
+        registry: RwLock::new(HashMap::new()),
+        ignored: Mutex::new(HashSet::new()),
+        pending: Mutex::new(HashSet::new()),
+        events,
+        rt,
+    });
+    service.rt.spawn(listen_notify(service.clone(), false));
+    service.rt.spawn(listen_notify(service.clone(), true));
+    service.rt.spawn(search_loop(service.clone()));
+    service.rt.spawn(sweep_loop(service.clone()));
+    info!("后台设备发现已启动");
+    service
+}
+
+/// 全局的发现服务，首次调用时启动
+pub fn service() -> Arc<DiscoveryService> {
+    SERVICE.get_or_init(start_service).clone()
+}
+
+/// 已经启动的发现服务（不会触发启动）
+pub fn running() -> Option<Arc<DiscoveryService>> {
+    SERVICE.get().cloned()
+}
+
+impl DiscoveryService {
+    /// 当前在线的渲染器，按发现顺序不保证
+    pub fn devices(&self) -> Vec<DlnaDevice> {
+        self.renderers().into_iter().map(|r| r.device).collect()
+    }
+
+    pub fn renderers(&self) -> Vec<DiscoveredRenderer> {
+        self.registry
+            .read()
+            .map(|r| r.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// 发现该设备时所在的网卡
+    pub fn interface_of(&self, location: &str) -> Option<NetInterface> {
+        self.registry
+            .read()
+            .ok()?
+            .values()
+            .find(|r| r.device.location == location)
+            .and_then(|r| r.device.interface.clone())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DiscoveryEvent> {
+        self.events.subscribe()
+    }
+
+    /// 立即发起一次 M-SEARCH（不等待结果）
+    pub fn search_now(self: &Arc<Self>) {
+        self.rt.spawn(search_once(self.clone()));
+    }
+
+    /// 等待设备出现：已有设备或有新设备加入后再等 `settle` 收集同一批响应，最多等 `timeout`
+    pub async fn wait_for_devices(&self, timeout: Duration, settle: Duration) -> Vec<DlnaDevice> {
+        let mut events = self.subscribe();
+        let deadline = tokio::time::Instant::now() + timeout;
+        if self.devices().is_empty() {
+            loop {
+                match tokio::time::timeout_at(deadline, events.recv()).await {
+                    Ok(Ok(DiscoveryEvent::Added(_))) | Ok(Err(broadcast::error::RecvError::Lagged(_))) => break,
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return self.devices(),
+                }
+            }
+        }
+        tokio::time::sleep_until(deadline.min(tokio::time::Instant::now() + settle)).await;
+        self.devices()
+    }
+
+    // 刷新已知设备的最后出现时间；未知设备或地址已变化（设备重启换了端口）时返回 false。
+    // 双栈设备会在 IPv4 与 IPv6 上各应答一次，另一地址族的地址视为同一设备仍在线，
+    // 只有原地址是无法直接访问的链路本地地址时才换成新地址
+    fn touch(&self, udn: &str, location: &str, max_age: Option<Duration>) -> bool {
+        let Ok(mut registry) = self.registry.write() else {
+            return false;
+        };
+        match registry.get_mut(udn) {
+            Some(entry) if entry.device.location == location || keeps_location(&entry.device.location, location) => {
+                entry.last_seen = Instant::now();
+                if let Some(max_age) = max_age {
+                    entry.max_age = max_age;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn insert(&self, udn: String, device: DlnaDevice, max_age: Option<Duration>) {
+        let (added, moved) = {
+            let Ok(mut registry) = self.registry.write() else {
+                return;
+            };
+            let previous = registry.insert(
+                udn.clone(),
+                DiscoveredRenderer {
+                    udn,
+                    device: device.clone(),
+                    last_seen: Instant::now(),
+                    max_age: max_age.unwrap_or(DEFAULT_MAX_AGE),
+                },
+            );
+            let is_new = previous.is_none();
+            let moved = previous.filter(|p| p.device.location != device.location);
+            // 地址变化按"旧地址离线、新地址上线"通知，设备列表据此更新
+            (is_new || moved.is_some(), moved)
+        };
+        if let Some(old) = moved {
+            info!("设备地址变化: {} ({} -> {})", device.friendly_name, old.device.location, device.location);
+            let _ = self.events.send(DiscoveryEvent::Removed {
+                udn: old.udn,
+                location: old.device.location,
+                friendly_name: old.device.friendly_name,
+            });
+        }
+        if added {
+            info!("发现设备: {} (位置: {})", device.friendly_name, device.location);
+            let _ = self.events.send(DiscoveryEvent::Added(Box::new(device)));
+        }
+    }
+
+    fn remove(&self, udn: &str, reason: &str) {
+        let removed = self.registry.write().ok().and_then(|mut r| r.remove(udn));
+        if let Some(entry) = removed {
+            info!("设备离线({}): {}", reason, entry.device.friendly_name);
+            let _ = self.events.send(DiscoveryEvent::Removed {
+                udn: entry.udn,
+                location: entry.device.location,
+                friendly_name: entry.device.friendly_name,
+            });
+        }
+    }
+
+    // 处理一条 alive / 响应：已知设备只刷新时间，未知设备拉取描述文件确认是渲染器。
+    // scope 为收到报文的网卡序号
+    async fn on_alive(self: Arc<Self>, udn: String, location: String, max_age: Option<Duration>, scope: Option<u32>) {
+        if self.touch(&udn, &location, max_age) {
+            return;
+        }
+        if self.ignored.lock().map(|i| i.contains(&udn)).unwrap_or(false) {
+            return;
+        }
+        if !self.pending.lock().map(|mut p| p.insert(udn.clone())).unwrap_or(false) {
+            return;
+        }
+
+        let result = async {
+            let uri = location.parse().ok()?;
+            rupnp::Device::from_url(uri)
+                .await
+                .inspect_err(|e| debug!("拉取设备描述失败 {}: {}", location, e))
+                .ok()
+        }
+        .await;
+        if let Ok(mut p) = self.pending.lock() {
+            p.remove(&udn);
+        }
+
+        match result {
+            Some(device) if is_renderer(&device) => {
+                let interface = interface_for_location(&location, scope);
+                self.insert(udn, DlnaDevice::from_rupnp(device).with_interface(interface), max_age);
+            }
+            Some(_) => {
+                if let Ok(mut i) = self.ignored.lock() {
+                    i.insert(udn);
+                }
+            }
+            // 描述文件暂时拉不到，下次 alive 再试
+            None => {}
+        }
+    }
+}
+
+// 描述文件地址中的 IP
+fn location_ip(location: &str) -> Option<IpAddr> {
+    match url::Url::parse(location).ok()?.host()? {
+        url::Host::Ipv4(v4) => Some(IpAddr::V4(v4)),
+        url::Host::Ipv6(v6) => Some(IpAddr::V6(v6)),
+        url::Host::Domain(_) => None,
+    }
+}
+
+/// 按描述文件地址中的 IP 选择设备能访问到的本机地址；scope 为收到设备报文的网卡，
+/// 优先在该网卡上选择，该网卡没有同一地址族的地址时再在所有网卡上选择
+pub(crate) fn interface_for_location(location: &str, scope: Option<u32>) -> Option<NetInterface> {
+    let ip = location_ip(location)?;
+    scope
+        .and_then(|s| interfaces::interface_for(ip, Some(s)))
+        .or_else(|| interfaces::interface_for(ip, None))
+}
+
+// 已登记的地址与新地址属于不同地址族时保留已登记的，除非它是链路本地地址而新地址不是
+// （HTTP 客户端无法在 URL 中携带 scope id，链路本地地址上的设备控制不一定可用）
+fn keeps_location(registered: &str, location: &str) -> bool {
+    let (Some(registered), Some(location)) = (location_ip(registered), location_ip(location)) else {
+        return false;
+    };
+    let link_local = |ip: IpAddr| matches!(ip, IpAddr::V6(v6) if interfaces::is_link_local(v6));
+    registered.is_ipv4() != location.is_ipv4() && (!link_local(registered) || link_local(location))
+}
+
+fn is_renderer(device: &rupnp::Device) -> bool {
+    device.device_type().to_string().contains("MediaRenderer")
+        && device.services().iter().any(|s| *s.service_type() == AV_TRANSPORT)
+}
+
+// 监听组播的 NOTIFY，IPv4 与 IPv6 各一个套接字
+async fn listen_notify(service: Arc<DiscoveryService>, ipv6: bool) {
+    let socket = match if ipv6 { bind_multicast_v6() } else { bind_multicast() } {
+        Ok(socket) => socket,
+        // 没有 IPv6 的网络很常见，不必提示
+        Err(e) if ipv6 => {
+            debug!("无法监听 IPv6 SSDP NOTIFY: {}", e);
+            return;
+        }
+        Err(e) => {
+            // 端口被占用等情况下仍可依赖定期 M-SEARCH
+            warn!("无法监听 SSDP NOTIFY，只使用定期搜索: {}", e);
+            return;
+        }
+    };
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("接收 SSDP 报文失败: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some(msg) = parse_ssdp_message(&String::from_utf8_lossy(&buf[..len])) else {
+            continue;
+        };
+        debug!("SSDP {:?} {} from {}", msg.kind, msg.udn, from);
+        match (msg.kind, msg.location) {
+            (SsdpKind::ByeBye, _) => service.remove(&msg.udn, "byebye"),
+            (_, Some(location)) => {
+                // IPv6 报文的来源地址带着收到它的网卡
+                let scope = match from {
+                    SocketAddr::V6(v6) if v6.scope_id() != 0 => Some(v6.scope_id()),
+                    _ => None,
+                };
+                tokio::spawn(service.clone().on_alive(msg.udn, location, msg.max_age, scope));
+            }
+            (_, None) => {}
+        }
+    }
+}
+
+fn bind_multicast() -> std::io::Result<tokio::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // 允许与系统或其他程序的 SSDP 服务共用 1900 端口
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    // 在每个搜索网卡上加入组播组，否则只会收到默认路由网卡上的 NOTIFY
+    let ifaces = interfaces::search_interfaces();
+    let mut joined = 0;
+    for iface in &ifaces {
+        let IpAddr::V4(addr) = iface.addr else {
+            continue;
+        };
+        match socket.join_multicast_v4(&SSDP_MULTICAST_ADDR, &addr) {
+            Ok(()) => joined += 1,
+            Err(e) => debug!("网卡 {} 加入组播失败: {}", iface, e),
+        }
+    }
+    if joined == 0 {
+        socket.join_multicast_v4(&SSDP_MULTICAST_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    }
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+fn bind_multicast_v6() -> std::io::Result<tokio::net::UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    // 与 IPv4 的套接字分开监听 1900 端口
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    // IPv6 组播按网卡序号加入
+    let mut joined = 0;
+    for iface in interfaces::search_interfaces().iter().filter(|i| i.addr.is_ipv6()) {
+        match socket.join_multicast_v6(&SSDP_MULTICAST_ADDR_V6, iface.index) {
+            Ok(()) => joined += 1,
+            Err(e) => debug!("网卡 {} 加入 IPv6 组播失败: {}", iface, e),
+        }
+    }
+    if joined == 0 {
+        socket.join_multicast_v6(&SSDP_MULTICAST_ADDR_V6, 0)?;
+    }
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+async fn search_loop(service: Arc<DiscoveryService>) {
+    loop {
+        search_once(service.clone()).await;
+        tokio::time::sleep(SEARCH_INTERVAL).await;
+    }
+}
+
+// 在每个网卡上各发一次 M-SEARCH，响应到达一个处理一个，不等超时结束
+async fn search_once(service: Arc<DiscoveryService>) {
+    let searches = interfaces::search_interfaces().into_iter().map(|iface| {
+        let service = service.clone();
+        async move {
+            let result = msearch(&iface, SEARCH_TIMEOUT, |msg| {
+                if let Some(location) = msg.location {
+                    tokio::spawn(service.clone().on_alive(msg.udn, location, msg.max_age, Some(iface.index)));
+                }
+            })
+            .await;
+            if let Err(e) = result {
+                warn!("网卡 {} 上 M-SEARCH 失败: {}", iface, e);
+            }
+        }
+    });
+    futures::future::join_all(searches).await;
+}
+
+/// 一次性搜索：在每个网卡上 M-SEARCH，等满 `timeout` 后返回所有渲染器（带发现网卡）
+pub(crate) async fn search_all(timeout: Duration) -> Vec<DlnaDevice> {
+    let searches = interfaces::search_interfaces().into_iter().map(|iface| async move {
+        let mut locations = Vec::new();
+        if let Err(e) = msearch(&iface, timeout, |msg| locations.extend(msg.location)).await {
+            warn!("网卡 {} 上 M-SEARCH 失败: {}", iface, e);
+        }
+        locations.into_iter().map(move |l| (l, iface.clone())).collect::<Vec<_>>()
+    });
+    let mut seen = HashSet::new();
+    let found: Vec<(String, NetInterface)> = futures::future::join_all(searches)
+        .await
+        .into_iter()
+        .flatten()
+        .filter(|(location, _)| seen.insert(location.clone()))
+        .collect();
+    debug!("发现设备总数: {}", found.len());
+
+    let fetches = found.into_iter().map(|(location, iface): (String, NetInterface)| async move {
+        match fetch_renderer(&location, Some(iface.index)).await {
+            Ok(device) => device,
+            Err(e) => {
+                log::error!("设备发现错误: {}", e);
+                None
+            }
+        }
+    });
+    futures::future::join_all(fetches).await.into_iter().flatten().collect()
+}
+
+// 从指定网卡发送 M-SEARCH 并在 timeout 内逐条回调响应
+async fn msearch(iface: &NetInterface, timeout: Duration, mut on_msg: impl FnMut(SsdpMessage)) -> std::io::Result<()> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::for_address(iface.socket_addr(0)), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&iface.socket_addr(0).into())?;
+    let (group, target) = match iface.addr {
+        IpAddr::V4(addr) => {
+            socket.set_multicast_if_v4(&addr)?;
+            socket.set_multicast_ttl_v4(2)?;
+            (IpAddr::V4(SSDP_MULTICAST_ADDR), SocketAddr::from((SSDP_MULTICAST_ADDR, SSDP_PORT)))
+        }
+        IpAddr::V6(_) => {
+            socket.set_multicast_if_v6(iface.index)?;
+            socket.set_multicast_hops_v6(2)?;
+            let target = SocketAddrV6::new(SSDP_MULTICAST_ADDR_V6, SSDP_PORT, 0, iface.index);
+            (IpAddr::V6(SSDP_MULTICAST_ADDR_V6), SocketAddr::V6(target))
+        }
+    };
+    let socket = tokio::net::UdpSocket::from_std(socket.into())?;
+
+    // HOST 为 239.255.255.250:1900 或 [ff02::c]:1900
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        SocketAddr::new(group, SSDP_PORT),
+        timeout.as_secs().clamp(1, 5),
+        AV_TRANSPORT_ST
+    );
+    // UDP 可能丢包，和 rupnp 一样发送多次
+    for _ in 0..3 {
+        socket.send_to(request.as_bytes(), target).await?;
+    }
+
+    let mut buf = vec![0u8; 4096];
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let Ok((len, _)) = received else {
+            continue;
+        };
+        if let Some(msg) = parse_ssdp_message(&String::from_utf8_lossy(&buf[..len]))
+            .filter(|m| m.kind == SsdpKind::Response)
+        {
+            on_msg(msg);
+        }
+    }
+    Ok(())
+}
+
+// 清理超过 max-age 未再出现的设备
+async fn sweep_loop(service: Arc<DiscoveryService>) {
+    loop {
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+        let expired: Vec<String> = service
+            .renderers()
+            .into_iter()
+            .filter(|r| r.last_seen.elapsed() > r.max_age)
+            .map(|r| r.udn)
+            .collect();
+        for udn in expired {
+            service.remove(&udn, "超时");
+        }
+    }
+}
+
+// 手动添加设备时尝试的描述文件端口与路径（常见电视/盒子的默认值）
+const PROBE_PORTS: &[u16] = &[
+    49152, 49153, 49154, 49494, 1400, 2869, 7676, 8080, 8200, 9197, 16500, 38400, 52235, 55000,
+];
+const PROBE_PATHS: &[&str] = &[
+    "/description.xml",
+    "/dmr.xml",
+    "/rootDesc.xml",
+    "/DeviceDescription.xml",
+    "/dd.xml",
+    "/upnp/desc.xml",
+    "/MediaRenderer/desc.xml",
+    "/xml/device_description.xml",
+    "/",
+];
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+const UNICAST_SEARCH_WAIT: Duration = Duration::from_secs(2);
+
+/// 按 IP / 主机名手动添加设备（酒店 Wi-Fi 等组播不通的环境）。
+///
+/// `host` 可以是 `192.168.1.20`、`192.168.1.20:49152`、`fd00::20`、`[fd00::20]:49152` 或完整的描述文件 URL。
+/// 先向该主机单播 M-SEARCH，再探测常见的描述文件端口与路径；找到的渲染器会加入发现列表。
+pub async fn probe_host(host: &str) -> Result<DlnaDevice> {
+    let host = host.trim();
+    if host.starts_with("http://") || host.starts_with("https://") {
+        return fetch_renderer(host, None)
+            .await?
+            .map(register_manual)
+            .ok_or(Error::UnsupportedService("AVTransport"));
+    }
+    let (hostname, port) = split_host_port(host)?;
+    info!("手动添加设备: {}", host);
+
+    // 1. 单播 M-SEARCH：设备即使不回应组播，通常也会回应发给它的搜索
+    for location in unicast_search(&hostname).await {
+        if let Ok(Some(device)) = fetch_renderer(&location, None).await {
+            return Ok(register_manual(device));
+        }
+    }
+
+    // 2. 并发探测常见端口与路径
+    let ports: Vec<u16> = match port {
+        Some(p) => vec![p],
+        None => PROBE_PORTS.to_vec(),
+    };
+    let candidates: Vec<String> = ports
+        .iter()
+        .flat_map(|port| PROBE_PATHS.iter().map(move |path| (port, path)))
+        .map(|(port, path)| format!("http://{}:{}{}", url_host(&hostname), port, path))
+        .collect();
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .timeout(PROBE_TIMEOUT)
+        .build()
+        .map_err(|e| Error::Network(format!("创建reqwest client失败: {}", e)))?;
+    let mut probes = futures::stream::iter(candidates)
+        .map(|url| {
+            let client = client.clone();
+            async move { looks_like_renderer_description(&client, &url).await.then_some(url) }
+        })
+        .buffer_unordered(16);
+    while let Some(result) = probes.next().await {
+        let Some(location) = result else {
+            continue;
+        };
+        debug!("找到设备描述: {}", location);
+        if let Ok(Some(device)) = fetch_renderer(&location, None).await {
+            return Ok(register_manual(device));
+        }
+    }
+
+    Err(Error::Network(format!("{} 上没有找到 DLNA 渲染器", host)))
+}
+
+fn register_manual(device: DlnaDevice) -> DlnaDevice {
+    let udn = device.device.udn().to_string();
+    service().insert(udn, device.clone(), None);
+    device
+}
+
+// IPv6 地址在 URL 中要加方括号
+fn url_host(hostname: &str) -> String {
+    if hostname.contains(':') {
+        format!("[{}]", hostname)
+    } else {
+        hostname.to_string()
+    }
+}
+
+// 拆出主机与端口；IPv6 地址带端口时写成 [fd00::20]:49152，不带端口时可省略方括号
+fn split_host_port(host: &str) -> Result<(String, Option<u16>)> {
+    if let Some(rest) = host.strip_prefix('[') {
+        let (h, after) = rest
+            .split_once(']')
+            .ok_or_else(|| Error::Parse(format!("地址无效: {}", host)))?;
+        let port = match after.strip_prefix(':') {
+            Some(p) => Some(p.parse().map_err(|_| Error::Parse(format!("端口无效: {}", host)))?),
+            None if after.is_empty() => None,
+            None => return Err(Error::Parse(format!("地址无效: {}", host))),
+        };
+        return Ok((h.to_string(), port));
+    }
+    if host.parse::<Ipv6Addr>().is_ok() {
+        return Ok((host.to_string(), None));
+    }
+    match host.rsplit_once(':') {
+        Some((h, p)) if !h.is_empty() => {
+            let port = p
+                .parse()
+                .map_err(|_| Error::Parse(format!("端口无效: {}", host)))?;
+            Ok((h.to_string(), Some(port)))
+        }
+        Some(_) => Err(Error::Parse(format!("地址无效: {}", host))),
+        None if host.is_empty() => Err(Error::Parse("地址为空".to_string())),
+        None => Ok((host.to_string(), None)),
+    }
+}
+
+// 向指定主机的 1900 端口发送 M-SEARCH，返回响应中的 LOCATION
+async fn unicast_search(hostname: &str) -> Vec<String> {
+    let Ok(mut addrs) = tokio::net::lookup_host((hostname, SSDP_PORT)).await else {
+        return Vec::new();
+    };
+    let Some(target) = addrs.next() else {
+        return Vec::new();
+    };
+    let bind: SocketAddr = if target.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let Ok(socket) = tokio::net::UdpSocket::bind(bind).await else {
+        return Vec::new();
+    };
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: {}\r\n\r\n",
+        target, AV_TRANSPORT_ST
+    );
+    if let Err(e) = socket.send_to(request.as_bytes(), target).await {
+        debug!("单播 M-SEARCH 发送失败: {}", e);
+        return Vec::new();
+    }
+
+    let mut locations = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let deadline = tokio::time::Instant::now() + UNICAST_SEARCH_WAIT;
+    while let Ok(Ok((len, _))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        if let Some(location) = parse_ssdp_message(&String::from_utf8_lossy(&buf[..len])).and_then(|m| m.location)
+            && !locations.contains(&location)
+        {
+            locations.push(location);
+            // 一般第一条就够用，不必等满
+            break;
+        }
+    }
+    locations
+}
+
+async fn looks_like_renderer_description(client: &reqwest::Client, url: &str) -> bool {
+    let Ok(resp) = client.get(url).send().await else {
+        return false;
+    };
+    if !resp.status().is_success() {
+        return false;
+    }
+    resp.text()
+        .await
+        .map(|body| body.contains("<root") && body.contains("MediaRenderer"))
+        .unwrap_or(false)
+}
+
+// 拉取描述文件；是渲染器时返回设备，不是时返回 None。scope 为收到设备报文的网卡
+async fn fetch_renderer(location: &str, scope: Option<u32>) -> Result<Option<DlnaDevice>> {
+    let uri = location
+        .parse()
+        .map_err(|_| Error::Parse(format!("无法解析设备地址: {}", location)))?;
+    let device = rupnp::Device::from_url(uri).await.map_err(|e| Error::upnp("GetDescription", e))?;
+    Ok(is_renderer(&device)
+        .then(|| DlnaDevice::from_rupnp(device).with_interface(interface_for_location(location, scope))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssdp_message() {
+        let alive = "NOTIFY * HTTP/1.1\r\n\
+            HOST: 239.255.255.250:1900\r\n\
+            CACHE-CONTROL: max-age=66\r\n\
+            LOCATION: http://192.168.1.20:49152/description.xml\r\n\
+            NT: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+            NTS: ssdp:alive\r\n\
+            USN: uuid:5e2f-tv::urn:schemas-upnp-org:device:MediaRenderer:1\r\n\r\n";
+        let msg = parse_ssdp_message(alive).unwrap();
+        assert_eq!(msg.kind, SsdpKind::Alive);
+        assert_eq!(msg.udn, "uuid:5e2f-tv");
+        assert_eq!(msg.location.as_deref(), Some("http://192.168.1.20:49152/description.xml"));
+        assert_eq!(msg.max_age, Some(Duration::from_secs(66)));
+
+        let byebye = "NOTIFY * HTTP/1.1\r\nnts: ssdp:byebye\r\nusn: uuid:5e2f-tv\r\n\r\n";
+        let msg = parse_ssdp_message(byebye).unwrap();
+        assert_eq!(msg.kind, SsdpKind::ByeBye);
+        assert_eq!(msg.location, None);
+
+        let response = "HTTP/1.1 200 OK\r\nCache-Control: no-cache, max-age=1800\r\nLocation: http://10.0.0.5:8080/dmr.xml\r\nUSN: uuid:abc::upnp:rootdevice\r\n\r\n";
+        let msg = parse_ssdp_message(response).unwrap();
+        assert_eq!(msg.kind, SsdpKind::Response);
+        assert_eq!(msg.max_age, Some(Duration::from_secs(1800)));
+
+        // 其他控制点发出的 M-SEARCH 不处理
+        assert!(parse_ssdp_message("M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:discover\"\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("192.168.1.20").unwrap(), ("192.168.1.20".to_string(), None));
+        assert_eq!(split_host_port("tv.lan:49152").unwrap(), ("tv.lan".to_string(), Some(49152)));
+        assert!(split_host_port("192.168.1.20:abc").is_err());
+        assert_eq!(split_host_port("fd00::20").unwrap(), ("fd00::20".to_string(), None));
+        assert_eq!(split_host_port("[fd00::20]:49152").unwrap(), ("fd00::20".to_string(), Some(49152)));
+        assert_eq!(split_host_port("[fe80::1]").unwrap(), ("fe80::1".to_string(), None));
+        assert!(split_host_port("[fd00::20").is_err());
+
+        // 双栈设备在另一地址族上的应答不替换已登记的地址，链路本地地址除外
+        let v4 = "http://192.168.1.20:49152/description.xml";
+        let ula = "http://[fd00::20]:49152/description.xml";
+        let link_local = "http://[fe80::20]:49152/description.xml";
+        assert!(keeps_location(v4, ula));
+        assert!(keeps_location(ula, v4));
+        assert!(!keeps_location(link_local, v4));
+        assert!(keeps_location(v4, link_local));
+        assert!(!keeps_location(ula, "http://[fd00::20]:49153/description.xml"));
+    }
+}
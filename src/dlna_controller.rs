@@ -1,37 +1,211 @@
-use chrono::{NaiveTime, Timelike};
-use futures::future::try_join_all;
-use futures::stream::StreamExt;
+use crate::duration_resolver::parse_upnp_duration;
+use crate::discovery;
+use crate::dlna_events::EventSubscriber;
+use crate::error::Error;
+use crate::interfaces::NetInterface;
+use crate::media_server;
+use crate::quirks::{self, ControlEndpoint, MetadataStyle, QuirkProfile};
+use crate::renderer::{MediaLoad, Renderer, Subscription};
+use futures::future::{BoxFuture, try_join_all};
 use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
 use rupnp::Device;
 use rupnp::http::Uri;
-use rupnp::ssdp::{SearchTarget, URN};
-use std::collections::{HashMap, HashSet};
+use rupnp::ssdp::URN;
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
-fn extract_xml_tag_value(xml: &str, tag: &str) -> Option<String> {
-    // 解析XML标签值，支持带命名空间属性的标签
-    let start_pattern = format!("<{}", tag);
-    let end_pattern = format!("</{}>", tag);
-
-    // 找到开始标签
-    if let Some(start_idx) = xml.find(&start_pattern) {
-        // 找到开始标签的结束位置 >
-        if let Some(tag_end_idx) = xml[start_idx..].find('>') {
-            let value_start = start_idx + tag_end_idx + 1;
-
-            // 找到对应的结束标签
-            if let Some(value_end) = xml[value_start..].find(&end_pattern) {
-                let value = &xml[value_start..value_start + value_end];
-                return Some(value.trim().to_string());
+// --- XML 解析 ---
+// SOAP 响应、UPnP Fault、LastChange 与 DIDL-Lite 都用 roxmltree 解析：命名空间前缀按 URI 解析、
+// 实体与 CDATA 由解析器解码。只有设备返回的 XML 本身不合法时才退回到字符串查找。
+
+// 部分设备在 DIDL-Lite 里使用未声明的前缀（常见于 dlna:、sec:），给它补一个占位声明
+const UNDECLARED_NS: &str = "urn:ktv-casting:undeclared";
+
+/// 解析 XML 后交给 `f` 读取；设备返回的正文前可能带 BOM/空白、末尾带 \0，
+/// 根元素用了未声明的前缀时补上声明后重试。无法解析时返回 None。
+pub(crate) fn with_xml<T>(text: &str, f: impl FnOnce(roxmltree::Document) -> T) -> Option<T> {
+    let mut source = text
+        .trim_start_matches('\u{feff}')
+        .trim_end_matches('\0')
+        .trim()
+        .to_string();
+    // 每次补一个前缀，设备一般只漏一两个
+    for _ in 0..4 {
+        match roxmltree::Document::parse(&source) {
+            Ok(doc) => return Some(f(doc)),
+            Err(roxmltree::Error::UnknownNamespace(prefix, _)) => {
+                let insert_at = root_name_end(&source)?;
+                source.insert_str(insert_at, &format!(r#" xmlns:{}="{}""#, prefix, UNDECLARED_NS));
+            }
+            Err(e) => {
+                log::debug!("XML 解析失败: {}", e);
+                return None;
+            }
+        }
+    }
+    None
+}
+
+// 根元素标签名结束的位置（跳过 <?xml ...?>、注释等）
+fn root_name_end(xml: &str) -> Option<usize> {
+    let mut search = 0;
+    while let Some(idx) = xml[search..].find('<') {
+        let start = search + idx + 1;
+        if xml[start..].starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            let len = xml[start..].find(|c: char| c.is_whitespace() || c == '/' || c == '>')?;
+            return Some(start + len);
+        }
+        search = start;
+    }
+    None
+}
+
+/// 按本地名（忽略命名空间前缀）查找第一个后代元素
+pub(crate) fn find_element<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.descendants()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// 元素内容：纯文本（含 CDATA）时返回解码后的文本；
+/// 设备把 DIDL-Lite 等 XML 不转义直接嵌进来时，原样返回内部的 XML 片段
+pub(crate) fn element_text(node: roxmltree::Node, source: &str) -> String {
+    if node.children().any(|c| c.is_element())
+        && let (Some(first), Some(last)) = (node.first_child(), node.last_child())
+    {
+        return source[first.range().start..last.range().end].trim().to_string();
+    }
+    node.children()
+        .filter_map(|c| c.text())
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// SOAP 响应 `<s:Body><u:ActionResponse>...</u:ActionResponse></s:Body>` 中的全部输出参数
+fn parse_soap_response(body: &str) -> Option<HashMap<String, String>> {
+    with_xml(body, |doc| {
+        let response = find_element(doc.root(), "Body")?
+            .children()
+            .find(|n| n.is_element())?;
+        Some(
+            response
+                .children()
+                .filter(|n| n.is_element())
+                .map(|n| (n.tag_name().name().to_string(), element_text(n, doc.input_text())))
+                .collect(),
+        )
+    })
+    .flatten()
+}
+
+// 设备返回的 XML 不合法时（常见于 URL 里未转义的 &）按标签名尽力查找
+fn scan_soap_response(body: &str, keys: &[&str]) -> HashMap<String, String> {
+    keys.iter()
+        .filter_map(|k| scan_tag_text(body, k).map(|v| (k.to_string(), v)))
+        .collect()
+}
+
+pub(crate) fn scan_tag_text(xml: &str, tag: &str) -> Option<String> {
+    // 同时匹配 <Tag> 与 <prefix:Tag>
+    let mut rest = xml;
+    while let Some(idx) = rest.find('<') {
+        let after = &rest[idx + 1..];
+        let name_len = after
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(after.len());
+        let qname = &after[..name_len];
+        let local = qname.rsplit(':').next().unwrap_or(qname);
+        if local == tag && !qname.starts_with('/') {
+            let open_end = after.find('>')?;
+            if after[..open_end].ends_with('/') {
+                return Some(String::new());
             }
+            let value = &after[open_end + 1..];
+            let end = value.find(&format!("</{}>", qname))?;
+            let value = value[..end].trim();
+            return Some(match value.strip_prefix("<![CDATA[").and_then(|v| v.strip_suffix("]]>")) {
+                Some(cdata) => cdata.to_string(),
+                None => xml_unescape(value),
+            });
+        }
+        rest = after;
+    }
+    None
+}
+
+/// 按字符串查找形如 `<Tag attr="value" .../>` 的属性值，LastChange 不是合法 XML 时使用
+/// （例如设备只转义了一层，URL 里的 & 解开后变成裸字符）。
+/// `filter` 用于在同名标签出现多次时挑选，例如 Volume 只取 `channel="Master"`。
+pub(crate) fn extract_xml_attr_value(
+    xml: &str,
+    tag: &str,
+    attr: &str,
+    filter: Option<(&str, &str)>,
+) -> Option<String> {
+    let start_pattern = format!("<{}", tag);
+    let mut rest = xml;
+
+    while let Some(start_idx) = rest.find(&start_pattern) {
+        let after_name = &rest[start_idx + start_pattern.len()..];
+        // 避免 <TransportState 匹配到 <TransportStatus
+        if !after_name.starts_with(|c: char| c.is_whitespace() || c == '/' || c == '>') {
+            rest = after_name;
+            continue;
+        }
+        let Some(tag_end) = after_name.find('>') else {
+            break;
+        };
+        let tag_body = &after_name[..tag_end];
+
+        let matches_filter = match filter {
+            Some((k, v)) => attr_in_tag(tag_body, k).is_none_or(|found| found.eq_ignore_ascii_case(v)),
+            None => true,
+        };
+        if matches_filter && let Some(value) = attr_in_tag(tag_body, attr) {
+            return Some(value);
         }
+        rest = &after_name[tag_end..];
     }
 
     None
 }
 
-fn xml_escape(s: &str) -> String {
+fn attr_in_tag(tag_body: &str, attr: &str) -> Option<String> {
+    let pattern = format!("{}=", attr);
+    let mut search = tag_body;
+    while let Some(idx) = search.find(&pattern) {
+        // 属性名前必须是空白，避免 val= 匹配到 xval=
+        let preceded_ok = search[..idx]
+            .chars()
+            .last()
+            .is_none_or(|c| c.is_whitespace());
+        let after = &search[idx + pattern.len()..];
+        if preceded_ok && let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let value = &after[1..];
+            if let Some(end) = value.find(quote) {
+                return Some(value[..end].to_string());
+            }
+        }
+        search = after;
+    }
+    None
+}
+
+pub(crate) fn xml_unescape(s: &str) -> String {
+    // 与 xml_escape 对应；&amp; 放最后，避免 "&amp;lt;" 被解成 "<"
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
     // Minimal XML escaping for element text nodes.
     // (Enough to keep SOAP XML well-formed when URLs contain & and friends.)
     s.replace('&', "&amp;")
@@ -41,8 +215,33 @@ fn xml_escape(s: &str) -> String {
         .replace('\'', "&apos;")
 }
 
-fn build_didl_lite_metadata(title: &str, media_url: &str, protocol_info: Option<&str>) -> String {
-    // Build a minimal DIDL-Lite and then XML-escape it for embedding into <CurrentURIMetaData>.
+/// SetAVTransportURI / SetNextAVTransportURI 中 DIDL-Lite 的内容，未知的字段不输出
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaMetadata {
+    /// 歌单里的歌曲标题，未知时用代理路径
+    pub title: Option<String>,
+    /// 点歌人（ktv-song-web 的 addedBy），写入 upnp:artist 与 dc:creator
+    pub artist: Option<String>,
+    pub duration_secs: Option<u32>,
+    /// 上游文件大小（Content-Length）
+    pub size: Option<u64>,
+    /// 封面地址（经本地代理），写入 upnp:albumArtURI
+    pub album_art_url: Option<String>,
+}
+
+// res@duration 使用 H+:MM:SS.F+ 格式
+fn format_res_duration(secs: u32) -> String {
+    format!("{}:{:02}:{:02}.000", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+fn build_didl_lite_metadata(
+    fallback_title: &str,
+    metadata: &MediaMetadata,
+    media_url: &str,
+    protocol_info: Option<&str>,
+    style: MetadataStyle,
+) -> String {
+    // Build a DIDL-Lite and then XML-escape it for embedding into <CurrentURIMetaData>.
     // Many renderers require at least: upnp:class + res@protocolInfo.
     // NOTE: avoid strict DLNA.ORG_PN profile binding; some renderers reject when profile ≠ actual.
     // Start permissive, then tighten if needed.
@@ -50,26 +249,104 @@ fn build_didl_lite_metadata(title: &str, media_url: &str, protocol_info: Option<
 
     // Important: the <res> inner URL should be XML-escaped *once* (so & -> &amp;).
     let res_url = xml_escape(media_url);
-
-    let didl = format!(
-        r#"<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">
-        <item id=\"0\" parentID=\"-1\" restricted=\"1\">
-        <dc:title>{}</dc:title>
-        <upnp:storageMedium>UNKNOWN</upnp:storageMedium>
-        <upnp:writeStatus>UNKNOWN</upnp:writeStatus>
-        <res protocolInfo=\"{}\">{}</res>
-        <upnp:class>object.item.videoItem</upnp:class>
-        </item>
-        </DIDL-Lite>"#,
-        xml_escape(title),
-        protocol,
-        res_url
-    );
+    let title = xml_escape(metadata.title.as_deref().unwrap_or(fallback_title));
+
+    let didl = match style {
+        MetadataStyle::Empty => return String::new(),
+        MetadataStyle::Minimal => format!(
+            r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/"><item id="0" parentID="-1" restricted="1"><dc:title>{}</dc:title><res protocolInfo="{}">{}</res><upnp:class>object.item.videoItem</upnp:class></item></DIDL-Lite>"#,
+            title, protocol, res_url
+        ),
+        MetadataStyle::Didl => {
+            let mut extra = String::new();
+            if let Some(artist) = &metadata.artist {
+                let artist = xml_escape(artist);
+                extra.push_str(&format!("<upnp:artist>{artist}</upnp:artist><dc:creator>{artist}</dc:creator>"));
+            }
+            if let Some(art) = &metadata.album_art_url {
+                extra.push_str(&format!(
+                    r#"<upnp:albumArtURI dlna:profileID="JPEG_TN">{}</upnp:albumArtURI>"#,
+                    xml_escape(art)
+                ));
+            }
+            let mut res_attrs = format!(r#"protocolInfo="{}""#, protocol);
+            if let Some(secs) = metadata.duration_secs.filter(|s| *s > 0) {
+                res_attrs.push_str(&format!(r#" duration="{}""#, format_res_duration(secs)));
+            }
+            if let Some(size) = metadata.size.filter(|s| *s > 0) {
+                res_attrs.push_str(&format!(r#" size="{}""#, size));
+            }
+            format!(
+                r#"<DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:upnp="urn:schemas-upnp-org:metadata-1-0/upnp/" xmlns:dlna="urn:schemas-dlna-org:metadata-1-0/"><item id="0" parentID="-1" restricted="1"><dc:title>{}</dc:title>{}<upnp:storageMedium>UNKNOWN</upnp:storageMedium><upnp:writeStatus>UNKNOWN</upnp:writeStatus><res {}>{}</res><upnp:class>object.item.videoItem</upnp:class></item></DIDL-Lite>"#,
+                title, extra, res_attrs, res_url
+            )
+        }
+    };
 
     // Embed metadata as escaped XML text nodes: <CurrentURIMetaData>&lt;DIDL-Lite ...&gt;...
     xml_escape(&didl)
 }
 
+const NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const NS_UPNP: &str = "urn:schemas-upnp-org:metadata-1-0/upnp/";
+
+/// DIDL-Lite 中第一个 item 的内容（GetMediaInfo / GetPositionInfo 返回的元数据）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DidlItem {
+    pub metadata: MediaMetadata,
+    /// upnp:class，如 "object.item.videoItem"
+    pub class: Option<String>,
+    pub res_url: Option<String>,
+    pub protocol_info: Option<String>,
+}
+
+/// 解析 DIDL-Lite（已解码的文本，即 CurrentURIMetaData / TrackMetaData 字段的值）。
+/// 空串、NOT_IMPLEMENTED 或无法解析时返回 None
+pub fn parse_didl_lite(xml: &str) -> Option<DidlItem> {
+    if !xml.trim_start().starts_with('<') {
+        return None;
+    }
+    with_xml(xml, |doc| {
+        let source = doc.input_text();
+        let item = doc
+            .descendants()
+            .find(|n| n.is_element() && matches!(n.tag_name().name(), "item" | "container"))?;
+        // dc:/upnp: 前缀按命名空间 URI 匹配；设备漏掉声明时（占位命名空间）只看本地名
+        let text = |ns: &str, name: &str| {
+            item.children()
+                .find(|n| {
+                    n.is_element()
+                        && n.tag_name().name() == name
+                        && n.tag_name().namespace().is_some_and(|uri| uri == ns || uri == UNDECLARED_NS)
+                })
+                .map(|n| element_text(n, source))
+                .filter(|t| !t.is_empty())
+        };
+        let res = item
+            .children()
+            .find(|n| n.is_element() && n.tag_name().name() == "res");
+        Some(DidlItem {
+            metadata: MediaMetadata {
+                title: text(NS_DC, "title"),
+                artist: text(NS_UPNP, "artist").or_else(|| text(NS_DC, "creator")),
+                duration_secs: res
+                    .and_then(|r| r.attribute("duration"))
+                    .and_then(parse_upnp_duration)
+                    .map(|d| d.as_secs() as u32)
+                    .filter(|s| *s > 0),
+                size: res
+                    .and_then(|r| r.attribute("size"))
+                    .and_then(|s| s.trim().parse().ok()),
+                album_art_url: text(NS_UPNP, "albumArtURI"),
+            },
+            class: text(NS_UPNP, "class"),
+            res_url: res.map(|r| element_text(r, source)).filter(|u| !u.is_empty()),
+            protocol_info: res.and_then(|r| r.attribute("protocolInfo")).map(str::to_string),
+        })
+    })
+    .flatten()
+}
+
 fn build_soap_envelope(action: &str, args_xml: &str) -> String {
     // Keep the shape consistent with what most renderers accept (and close to your B站抓包).
     // Note: `rupnp` will build its own envelope too, but we log a best-effort equivalent
@@ -86,11 +363,11 @@ fn build_soap_envelope(action: &str, args_xml: &str) -> String {
     )
 }
 
-fn device_location_uri(device: &DlnaDevice) -> Result<Uri, rupnp::Error> {
+fn device_location_uri(device: &DlnaDevice) -> Result<Uri, Error> {
     device
         .location
         .parse::<Uri>()
-        .map_err(|_| rupnp::Error::ParseError("无法解析设备location为Uri"))
+        .map_err(|_| Error::Parse(format!("无法解析设备location: {}", device.location)))
 }
 
 fn log_upnp_action(service: &rupnp::Service, base_url: &Uri, action: &str, args_xml: &str) {
@@ -120,44 +397,86 @@ fn log_upnp_action(service: &rupnp::Service, base_url: &Uri, action: &str, args_
 /// `rupnp`'s internal URL replacement may produce the wrong path for such devices.
 /// To make behavior explicit (and loggable), we send the SOAP request ourselves to:
 /// `{scheme}://{host}:{port}/{control_path}`.
+///
+/// 成功的发送方式按设备 UDN 记录到 `quirks`，之后直接使用，不再每次先走原生方法再逐个猜路径。
 async fn avtransport_action_compat(
+    device: &DlnaDevice,
     service: &rupnp::Service,
     base_url: &Uri,
     action: &str,
     args_xml: &str,
-) -> Result<HashMap<String, String>, rupnp::Error> {
-    // 首先尝试使用 rupnp 原生的 action 方法（适用于Windows Media Player等标准设备）
-    match service.action(base_url, action, args_xml).await {
-        Ok(response) => {
-            log::debug!("UPnP Action (native) succeeded");
-            log::debug!("UPnP Action (native) response: {:?}", response);
-            return Ok(response);
-        }
-        Err(e) => {
-            log::warn!(
-                "UPnP Action (native) failed: {}, trying compatibility mode",
-                e
-            );
-        }
-    }
-
-    // 原生方法失败，尝试兼容性模式
-
-    // 从 debug 输出中我们可以看到 service 的结构
-    // 我们可以通过 Debug 表示式提取 control_endpoint 信息
-    let service_debug = format!("{:?}", service);
-    log::debug!("Service Debug info: {}", service_debug);
+) -> Result<HashMap<String, String>, Error> {
+    let udn = device.device.udn();
+    let profile = device_quirks(device);
 
     let host = base_url
         .host()
-        .ok_or(rupnp::Error::ParseError("base_url缺少host"))?
+        .ok_or_else(|| Error::Parse("base_url缺少host".to_string()))?
         .to_string();
     let scheme = base_url
         .scheme_str()
-        .ok_or(rupnp::Error::ParseError("base_url缺少scheme"))?;
+        .ok_or_else(|| Error::Parse("base_url缺少scheme".to_string()))?;
     let port = base_url
         .port_u16()
         .unwrap_or(if scheme == "https" { 443 } else { 80 });
+    let control_url = |path: &str| {
+        if path.starts_with("http://") || path.starts_with("https://") {
+            path.to_string()
+        } else {
+            format!("{}://{}:{}{}", scheme, host, port, path)
+        }
+    };
+
+    // 所有尝试都失败时优先报告设备返回的 SOAP Fault，其次是最后一次网络/HTTP 错误
+    let mut last_error: Option<Error> = None;
+
+    // 已知可用（或手写配置指定）的控制路径，直接发送
+    let known_path = match &profile.control {
+        Some(ControlEndpoint::Path(path)) => Some(normalize_control_path(path)),
+        _ => None,
+    };
+    if let Some(path) = &known_path {
+        match soap_post(&control_url(path), action, args_xml).await {
+            Ok(out) => return Ok(out),
+            // 路径是对的，只是动作本身被设备拒绝
+            Err(e @ Error::SoapFault { .. }) => return Err(e),
+            Err(e) => {
+                log::warn!("已记录的控制路径 {} 失效: {}，重新探测", path, e);
+                last_error = prefer_fault(last_error, e);
+            }
+        }
+    }
+
+    // 尝试使用 rupnp 原生的 action 方法（适用于Windows Media Player等标准设备）
+    if known_path.is_none() {
+        match service.action(base_url, action, args_xml).await {
+            Ok(response) => {
+                log::debug!("UPnP Action (native) succeeded");
+                log::debug!("UPnP Action (native) response: {:?}", response);
+                learn_control(device, ControlEndpoint::Native);
+                return Ok(response);
+            }
+            Err(e) => {
+                log::warn!(
+                    "UPnP Action (native) failed: {}, trying compatibility mode",
+                    e
+                );
+                let e = Error::upnp(action, e);
+                // 已确认原生方法可用的设备返回 SOAP Fault，说明只是动作被拒绝，不必再猜路径
+                if profile.control == Some(ControlEndpoint::Native) && matches!(e, Error::SoapFault { .. }) {
+                    return Err(e);
+                }
+                last_error = prefer_fault(last_error, e);
+            }
+        }
+    }
+
+    // 原生方法失败，尝试兼容性模式
+
+    // 从 debug 输出中我们可以看到 service 的结构
+    // 我们可以通过 Debug 表示式提取 control_endpoint 信息
+    let service_debug = format!("{:?}", service);
+    log::debug!("Service Debug info: {}", service_debug);
 
     // 候选控制路径：优先使用 debug 中的 control_endpoint，并补充常见路径
     let mut possible_paths: Vec<String> = Vec::new();
@@ -188,96 +507,178 @@ async fn avtransport_action_compat(
 
     // 尝试匹配可能的路径模式
     for path in possible_paths {
-        let final_url = if path.starts_with("http://") || path.starts_with("https://") {
-            path
-        } else {
-            format!("{}://{}:{}{}", scheme, host, port, path)
-        };
+        if known_path.as_ref() == Some(&path) {
+            continue;
+        }
+        match soap_post(&control_url(&path), action, args_xml).await {
+            Ok(out) => {
+                learn_control(device, ControlEndpoint::Path(path));
+                return Ok(out);
+            }
+            Err(e) => last_error = prefer_fault(last_error, e),
+        }
+    }
 
-        let soap_action_header =
-            format!("\"urn:schemas-upnp-org:service:AVTransport:1#{}\"", action);
-        let body = build_soap_envelope(action, args_xml);
+    // 所有尝试都失败
+    let last_error = last_error.unwrap_or_else(|| Error::Network(format!("{} 没有可用的控制地址", udn)));
+    log::warn!("所有AVTransport操作尝试都失败: {}", last_error);
+    Err(last_error)
+}
 
-        log::info!(
-            "UPnP Action (compat) -> url={} SOAPAction={}",
-            final_url,
-            soap_action_header
-        );
-        log::debug!("UPnP Action (compat) body => {}", body);
+// SOAP Fault 比网络/HTTP 错误更能说明问题，保留它
+fn prefer_fault(last: Option<Error>, new: Error) -> Option<Error> {
+    match last {
+        Some(fault @ Error::SoapFault { .. }) if !matches!(new, Error::SoapFault { .. }) => Some(fault),
+        _ => Some(new),
+    }
+}
 
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "SOAPAction",
-            HeaderValue::from_str(&soap_action_header)
-                .map_err(|_| rupnp::Error::ParseError("SOAPAction header非法"))?,
-        );
-        headers.insert(
-            CONTENT_TYPE,
-            HeaderValue::from_static("text/xml; charset=\"utf-8\""),
-        );
+fn device_quirks(device: &DlnaDevice) -> QuirkProfile {
+    let spec = &device.device;
+    quirks::store().resolve(spec.udn(), spec.manufacturer(), spec.model_name())
+}
 
-        let client = reqwest::Client::builder()
-            .no_proxy()
-            .build()
-            .map_err(|_| rupnp::Error::ParseError("创建reqwest client失败"))?;
+fn learn_control(device: &DlnaDevice, control: ControlEndpoint) {
+    quirks::store().learn(device.device.udn(), &device.friendly_name, |p| {
+        p.control = Some(control);
+    });
+}
 
-        match client
-            .post(&final_url)
-            .headers(headers)
-            .body(body)
-            .send()
-            .await
-        {
-            Ok(resp) => {
-                let status = resp.status();
-                let text = resp.text().await.map_err(|e| {
-                    rupnp::Error::ParseError(Box::leak(
-                        format!("读取SOAP响应失败: {}", e).into_boxed_str(),
-                    ))
-                })?;
-
-                if status.as_u16() == 200 {
-                    log::info!("UPnP Action (compat) succeeded with path: {}", final_url);
-                    log::debug!("UPnP Action (compat) status=200 body={}", text);
-
-                    let mut out = HashMap::new();
-                    for k in [
-                        "Track",
-                        "TrackDuration",
-                        "TrackMetaData",
-                        "TrackURI",
-                        "RelTime",
-                        "AbsTime",
-                        "RelCount",
-                        "AbsCount",
-                    ] {
-                        if let Some(v) = extract_xml_tag_value(&text, k) {
-                            log::debug!("提取到字段 '{}' 的值: '{}'", k, v);
-                            out.insert(k.to_string(), v);
-                        }
-                    }
+// 兼容路径下响应不是合法 XML 时，按名称查找的输出参数
+const COMPAT_RESPONSE_FIELDS: &[&str] = &[
+    "Track",
+    "TrackDuration",
+    "TrackMetaData",
+    "TrackURI",
+    "RelTime",
+    "AbsTime",
+    "RelCount",
+    "AbsCount",
+    // GetTransportInfo
+    "CurrentTransportState",
+    "CurrentTransportStatus",
+    "CurrentSpeed",
+    // GetMediaInfo
+    "NrTracks",
+    "MediaDuration",
+    "CurrentURI",
+    "CurrentURIMetaData",
+    "NextURI",
+    "NextURIMetaData",
+    "PlayMedium",
+];
+
+// 自行构造 SOAP 请求发送到 final_url，返回响应中的输出参数（已解码）
+async fn soap_post(final_url: &str, action: &str, args_xml: &str) -> Result<HashMap<String, String>, Error> {
+    let soap_action_header = format!("\"urn:schemas-upnp-org:service:AVTransport:1#{}\"", action);
+    let body = build_soap_envelope(action, args_xml);
 
-                    log::debug!("解析后的响应字段: {:?}", out);
-                    return Ok(out);
-                } else {
-                    log::warn!(
-                        "UPnP Action (compat) failed with path {}: status={} body={}",
-                        final_url,
-                        status,
-                        text
-                    );
-                }
-            }
-            Err(e) => {
-                log::warn!("UPnP Action (compat) failed with path {}: {}", final_url, e);
-            }
+    log::info!(
+        "UPnP Action (compat) -> url={} SOAPAction={}",
+        final_url,
+        soap_action_header
+    );
+    log::debug!("UPnP Action (compat) body => {}", body);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "SOAPAction",
+        HeaderValue::from_str(&soap_action_header)
+            .map_err(|_| Error::Parse("SOAPAction header非法".to_string()))?,
+    );
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/xml; charset=\"utf-8\""),
+    );
+
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .map_err(|e| Error::Network(format!("创建reqwest client失败: {}", e)))?;
+
+    let resp = client
+        .post(final_url)
+        .headers(headers)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| {
+            log::warn!("UPnP Action (compat) failed with path {}: {}", final_url, e);
+            Error::Network(e.to_string())
+        })?;
+
+    let status = resp.status();
+    let text = resp
+        .text()
+        .await
+        .map_err(|e| Error::Network(format!("读取SOAP响应失败: {}", e)))?;
+
+    if status.as_u16() != 200 {
+        log::warn!(
+            "UPnP Action (compat) failed with path {}: status={} body={}",
+            final_url,
+            status,
+            text
+        );
+        return Err(parse_soap_fault(action, &text).unwrap_or_else(|| Error::HttpStatus {
+            status: status.as_u16(),
+            context: final_url.to_string(),
+        }));
+    }
+
+    log::info!("UPnP Action (compat) succeeded with path: {}", final_url);
+    log::debug!("UPnP Action (compat) status=200 body={}", text);
+
+    // 少数设备出错时也返回 200，正文是 Fault
+    if let Some(fault) = parse_soap_fault(action, &text) {
+        return Err(fault);
+    }
+    let out = match parse_soap_response(&text) {
+        Some(out) => out,
+        None => {
+            log::debug!("SOAP 响应不是合法 XML，按标签名查找字段");
+            scan_soap_response(&text, COMPAT_RESPONSE_FIELDS)
         }
+    };
+    log::debug!("解析后的响应字段: {:?}", out);
+    Ok(out)
+}
+
+/// 从 SOAP Fault 响应体中取出 UPnP errorCode / errorDescription
+fn parse_soap_fault(action: &str, body: &str) -> Option<Error> {
+    if !body.contains("Fault") {
+        return None;
     }
+    let fields = with_xml(body, |doc| {
+        let fault = find_element(doc.root(), "Fault")?;
+        let field = |name: &str| find_element(fault, name).map(|n| element_text(n, doc.input_text()));
+        Some((
+            field("errorCode"),
+            field("errorDescription").or_else(|| field("faultstring")),
+        ))
+    });
+    let (error_code, description) = match fields {
+        Some(Some(fields)) => fields,
+        // 合法的 XML，但不是 Fault
+        Some(None) => return None,
+        None => (
+            scan_tag_text(body, "errorCode"),
+            scan_tag_text(body, "errorDescription").or_else(|| scan_tag_text(body, "faultstring")),
+        ),
+    };
+    let error_code = error_code.and_then(|c| c.trim().parse().ok());
+    Some(Error::soap_fault(action, error_code, &description.unwrap_or_default()))
+}
 
-    // 所有尝试都失败
-    Err(rupnp::Error::ParseError(Box::leak(
-        "所有AVTransport操作尝试都失败".to_string().into_boxed_str(),
-    )))
+fn gena_request(method: &[u8], url: &str) -> Result<reqwest::RequestBuilder, Error> {
+    let method = reqwest::Method::from_bytes(method)
+        .map_err(|_| Error::Parse("非法的GENA方法".to_string()))?;
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .map_err(|e| Error::Network(format!("创建reqwest client失败: {}", e)))?;
+    Ok(client.request(method, url))
 }
 
 fn normalize_control_path(path: &str) -> String {
@@ -292,6 +693,19 @@ fn normalize_control_path(path: &str) -> String {
     }
 }
 
+fn extract_event_sub_endpoint_from_debug(service_debug: &str) -> Option<String> {
+    // event_sub_endpoint 是 rupnp::Service 的最后一个字段，Debug 输出以 " }" 结尾
+    let start = service_debug.find("event_sub_endpoint: ")? + "event_sub_endpoint: ".len();
+    let rest = &service_debug[start..];
+    let end = rest.find([',', ' ', '}']).unwrap_or(rest.len());
+    let path = rest[..end].trim();
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.to_string())
+    }
+}
+
 fn extract_control_endpoint_from_debug(service_debug: &str) -> Option<String> {
     if let Some(start) = service_debug.find("control_endpoint: ") {
         let start = start + "control_endpoint: ".len();
@@ -307,7 +721,312 @@ fn extract_control_endpoint_from_debug(service_debug: &str) -> Option<String> {
 }
 
 // AVTransport服务URN
-const AV_TRANSPORT: URN = URN::service("schemas-upnp-org", "AVTransport", 1);
+pub(crate) const AV_TRANSPORT: URN = URN::service("schemas-upnp-org", "AVTransport", 1);
+// RenderingControl服务URN
+const RENDERING_CONTROL: URN = URN::service("schemas-upnp-org", "RenderingControl", 1);
+// ConnectionManager服务URN
+const CONNECTION_MANAGER: URN = URN::service("schemas-upnp-org", "ConnectionManager", 1);
+
+// DLNA.ORG_OP=01：支持按字节 Range 跳转（本地代理会转发 Range）
+const DLNA_ORG_OP: &str = "DLNA.ORG_OP=01";
+// DLNA.ORG_FLAGS：streaming 传输模式 + background 传输模式 + 连接暂停 + DLNA v1.5
+const DLNA_ORG_FLAGS: &str = "DLNA.ORG_FLAGS=01700000000000000000000000000000";
+
+/// 一条 protocolInfo：`<protocol>:<network>:<contentFormat>:<additionalInfo>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolInfo {
+    pub protocol: String,
+    pub network: String,
+    pub content_format: String,
+    pub additional_info: String,
+}
+
+impl ProtocolInfo {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().splitn(4, ':');
+        let protocol = parts.next()?.trim();
+        let network = parts.next()?.trim();
+        let content_format = parts.next()?.trim();
+        let additional_info = parts.next()?.trim();
+        if protocol.is_empty() || content_format.is_empty() {
+            return None;
+        }
+        Some(Self {
+            protocol: protocol.to_string(),
+            network: network.to_string(),
+            content_format: content_format.to_string(),
+            additional_info: additional_info.to_string(),
+        })
+    }
+
+    /// 解析 GetProtocolInfo 返回的逗号分隔列表
+    pub fn parse_list(value: &str) -> Vec<Self> {
+        value
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .filter_map(Self::parse)
+            .collect()
+    }
+
+    /// DLNA.ORG_PN 配置文件名
+    pub fn dlna_profile(&self) -> Option<&str> {
+        self.additional_info
+            .split(';')
+            .find_map(|kv| kv.trim().strip_prefix("DLNA.ORG_PN="))
+    }
+
+    fn accepts_mime(&self, mime: &str) -> bool {
+        if !self.protocol.eq_ignore_ascii_case("http-get") {
+            return false;
+        }
+        let format = self.content_format.to_ascii_lowercase();
+        format == "*" || mime_aliases(mime).contains(&format.as_str())
+    }
+}
+
+impl std::fmt::Display for ProtocolInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.protocol, self.network, self.content_format, self.additional_info
+        )
+    }
+}
+
+// 同一种格式在不同设备上的常见 MIME 写法（含 mime 本身），mime 须已转为小写
+fn mime_aliases(mime: &str) -> Vec<&str> {
+    let aliases: &[&'static str] = match mime {
+        "video/mp4" => &["video/mp4", "video/mpeg4"],
+        "application/vnd.apple.mpegurl" | "application/x-mpegurl" | "audio/mpegurl" | "audio/x-mpegurl" => &[
+            "application/vnd.apple.mpegurl",
+            "application/x-mpegurl",
+            "audio/mpegurl",
+            "audio/x-mpegurl",
+        ],
+        "video/x-flv" => &["video/x-flv", "video/flv"],
+        "video/mp2t" => &["video/mp2t", "video/mpeg"],
+        "audio/mpeg" => &["audio/mpeg", "audio/mp3"],
+        "audio/mp4" => &["audio/mp4", "audio/x-m4a"],
+        _ => &[],
+    };
+    let mut all = vec![mime];
+    all.extend(aliases.iter().copied().filter(|a| *a != mime));
+    all
+}
+
+/// 从渲染器的 Sink 列表中为 `mime` 挑选 protocolInfo。
+///
+/// - Sink 列表为空（设备不支持 GetProtocolInfo）或格式未知（`application/octet-stream`）时
+///   返回宽松的 `http-get:*:{mime}:*`，交给设备自己判断
+/// - 优先使用不绑定 DLNA.ORG_PN 的条目（有些设备在 PN 与实际内容不符时拒绝播放）
+/// - 设备明确不支持该格式时返回 None
+pub fn select_protocol_info(sinks: &[ProtocolInfo], mime: &str) -> Option<String> {
+    let mime = mime.to_ascii_lowercase();
+    if sinks.is_empty() || mime == "application/octet-stream" {
+        return Some(format!("http-get:*:{}:*", mime));
+    }
+
+    let candidates: Vec<&ProtocolInfo> = sinks.iter().filter(|p| p.accepts_mime(&mime)).collect();
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let flags = format!("{};{}", DLNA_ORG_OP, DLNA_ORG_FLAGS);
+    let chosen = candidates
+        .iter()
+        .find(|p| p.dlna_profile().is_none())
+        .or_else(|| candidates.first())?;
+    let additional = match chosen.dlna_profile() {
+        Some(pn) => format!("DLNA.ORG_PN={};{}", pn, flags),
+        None => flags,
+    };
+    Some(format!("http-get:*:{}:{}", mime, additional))
+}
+
+/// AVTransport 的 TransportState 状态变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportState {
+    Stopped,
+    Playing,
+    Transitioning,
+    PausedPlayback,
+    PausedRecording,
+    Recording,
+    NoMediaPresent,
+    /// 厂商自定义的状态值，原样保留
+    Other(String),
+}
+
+impl TransportState {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "STOPPED" => Self::Stopped,
+            "PLAYING" => Self::Playing,
+            "TRANSITIONING" => Self::Transitioning,
+            "PAUSED_PLAYBACK" => Self::PausedPlayback,
+            "PAUSED_RECORDING" => Self::PausedRecording,
+            "RECORDING" => Self::Recording,
+            "NO_MEDIA_PRESENT" => Self::NoMediaPresent,
+            _ => Self::Other(value.trim().to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Stopped => "STOPPED",
+            Self::Playing => "PLAYING",
+            Self::Transitioning => "TRANSITIONING",
+            Self::PausedPlayback => "PAUSED_PLAYBACK",
+            Self::PausedRecording => "PAUSED_RECORDING",
+            Self::Recording => "RECORDING",
+            Self::NoMediaPresent => "NO_MEDIA_PRESENT",
+            Self::Other(s) => s,
+        }
+    }
+}
+
+impl std::fmt::Display for TransportState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// AVTransport 的 TransportStatus 状态变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportStatus {
+    Ok,
+    ErrorOccurred,
+    Other(String),
+}
+
+impl TransportStatus {
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "OK" => Self::Ok,
+            "ERROR_OCCURRED" => Self::ErrorOccurred,
+            _ => Self::Other(value.trim().to_string()),
+        }
+    }
+}
+
+/// GetTransportInfo 的返回值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportInfo {
+    pub state: TransportState,
+    pub status: TransportStatus,
+    /// CurrentSpeed，正常播放为 "1"
+    pub speed: String,
+}
+
+/// GetMediaInfo 的返回值
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MediaInfo {
+    pub nr_tracks: u32,
+    /// MediaDuration 原始字符串，如 "0:03:25"，不支持时为 "NOT_IMPLEMENTED"
+    pub media_duration: String,
+    pub current_uri: String,
+    /// 解码后的 DIDL-Lite，可用 [`parse_didl_lite`] 读取
+    pub current_uri_metadata: String,
+    pub next_uri: String,
+    pub next_uri_metadata: String,
+}
+
+/// GetPositionInfo 中常用的字段
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackPosition {
+    pub position_secs: u32,
+    /// 设备报告的 TrackDuration，0 或无法解析时为 None
+    pub duration_secs: Option<u32>,
+    /// 设备当前正在播放的地址，用于识别 SetNextAVTransportURI 的自动切换
+    pub track_uri: String,
+}
+
+/// 可订阅 GENA 事件的服务
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventService {
+    AVTransport,
+    RenderingControl,
+}
+
+impl EventService {
+    fn urn(&self) -> URN {
+        match self {
+            Self::AVTransport => AV_TRANSPORT,
+            Self::RenderingControl => RENDERING_CONTROL,
+        }
+    }
+
+    pub(crate) fn service_name(&self) -> &'static str {
+        match self {
+            Self::AVTransport => "AVTransport",
+            Self::RenderingControl => "RenderingControl",
+        }
+    }
+
+    /// NOTIFY 回调地址中使用的路径段
+    pub fn path_segment(&self) -> &'static str {
+        match self {
+            Self::AVTransport => "avt",
+            Self::RenderingControl => "rc",
+        }
+    }
+
+    pub fn from_path_segment(segment: &str) -> Option<Self> {
+        match segment {
+            "avt" => Some(Self::AVTransport),
+            "rc" => Some(Self::RenderingControl),
+            _ => None,
+        }
+    }
+}
+
+/// 一次成功的 GENA 订阅
+#[derive(Debug, Clone)]
+pub struct EventSubscription {
+    pub service: EventService,
+    pub sid: String,
+    /// 设备接受的订阅时长（秒），需在到期前续订
+    pub timeout_secs: u32,
+    event_url: String,
+}
+
+// Seek 支持的单位，按默认尝试顺序
+const SEEK_UNITS: [&str; 3] = ["REL_TIME", "ABS_TIME", "X_DLNA_REL_BYTE"];
+// Seek 后等待多久再查询进度确认
+const SEEK_VERIFY_DELAY: Duration = Duration::from_millis(1500);
+// 设备进度与目标相差不超过这么多秒（另加确认前播放的时间）即视为跳转成功
+const SEEK_TOLERANCE_SECS: u32 = 3;
+// 设备忽略 Seek 时最多发送几次
+const SEEK_ATTEMPTS: u32 = 3;
+
+fn seek_reached(position_secs: u32, target_secs: u32) -> bool {
+    let played = SEEK_VERIFY_DELAY.as_secs() as u32 + 1;
+    position_secs + SEEK_TOLERANCE_SECS >= target_secs
+        && position_secs <= target_secs + SEEK_TOLERANCE_SECS + played
+}
+
+// 按码率均匀估算 seconds 处的字节偏移
+fn byte_offset(seconds: u32, size: u64, duration_secs: u32) -> Option<u64> {
+    (size > 0 && duration_secs > 0).then(|| size * seconds.min(duration_secs) as u64 / duration_secs as u64)
+}
+
+// 710 Seek mode not supported、711 Illegal seek target、402 Invalid Args：换一种 Unit 再试
+fn rejects_seek_unit(e: &Error) -> bool {
+    match e {
+        Error::SoapFault { error_code, .. } => matches!(error_code, None | Some(402) | Some(710) | Some(711)),
+        Error::HttpStatus { status, .. } => *status == 500,
+        _ => false,
+    }
+}
+
+fn parse_gena_timeout(value: Option<&str>, requested: u32) -> u32 {
+    // 形如 "Second-1800"，也可能是 "infinite"
+    value
+        .and_then(|v| v.trim().strip_prefix("Second-").or_else(|| v.trim().strip_prefix("second-")))
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(requested)
+}
 
 // DLNA设备信息
 #[derive(Debug, Clone)]
@@ -316,9 +1035,41 @@ pub struct DlnaDevice {
     pub friendly_name: String,
     pub location: String,
     pub services: Vec<URN>,
+    /// ConnectionManager::GetProtocolInfo 返回的 Sink 列表，连接时填充
+    pub sink_protocols: Vec<ProtocolInfo>,
+    /// 发现该设备的本机网卡，媒体服务器地址据此选择
+    pub interface: Option<NetInterface>,
 }
 
-#[derive(Clone)]
+impl DlnaDevice {
+    /// 由 rupnp 拉取到的设备描述构建；Sink 协议列表需另行调用 `load_sink_protocols`
+    pub fn from_rupnp(device: Device) -> Self {
+        Self {
+            friendly_name: device.friendly_name().to_string(),
+            location: device.url().to_string(),
+            services: device
+                .services()
+                .iter()
+                .map(|s| s.service_type().clone())
+                .collect(),
+            device,
+            sink_protocols: Vec::new(),
+            interface: None,
+        }
+    }
+
+    pub fn with_interface(mut self, interface: Option<NetInterface>) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    /// 设备能访问到的本机地址（发现它的网卡），未知时为 None
+    pub fn local_addr(&self) -> Option<IpAddr> {
+        self.interface.as_ref().map(|i| i.addr)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct DlnaController;
 
 impl DlnaController {
@@ -326,82 +1077,37 @@ impl DlnaController {
         Self
     }
 
-    // 发现网络中的DLNA渲染器设备
-    pub async fn discover_devices(&self) -> Result<Vec<DlnaDevice>, rupnp::Error> {
+    // 发现网络中的DLNA渲染器设备：在每个网卡（或 KTV_INTERFACE 指定的网卡）上搜索
+    pub async fn discover_devices(&self) -> Result<Vec<DlnaDevice>, Error> {
         log::info!("正在搜索DLNA设备...");
-
-        let search_target = SearchTarget::URN(AV_TRANSPORT);
-        let devices_stream = rupnp::discover(&search_target, Duration::from_secs(5), None).await?;
-
-        let devices: Vec<Result<Device, rupnp::Error>> = devices_stream.collect().await;
-        log::debug!("发现设备总数: {}", devices.len());
-        let mut dlna_devices = Vec::new();
-
-        let mut seen_locations = HashSet::new();
-
-        for device_result in devices {
-            match device_result {
-                Ok(device) => {
-                    let location = device.url().to_string();
-
-                    if !seen_locations.insert(location.clone()) {
-                        continue;
-                    }
-
-                    let device_type_str = device.device_type().to_string();
-                    if device_type_str.contains("MediaRenderer") {
-                        let friendly_name = device.friendly_name().to_string();
-
-                        // 检查设备是否支持AVTransport服务
-                        let services: Vec<URN> = device
-                            .services()
-                            .iter()
-                            .map(|s| s.service_type().clone())
-                            .collect();
-
-                        log::info!("发现设备: {} (位置: {})", friendly_name, location);
-                        log::debug!("支持的服务: {:?}", services);
-
-                        dlna_devices.push(DlnaDevice {
-                            device,
-                            friendly_name,
-                            location,
-                            services,
-                        });
-                    }
-                }
-                Err(e) => {
-                    log::error!("设备发现错误: {}", e);
-                }
-            }
+        let devices = discovery::search_all(Duration::from_secs(5)).await;
+        for device in &devices {
+            log::info!(
+                "发现设备: {} (位置: {}, 网卡: {})",
+                device.friendly_name,
+                device.location,
+                device.interface.as_ref().map(|i| i.to_string()).unwrap_or_default()
+            );
+            log::debug!("支持的服务: {:?}", device.services);
         }
-
-        Ok(dlna_devices)
+        Ok(devices)
     }
 
-    pub async fn get_devices_from_urls(
+    // 按设备描述文件地址直接构建设备（不经过 SSDP）
+    pub async fn get_devices_from_urls<S: AsRef<str>>(
         &self,
-        urls: &Vec<&'static str>,
-    ) -> Result<Vec<DlnaDevice>, rupnp::Error> {
-        let devices = try_join_all(urls.iter().map(|url| {
-            let uri = Uri::from_static(url);
-            Device::from_url(uri)
+        urls: &[S],
+    ) -> Result<Vec<DlnaDevice>, Error> {
+        let devices = try_join_all(urls.iter().map(|url| async move {
+            let uri: Uri = url
+                .as_ref()
+                .parse()
+                .map_err(|_| Error::Parse(format!("无法解析设备地址: {}", url.as_ref())))?;
+            Device::from_url(uri).await.map_err(|e| Error::upnp("GetDescription", e))
         }))
         .await?;
 
-        let dlna_devices: Vec<DlnaDevice> = devices
-            .into_iter()
-            .map(|device| DlnaDevice {
-                device: device.clone(),
-                friendly_name: device.friendly_name().to_string(),
-                location: device.url().to_string(),
-                services: device
-                    .services()
-                    .iter()
-                    .map(|s| s.service_type().clone())
-                    .collect(),
-            })
-            .collect();
+        let dlna_devices: Vec<DlnaDevice> = devices.into_iter().map(DlnaDevice::from_rupnp).collect();
         Ok(dlna_devices)
     }
 
@@ -414,39 +1120,268 @@ impl DlnaController {
             .find(|s| *s.service_type() == AV_TRANSPORT)
     }
 
+    // RenderingControl 按服务类型查找：不少电视只声明 :2 / :3（SetVolume/GetVolume 与 :1 相同），
+    // 有多个版本时取最高的；SOAPAction 使用设备声明的版本
+    fn get_rendering_control_service<'a>(&'a self, device: &'a DlnaDevice) -> Option<&'a rupnp::Service> {
+        let service = device
+            .device
+            .services()
+            .iter()
+            .filter(|s| {
+                s.service_type().domain_name() == RENDERING_CONTROL.domain_name()
+                    && s.service_type().typ() == RENDERING_CONTROL.typ()
+            })
+            .max_by_key(|s| s.service_type().version())?;
+        if service.service_type().version() != RENDERING_CONTROL.version() {
+            log::debug!("设备 {} 使用 {}", device.friendly_name, service.service_type());
+        }
+        Some(service)
+    }
+
+    fn get_event_service<'a>(
+        &'a self,
+        device: &'a DlnaDevice,
+        service: EventService,
+    ) -> Option<&'a rupnp::Service> {
+        if service == EventService::RenderingControl {
+            return self.get_rendering_control_service(device);
+        }
+        let urn = service.urn();
+        device
+            .device
+            .services()
+            .iter()
+            .find(|s| *s.service_type() == urn)
+    }
+
+    // 解析服务的 eventSubURL（rupnp 没有公开该字段，同 compat 逻辑一样从 Debug 输出里取）
+    fn event_sub_url(
+        &self,
+        device: &DlnaDevice,
+        service: EventService,
+    ) -> Result<String, Error> {
+        let svc = self
+            .get_event_service(device, service)
+            .ok_or(Error::UnsupportedService(service.service_name()))?;
+        let path = extract_event_sub_endpoint_from_debug(&format!("{:?}", svc))
+            .ok_or_else(|| Error::Parse("设备未提供eventSubURL".to_string()))?;
+        let path = normalize_control_path(&path);
+        if path.starts_with("http://") || path.starts_with("https://") {
+            return Ok(path);
+        }
+
+        let base_url = device_location_uri(device)?;
+        let host = base_url
+            .host()
+            .ok_or_else(|| Error::Parse("base_url缺少host".to_string()))?;
+        let scheme = base_url.scheme_str().unwrap_or("http");
+        let port = base_url
+            .port_u16()
+            .unwrap_or(if scheme == "https" { 443 } else { 80 });
+        Ok(format!("{}://{}:{}{}", scheme, host, port, path))
+    }
+
+    // 订阅服务的 GENA 事件，设备会把状态变化 NOTIFY 到 callback_url
+    pub async fn subscribe_events(
+        &self,
+        device: &DlnaDevice,
+        service: EventService,
+        callback_url: &str,
+        timeout_secs: u32,
+    ) -> Result<EventSubscription, Error> {
+        let event_url = self.event_sub_url(device, service)?;
+        log::info!("订阅事件: {} -> {} (回调 {})", service.path_segment(), event_url, callback_url);
+
+        let resp = gena_request(b"SUBSCRIBE", &event_url)?
+            .header("CALLBACK", format!("<{}>", callback_url))
+            .header("NT", "upnp:event")
+            .header("TIMEOUT", format!("Second-{}", timeout_secs))
+            .send()
+            .await
+            .map_err(|e| {
+                log::warn!("SUBSCRIBE 请求失败: {}", e);
+                Error::Network(format!("SUBSCRIBE请求失败: {}", e))
+            })?;
+
+        if !resp.status().is_success() {
+            log::warn!("设备拒绝事件订阅: {} status={}", event_url, resp.status());
+            return Err(Error::HttpStatus {
+                status: resp.status().as_u16(),
+                context: "SUBSCRIBE".to_string(),
+            });
+        }
+
+        let sid = resp
+            .headers()
+            .get("SID")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| Error::Parse("SUBSCRIBE响应缺少SID".to_string()))?;
+        let timeout = parse_gena_timeout(
+            resp.headers().get("TIMEOUT").and_then(|v| v.to_str().ok()),
+            timeout_secs,
+        );
+        log::info!("事件订阅成功: sid={} timeout={}s", sid, timeout);
+
+        Ok(EventSubscription {
+            service,
+            sid,
+            timeout_secs: timeout,
+            event_url,
+        })
+    }
+
+    // 续订，返回设备给出的新时长
+    pub async fn renew_subscription(
+        &self,
+        subscription: &mut EventSubscription,
+        timeout_secs: u32,
+    ) -> Result<(), Error> {
+        let resp = gena_request(b"SUBSCRIBE", &subscription.event_url)?
+            .header("SID", subscription.sid.as_str())
+            .header("TIMEOUT", format!("Second-{}", timeout_secs))
+            .send()
+            .await
+            .map_err(|e| {
+                log::warn!("续订请求失败: {}", e);
+                Error::Network(format!("续订请求失败: {}", e))
+            })?;
+
+        if !resp.status().is_success() {
+            return Err(Error::HttpStatus {
+                status: resp.status().as_u16(),
+                context: "SUBSCRIBE(续订)".to_string(),
+            });
+        }
+        subscription.timeout_secs = parse_gena_timeout(
+            resp.headers().get("TIMEOUT").and_then(|v| v.to_str().ok()),
+            timeout_secs,
+        );
+        log::debug!("续订成功: sid={} timeout={}s", subscription.sid, subscription.timeout_secs);
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, subscription: &EventSubscription) -> Result<(), Error> {
+        let resp = gena_request(b"UNSUBSCRIBE", &subscription.event_url)?
+            .header("SID", subscription.sid.as_str())
+            .send()
+            .await
+            .map_err(|e| Error::Network(format!("UNSUBSCRIBE请求失败: {}", e)))?;
+        if !resp.status().is_success() {
+            return Err(Error::HttpStatus {
+                status: resp.status().as_u16(),
+                context: "UNSUBSCRIBE".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // 查询 ConnectionManager::GetProtocolInfo，返回 (Source, Sink)
+    pub async fn get_protocol_info(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<(Vec<ProtocolInfo>, Vec<ProtocolInfo>), Error> {
+        let connection_manager = device
+            .device
+            .services()
+            .iter()
+            .find(|s| *s.service_type() == CONNECTION_MANAGER)
+            .ok_or(Error::UnsupportedService("ConnectionManager"))?;
+
+        let base_url = device_location_uri(device)?;
+        let response = connection_manager
+            .action(&base_url, "GetProtocolInfo", "")
+            .await
+            .map_err(|e| Error::upnp("GetProtocolInfo", e))?;
+        log::debug!("GetProtocolInfo响应: {:?}", response);
+
+        let list = |k: &str| {
+            response
+                .get(k)
+                .map(|v| ProtocolInfo::parse_list(v))
+                .unwrap_or_default()
+        };
+        Ok((list("Source"), list("Sink")))
+    }
+
+    // 连接设备时调用：缓存设备支持的 Sink 协议，失败时保持为空（回退到宽松的 protocolInfo）
+    pub async fn load_sink_protocols(&self, device: &mut DlnaDevice) {
+        match self.get_protocol_info(device).await {
+            Ok((_, sinks)) => {
+                log::info!("设备支持 {} 种 Sink 协议", sinks.len());
+                log::debug!("Sink 协议: {:?}", sinks);
+                device.sink_protocols = sinks;
+            }
+            Err(e) => {
+                log::warn!("GetProtocolInfo 失败，将使用宽松的 protocolInfo: {}", e);
+            }
+        }
+    }
+
+    // 设备的兼容配置（手写配置与运行时学到的发送方式合并后）
+    pub fn quirks(&self, device: &DlnaDevice) -> QuirkProfile {
+        device_quirks(device)
+    }
+
+    // 按上游实际的 Content-Type 选择 protocolInfo；设备不支持该格式时直接报错
+    pub fn negotiate_protocol_info(
+        &self,
+        device: &DlnaDevice,
+        content_type: &str,
+    ) -> Result<String, Error> {
+        select_protocol_info(&device.sink_protocols, content_type).ok_or_else(|| {
+            // 同一 MIME 常按 DLNA.ORG_PN 重复出现，只保留第一次出现的
+            let mut supported: Vec<String> = Vec::new();
+            for p in &device.sink_protocols {
+                if !supported.contains(&p.content_format) {
+                    supported.push(p.content_format.clone());
+                }
+            }
+            log::error!(
+                "设备 {} 不支持媒体格式 {}，设备支持: {}",
+                device.friendly_name,
+                content_type,
+                supported.join(", ")
+            );
+            Error::UnsupportedFormat {
+                content_type: content_type.to_string(),
+                supported,
+            }
+        })
+    }
+
     // 设置AVTransport URI（发送媒体URL给设备）
     pub async fn set_avtransport_uri(
         &self,
         device: &DlnaDevice,
         current_uri: &str,
-        current_uri_metadata: &str,
+        metadata: &MediaMetadata,
+        protocol_info: Option<&str>,
         server_ip: IpAddr,
         server_port: u16,
-    ) -> Result<(), rupnp::Error> {
+    ) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         // 构建完整的媒体URL
-        let media_url = format!("http://{}:{}/{}", server_ip, server_port, current_uri);
+        let media_url = format!("{}/{}", media_server::base_url(server_ip, server_port), current_uri);
 
         log::info!("设置媒体URI: {}", media_url);
-        log::debug!("元数据(传入): {}", current_uri_metadata);
+        log::debug!("元数据(传入): {:?}", metadata);
 
-        // If caller didn't provide metadata, generate a minimal DIDL-Lite for compatibility.
-        let metadata = if current_uri_metadata.trim().is_empty() {
-            // Title can be anything; devices often only care about protocolInfo.
-            let protocol_info = if current_uri.contains(".m3u8") {
+        // 调用方未协商出 protocolInfo 时沿用按 URL 猜测的宽松写法
+        let protocol_info = protocol_info.or_else(|| {
+            if current_uri.contains(".m3u8") {
                 log::info!("检测到HLS流，使用宽松的协议描述以提高兼容性");
                 Some("http-get:*:application/vnd.apple.mpegurl:*")
-            }else{
+            } else {
                 None
-            };
-            log::info!("没有提供元数据，正在生成默认的DIDL-Lite元数据...");
-            build_didl_lite_metadata(current_uri, &media_url, protocol_info)
-        } else {
-            current_uri_metadata.to_string()
-        };
+            }
+        });
+        let style = device_quirks(device).metadata_style.unwrap_or_default();
+        let metadata = build_didl_lite_metadata(current_uri, metadata, &media_url, protocol_info, style);
 
         // 准备SOAP请求参数 - 只使用标准参数以提高兼容性
         let action = "SetAVTransportURI";
@@ -459,7 +1394,7 @@ impl DlnaController {
         // 发送SOAP请求 - 统一使用设备描述文档URL(location)作为base url
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, &args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, &args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, &args_str).await?;
 
         log::debug!("SetAVTransportURI响应: {:?}", response);
 
@@ -471,21 +1406,19 @@ impl DlnaController {
         &self,
         device: &DlnaDevice,
         next_uri: &str,
-        next_uri_metadata: &str,
+        metadata: &MediaMetadata,
+        protocol_info: Option<&str>,
         server_ip: IpAddr,
         server_port: u16,
-    ) -> Result<(), rupnp::Error> {
+    ) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         let action = "SetNextAVTransportURI";
-        let media_url = format!("http://{}:{}/{}", server_ip, server_port, next_uri);
-        let metadata = if next_uri_metadata.trim().is_empty() {
-            build_didl_lite_metadata(next_uri, &media_url, None)
-        } else {
-            next_uri_metadata.to_string()
-        };
+        let media_url = format!("{}/{}", media_server::base_url(server_ip, server_port), next_uri);
+        let style = device_quirks(device).metadata_style.unwrap_or_default();
+        let metadata = build_didl_lite_metadata(next_uri, metadata, &media_url, protocol_info, style);
 
         let args_str = format!(
             "<InstanceID>0</InstanceID><NextURI>{}</NextURI><NextURIMetaData>{}</NextURIMetaData>",
@@ -495,7 +1428,7 @@ impl DlnaController {
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, &args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, &args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, &args_str).await?;
 
         log::debug!("SetNextAVTransportURI响应: {:?}", response);
 
@@ -503,10 +1436,10 @@ impl DlnaController {
     }
 
     // 播放媒体
-    pub async fn play(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+    pub async fn play(&self, device: &DlnaDevice) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         log::info!("正在发送Play指令...");
         let action = "Play";
@@ -514,17 +1447,17 @@ impl DlnaController {
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
         log::debug!("Play响应: {:?}", response);
 
         Ok(())
     }
 
     // 暂停播放
-    pub async fn pause(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+    pub async fn pause(&self, device: &DlnaDevice) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         log::info!("正在发送Pause指令...");
         let action = "Pause";
@@ -532,17 +1465,17 @@ impl DlnaController {
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
         log::debug!("Pause响应: {:?}", response);
 
         Ok(())
     }
 
     // 停止播放
-    pub async fn stop(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+    pub async fn stop(&self, device: &DlnaDevice) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         log::info!("正在发送Stop指令...");
         let action = "Stop";
@@ -550,54 +1483,98 @@ impl DlnaController {
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
         log::debug!("Stop响应: {:?}", response);
 
         Ok(())
     }
 
     // 下一首
-    pub async fn next(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+    pub async fn next(&self, device: &DlnaDevice) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         let action = "Next";
         let args_str = "<InstanceID>0</InstanceID>";
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
         log::debug!("Next响应: {:?}", response);
 
         Ok(())
     }
 
     // 获取传输信息
-    pub async fn get_transport_info(&self, device: &DlnaDevice) -> Result<(), rupnp::Error> {
+    pub async fn get_transport_info(
+        &self,
+        device: &DlnaDevice,
+    ) -> Result<TransportInfo, Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         let action = "GetTransportInfo";
         let args_str = "<InstanceID>0</InstanceID>";
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, args_str);
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
         log::debug!("传输信息: {:?}", response);
 
-        Ok(())
+        let state = response
+            .get("CurrentTransportState")
+            .ok_or_else(|| Error::Parse("GetTransportInfo响应缺少CurrentTransportState".to_string()))?;
+        Ok(TransportInfo {
+            state: TransportState::parse(state),
+            status: response
+                .get("CurrentTransportStatus")
+                .map(|s| TransportStatus::parse(s))
+                .unwrap_or(TransportStatus::Ok),
+            speed: response
+                .get("CurrentSpeed")
+                .cloned()
+                .unwrap_or_else(|| "1".to_string()),
+        })
+    }
+
+    // 获取媒体信息（当前/下一个 URI、总时长）
+    pub async fn get_media_info(&self, device: &DlnaDevice) -> Result<MediaInfo, Error> {
+        let avtransport = self
+            .get_avtransport_service(device)
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
+
+        let action = "GetMediaInfo";
+        let args_str = "<InstanceID>0</InstanceID>";
+
+        let base_url = device_location_uri(device)?;
+        log_upnp_action(avtransport, &base_url, action, args_str);
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
+        log::debug!("媒体信息: {:?}", response);
+
+        let field = |k: &str| response.get(k).cloned().unwrap_or_default();
+        Ok(MediaInfo {
+            nr_tracks: response
+                .get("NrTracks")
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0),
+            media_duration: field("MediaDuration"),
+            current_uri: field("CurrentURI"),
+            current_uri_metadata: field("CurrentURIMetaData"),
+            next_uri: field("NextURI"),
+            next_uri_metadata: field("NextURIMetaData"),
+        })
     }
 
     // 获取位置信息
     pub async fn get_position_info(
         &self,
         device: &DlnaDevice,
-    ) -> Result<HashMap<String, String>, rupnp::Error> {
+    ) -> Result<HashMap<String, String>, Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
         let action = "GetPositionInfo";
         let args_str = "<InstanceID>0</InstanceID>";
@@ -606,117 +1583,183 @@ impl DlnaController {
         log_upnp_action(avtransport, &base_url, action, args_str);
 
         // 获取响应
-        let response = avtransport_action_compat(avtransport, &base_url, action, args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, args_str).await?;
 
         log::debug!("GetPositionInfo响应: {:?}", response);
 
         Ok(response)
     }
 
-    // 获取当前播放进度，返回 (当前时间秒, 总时长秒)
-    pub async fn get_secs(&self, device: &DlnaDevice) -> Result<(u32, u32), rupnp::Error> {
+    // 获取当前播放进度，返回 (当前时间秒, 设备报告的总时长秒)
+    // 设备返回 NOT_IMPLEMENTED / 0 等无效时长时总时长为 None，由 DurationResolver 兜底
+    pub async fn get_secs(&self, device: &DlnaDevice) -> Result<(u32, Option<u32>), Error> {
+        let track = self.get_track_position(device).await?;
+        Ok((track.position_secs, track.duration_secs))
+    }
+
+    // 获取进度、时长与当前曲目地址（一次 GetPositionInfo）
+    pub async fn get_track_position(&self, device: &DlnaDevice) -> Result<TrackPosition, Error> {
         let position_info = self.get_position_info(device).await?;
 
-        // 获取相对时间
-        let default_time = "00:00:00".to_string();
-        let rel_time = position_info.get("RelTime").unwrap_or(&default_time);
+        let rel_time = position_info.get("RelTime").map(String::as_str).unwrap_or("");
         let duration = position_info
             .get("TrackDuration")
-            .or_else(|| position_info.get("AbsTime"))
-            .unwrap_or(&default_time);
+            .map(String::as_str)
+            .unwrap_or("");
         log::debug!(
             "get_secs() : RelTime: {}, TrackDuration: {}",
             rel_time,
             duration
         );
 
-        // 解析时间字符串，支持格式如 "0:00:01" 和 "00:00:01"
-        fn parse_time_str(time_str: &str) -> Result<NaiveTime, rupnp::Error> {
-            let trimmed = time_str.trim();
-
-            // 尝试多种时间格式
-            let formats = ["%H:%M:%S", "%M:%S", "%S"];
+        let position_secs = parse_upnp_duration(rel_time)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+        let duration_secs = parse_upnp_duration(duration)
+            .map(|d| d.as_secs() as u32)
+            .filter(|s| *s > 0);
+        let track_uri = position_info
+            .get("TrackURI")
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(TrackPosition {
+            position_secs,
+            duration_secs,
+            track_uri,
+        })
+    }
 
-            for fmt in &formats {
-                if let Ok(time) = NaiveTime::parse_from_str(trimmed, fmt) {
-                    log::debug!("成功解析时间 '{}' 格式: {}", trimmed, fmt);
-                    return Ok(time);
+    // 设置播放进度（跳转到指定秒数），并用 GetPositionInfo 确认设备确实跳过去了：
+    // 不少电视在缓冲时会忽略 Seek 却照样返回成功，这时重发
+    pub async fn seek(&self, device: &DlnaDevice, seconds: u32) -> Result<(), Error> {
+        let mut last_position = 0;
+        for attempt in 1..=SEEK_ATTEMPTS {
+            self.seek_with_fallback(device, seconds).await?;
+            tokio::time::sleep(SEEK_VERIFY_DELAY).await;
+
+            let position = match self.get_position_info(device).await {
+                Ok(info) => info
+                    .get("RelTime")
+                    .and_then(|v| parse_upnp_duration(v))
+                    .map(|d| d.as_secs() as u32),
+                Err(e) => {
+                    log::debug!("Seek 后查询进度失败，无法确认: {}", e);
+                    return Ok(());
                 }
+            };
+            // 设备不报告进度时无法确认，按已跳转处理
+            let Some(position) = position else {
+                return Ok(());
+            };
+            if seek_reached(position, seconds) {
+                log::info!("Seek 已生效，当前进度 {} 秒", position);
+                return Ok(());
+            }
+            log::warn!(
+                "Seek 未生效（第 {} 次）：目标 {} 秒，设备进度 {} 秒",
+                attempt,
+                seconds,
+                position
+            );
+            last_position = position;
+        }
+        Err(Error::SeekNotApplied {
+            target_secs: seconds,
+            position_secs: last_position,
+        })
+    }
+
+    // 按 Seek Unit 依次尝试：设备配置/学到的单位优先，其后是 REL_TIME → ABS_TIME → X_DLNA_REL_BYTE。
+    // 设备拒绝某个单位后改用下一个，成功的单位记入设备配置
+    async fn seek_with_fallback(&self, device: &DlnaDevice, seconds: u32) -> Result<(), Error> {
+        let preferred: Vec<String> = device_quirks(device)
+            .seek_units
+            .into_iter()
+            .filter(|u| SEEK_UNITS.contains(&u.as_str()))
+            .collect();
+        let mut units = preferred.clone();
+        for unit in SEEK_UNITS {
+            if !units.iter().any(|u| u == unit) {
+                units.push(unit.to_string());
             }
+        }
 
-            // 处理格式如 "0:00:01"（单个小时位）的情况
-            if trimmed.contains(':') {
-                let parts: Vec<&str> = trimmed.split(':').collect();
-                if parts.len() == 3 {
-                    // 确保每个部分都有正确的位数
-                    let formatted = format!(
-                        "{:02}:{:02}:{:02}",
-                        parts[0].parse::<u32>().unwrap_or(0),
-                        parts[1].parse::<u32>().unwrap_or(0),
-                        parts[2].parse::<u32>().unwrap_or(0)
-                    );
-                    log::debug!("格式化时间 '{}' 为 '{}'", trimmed, formatted);
-                    if let Ok(time) = NaiveTime::parse_from_str(&formatted, "%H:%M:%S") {
-                        return Ok(time);
+        let mut last_error = None;
+        for unit in &units {
+            let Some(target) = self.seek_target(device, unit, seconds).await else {
+                log::debug!("无法计算 {} 的 Seek 目标，跳过", unit);
+                continue;
+            };
+            match self.send_seek(device, unit, &target).await {
+                Ok(()) => {
+                    if last_error.is_some() && preferred.first() != Some(unit) {
+                        quirks::store().learn(device.device.udn(), &device.friendly_name, |p| {
+                            p.seek_units = vec![unit.clone()];
+                        });
                     }
+                    return Ok(());
                 }
+                Err(e) if rejects_seek_unit(&e) => {
+                    log::info!("设备不接受 Seek Unit {}: {}，尝试下一种", unit, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
             }
-
-            Err(rupnp::Error::ParseError("无法解析时间字符串"))
         }
+        Err(last_error.unwrap_or(Error::UnsupportedService("Seek")))
+    }
 
-        let current_time =
-            parse_time_str(rel_time).unwrap_or_else(|_| NaiveTime::from_hms_opt(0, 0, 0).unwrap());
-        let total_time =
-            parse_time_str(duration).unwrap_or_else(|_| NaiveTime::from_hms_opt(0, 0, 0).unwrap());
-
-        let current_secs = current_time.num_seconds_from_midnight();
-        let total_secs = total_time.num_seconds_from_midnight();
-
-        Ok((current_secs, 36000)) //模拟设备没有正确返回总时长，暂时固定为10小时（36000秒），以避免除以0或其他错误
+    // Unit 对应的 Target：时间单位为 HH:MM:SS；
+    // X_DLNA_REL_BYTE 按当前曲目 DIDL-Lite 中 res 的 size / duration 折算字节偏移
+    async fn seek_target(&self, device: &DlnaDevice, unit: &str, seconds: u32) -> Option<String> {
+        if unit != "X_DLNA_REL_BYTE" {
+            return Some(format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                (seconds % 3600) / 60,
+                seconds % 60
+            ));
+        }
+        let info = self.get_position_info(device).await.ok()?;
+        let metadata = info
+            .get("TrackMetaData")
+            .and_then(|m| parse_didl_lite(m))?
+            .metadata;
+        let duration = metadata.duration_secs.or_else(|| {
+            info.get("TrackDuration")
+                .and_then(|v| parse_upnp_duration(v))
+                .map(|d| d.as_secs() as u32)
+        })?;
+        byte_offset(seconds, metadata.size?, duration).map(|b| b.to_string())
     }
 
-    // 设置播放进度（跳转到指定秒数）
-    pub async fn seek(&self, device: &DlnaDevice, seconds: u32) -> Result<(), rupnp::Error> {
+    async fn send_seek(&self, device: &DlnaDevice, unit: &str, target: &str) -> Result<(), Error> {
         let avtransport = self
             .get_avtransport_service(device)
-            .ok_or(rupnp::Error::ParseError("设备不支持AVTransport服务"))?;
-
-        // 将秒数转换为 HH:MM:SS 格式
-        let hours = seconds / 3600;
-        let minutes = (seconds % 3600) / 60;
-        let secs = seconds % 60;
-        let target_time = format!("{:02}:{:02}:{:02}", hours, minutes, secs);
+            .ok_or(Error::UnsupportedService("AVTransport"))?;
 
-        log::info!("正在发送Seek指令，跳转至: {}", target_time);
-
-        // Unit 常用选项:
-        // REL_TIME: 按照时间进度跳转 (最常用)
-        // TRACK_NR: 按照轨道编号跳转
+        log::info!("正在发送Seek指令，Unit={} Target={}", unit, target);
         let action = "Seek";
         let args_str = format!(
-            "<InstanceID>0</InstanceID><Unit>REL_TIME</Unit><Target>{}</Target>",
-            target_time
+            "<InstanceID>0</InstanceID><Unit>{}</Unit><Target>{}</Target>",
+            unit, target
         );
 
         let base_url = device_location_uri(device)?;
         log_upnp_action(avtransport, &base_url, action, &args_str);
 
-        // 发送请求
-        let response = avtransport_action_compat(avtransport, &base_url, action, &args_str).await?;
+        let response = avtransport_action_compat(device, avtransport, &base_url, action, &args_str).await?;
         log::info!("Seek响应: {:?}", response);
 
         Ok(())
     }
 
     // 设置渲染器音量
-    pub async fn set_volume(&self, device: &DlnaDevice, volume: u32) -> Result<(), rupnp::Error> {
-        let rendering_control = device
-            .device
-            .services()
-            .iter()
-            .find(|s| *s.service_type() == URN::service("schemas-upnp-org", "RenderingControl", 1))
-            .ok_or(rupnp::Error::ParseError("设备不支持RenderingControl服务"))?;
+    pub async fn set_volume(&self, device: &DlnaDevice, volume: u32) -> Result<(), Error> {
+        let rendering_control = self
+            .get_rendering_control_service(device)
+            .ok_or(Error::UnsupportedService("RenderingControl"))?;
 
         let action = "SetVolume";
         let args_str = format!(
@@ -729,9 +1772,8 @@ impl DlnaController {
         );
 
         let base_url = device_location_uri(device)?;
-        // RenderingControl uses a different service; still log with a reasonable SOAPAction.
         log::debug!(
-            "UPnP Action -> base_url={} service_id={} service_type={} SOAPAction=\"urn:schemas-upnp-org:service:RenderingControl:1#{}\"",
+            "UPnP Action -> base_url={} service_id={} SOAPAction=\"{}#{}\"",
             base_url,
             rendering_control.service_id(),
             rendering_control.service_type(),
@@ -743,30 +1785,29 @@ impl DlnaController {
                 r#"<?xml version="1.0" encoding="UTF-8"?>
                 <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
                 <s:Body>
-                    <u:{action} xmlns:u="urn:schemas-upnp-org:service:RenderingControl:1">{args}</u:{action}>
+                    <u:{action} xmlns:u="{service_type}">{args}</u:{action}>
                 </s:Body>
                 </s:Envelope>"#,
                 action = action,
+                service_type = rendering_control.service_type(),
                 args = args_str
             )
         );
 
         let response = rendering_control
             .action(&base_url, action, &args_str)
-            .await?;
+            .await
+            .map_err(|e| Error::upnp(action, e))?;
         log::debug!("SetVolume响应: {:?}", response);
 
         Ok(())
     }
 
     // 获取渲染器音量
-    pub async fn get_volume(&self, device: &DlnaDevice) -> Result<u32, rupnp::Error> {
-        let rendering_control = device
-            .device
-            .services()
-            .iter()
-            .find(|s| *s.service_type() == URN::service("schemas-upnp-org", "RenderingControl", 1))
-            .ok_or(rupnp::Error::ParseError("设备不支持RenderingControl服务"))?;
+    pub async fn get_volume(&self, device: &DlnaDevice) -> Result<u32, Error> {
+        let rendering_control = self
+            .get_rendering_control_service(device)
+            .ok_or(Error::UnsupportedService("RenderingControl"))?;
 
         let action = "GetVolume";
         let args_str = r#"
@@ -776,7 +1817,7 @@ impl DlnaController {
 
         let base_url = device_location_uri(device)?;
         log::debug!(
-            "UPnP Action -> base_url={} service_id={} service_type={} SOAPAction=\"urn:schemas-upnp-org:service:RenderingControl:1#{}\"",
+            "UPnP Action -> base_url={} service_id={} SOAPAction=\"{}#{}\"",
             base_url,
             rendering_control.service_id(),
             rendering_control.service_type(),
@@ -788,17 +1829,19 @@ impl DlnaController {
                 r#"<?xml version="1.0" encoding="UTF-8"?>
                 <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
                 <s:Body>
-                    <u:{action} xmlns:u="urn:schemas-upnp-org:service:RenderingControl:1">{args}</u:{action}>
+                    <u:{action} xmlns:u="{service_type}">{args}</u:{action}>
                 </s:Body>
                 </s:Envelope>"#,
                 action = action,
+                service_type = rendering_control.service_type(),
                 args = args_str
             )
         );
 
         let response = rendering_control
             .action(&base_url, action, args_str)
-            .await?;
+            .await
+            .map_err(|e| Error::upnp(action, e))?;
 
         // 解析音量值
         let default_volume = "0".to_string();
@@ -808,46 +1851,399 @@ impl DlnaController {
         Ok(volume)
     }
 }
+
+/// `DlnaController` 绑定到一台设备，作为引擎的播放目标
+#[derive(Clone)]
+pub struct DlnaRenderer {
+    controller: DlnaController,
+    device: DlnaDevice,
+}
+
+impl DlnaRenderer {
+    pub fn new(controller: DlnaController, device: DlnaDevice) -> Self {
+        Self { controller, device }
+    }
+
+    pub fn controller(&self) -> &DlnaController {
+        &self.controller
+    }
+
+    pub fn device(&self) -> &DlnaDevice {
+        &self.device
+    }
+}
+
+impl Renderer for DlnaRenderer {
+    fn id(&self) -> &str {
+        &self.device.location
+    }
+
+    fn name(&self) -> &str {
+        &self.device.friendly_name
+    }
+
+    fn udn(&self) -> &str {
+        self.device.device.udn()
+    }
+
+    fn local_addr(&self) -> Option<IpAddr> {
+        self.device.local_addr()
+    }
+
+    fn quirks(&self) -> QuirkProfile {
+        device_quirks(&self.device)
+    }
+
+    fn negotiate(&self, content_type: &str) -> Result<Option<String>, Error> {
+        self.controller
+            .negotiate_protocol_info(&self.device, content_type)
+            .map(Some)
+    }
+
+    fn load<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.controller.set_avtransport_uri(
+            &self.device,
+            media.uri_path,
+            media.metadata,
+            media.protocol_info,
+            media.server_ip,
+            media.server_port,
+        ))
+    }
+
+    fn load_next<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<(), Error>> {
+        Box::pin(self.controller.set_next_avtransport_uri(
+            &self.device,
+            media.uri_path,
+            media.metadata,
+            media.protocol_info,
+            media.server_ip,
+            media.server_port,
+        ))
+    }
+
+    fn play(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(self.controller.play(&self.device))
+    }
+
+    fn pause(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(self.controller.pause(&self.device))
+    }
+
+    fn stop(&self) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(self.controller.stop(&self.device))
+    }
+
+    fn seek(&self, secs: u32) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(self.controller.seek(&self.device, secs))
+    }
+
+    fn set_volume(&self, volume: u32) -> BoxFuture<'_, Result<(), Error>> {
+        Box::pin(self.controller.set_volume(&self.device, volume))
+    }
+
+    fn get_volume(&self) -> BoxFuture<'_, Result<u32, Error>> {
+        Box::pin(self.controller.get_volume(&self.device))
+    }
+
+    fn position(&self) -> BoxFuture<'_, Result<TrackPosition, Error>> {
+        Box::pin(self.controller.get_track_position(&self.device))
+    }
+
+    fn transport_state(&self) -> BoxFuture<'_, Result<TransportState, Error>> {
+        Box::pin(async move { Ok(self.controller.get_transport_info(&self.device).await?.state) })
+    }
+
+    fn subscribe<'a>(&'a self, callback_base: &'a str) -> BoxFuture<'a, Result<Box<dyn Subscription>, Error>> {
+        Box::pin(async move {
+            let subscriber = EventSubscriber::start(
+                self.controller.clone(),
+                self.device.clone(),
+                callback_base.to_string(),
+            )
+            .await?;
+            Ok(Box::new(subscriber) as Box<dyn Subscription>)
+        })
+    }
+
+    // 按 UDN / 名称重新发现同一台设备，重建 DlnaDevice 并确认它响应 AVTransport
+    fn reconnect(&self) -> BoxFuture<'_, Result<Arc<dyn Renderer>, Error>> {
+        Box::pin(async move {
+            let old = &self.device;
+            let location = crate::reconnect::rediscover(old.device.udn(), &old.friendly_name, &old.location)
+                .await
+                .unwrap_or_else(|| old.location.clone());
+            let device = crate::load_device(&self.controller, &location).await?;
+            // 描述文件能拉到还不够，确认设备真正响应 AVTransport
+            self.controller.get_transport_info(&device).await?;
+            Ok(Arc::new(Self::new(self.controller.clone(), device)) as Arc<dyn Renderer>)
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[tokio::test]
     async fn test_set_next_avtransport_uri() {
-        let controller = DlnaController::new();
+        use crate::mock_renderer::{MockOptions, MockRenderer, use_temp_data_dir};
 
-        // 发现DLNA设备
-        let devices = controller.discover_devices().await;
-        match devices {
-            Ok(devices) => {
-                if devices.is_empty() {
-                    println!("未发现DLNA设备，跳过测试");
-                    return;
-                }
+        use_temp_data_dir();
+        let mock = MockRenderer::start(MockOptions::default()).await;
+        let controller = DlnaController::new();
+        let device = crate::load_device(&controller, &mock.location).await.unwrap();
+
+        // 测试设置下一首媒体URI
+        controller
+            .set_next_avtransport_uri(
+                &device,
+                "media/test_next.mp4?a=1&b=2",
+                &MediaMetadata::default(),
+                None,
+                "127.0.0.1".parse().unwrap(),
+                8080,
+            )
+            .await
+            .unwrap();
+        let media = controller.get_media_info(&device).await.unwrap();
+        assert_eq!(media.next_uri, "http://127.0.0.1:8080/media/test_next.mp4?a=1&b=2");
+
+        // 不支持 SetNextAVTransportURI 的设备返回 SOAP 错误
+        let mock = MockRenderer::start(MockOptions {
+            next_uri: false,
+            ..Default::default()
+        })
+        .await;
+        let device = crate::load_device(&controller, &mock.location).await.unwrap();
+        let result = controller
+            .set_next_avtransport_uri(
+                &device,
+                "media/test_next.mp4",
+                &MediaMetadata::default(),
+                None,
+                "127.0.0.1".parse().unwrap(),
+                8080,
+            )
+            .await;
+        assert!(result.is_err());
+    }
 
-                // 使用第一个设备
-                let device = &devices[0];
-                println!("使用设备: {}", device.friendly_name);
-
-                // 测试设置下一首媒体URI
-                let result = controller
-                    .set_next_avtransport_uri(
-                        device,
-                        "/media/test_next.mp4",
-                        "",
-                        "127.0.0.1".parse().unwrap(),
-                        8080,
-                    )
-                    .await;
-
-                match result {
-                    Ok(_) => println!("设置下一首媒体URI成功"),
-                    Err(e) => println!("设置下一首媒体URI失败: {}", e),
-                }
+    #[test]
+    fn test_parse_soap_fault() {
+        let body = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>
+<detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>714</errorCode><errorDescription>Illegal MIME-type</errorDescription></UPnPError></detail>
+</s:Fault></s:Body></s:Envelope>"#;
+        match parse_soap_fault("SetAVTransportURI", body) {
+            Some(Error::SoapFault {
+                action,
+                error_code,
+                error_description,
+            }) => {
+                assert_eq!(action, "SetAVTransportURI");
+                assert_eq!(error_code, Some(714));
+                assert_eq!(error_description, "Illegal MIME-type");
             }
-            Err(e) => {
-                println!("设备发现失败: {}", e);
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(parse_soap_fault("Play", "<html>500</html>").is_none());
+
+        // LG webOS：SOAP-ENV 前缀，描述里带实体
+        let lg = r#"<?xml version="1.0" encoding="utf-8"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/"><SOAP-ENV:Body><SOAP-ENV:Fault><faultcode>SOAP-ENV:Client</faultcode><faultstring>UPnPError</faultstring><detail><u:UPnPError xmlns:u="urn:schemas-upnp-org:control-1-0"><u:errorCode> 701 </u:errorCode><u:errorDescription>Transition not available &amp; busy</u:errorDescription></u:UPnPError></detail></SOAP-ENV:Fault></SOAP-ENV:Body></SOAP-ENV:Envelope>"#;
+        match parse_soap_fault("Seek", lg) {
+            Some(Error::SoapFault {
+                error_code,
+                error_description,
+                ..
+            }) => {
+                assert_eq!(error_code, Some(701));
+                assert_eq!(error_description, "Transition not available & busy");
             }
+            other => panic!("unexpected: {:?}", other),
         }
+        // 只有 faultstring 的 Fault
+        match parse_soap_fault("Play", r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><s:Fault><faultcode>s:Server</faultcode><faultstring>Internal Error</faultstring></s:Fault></s:Body></s:Envelope>"#) {
+            Some(Error::SoapFault {
+                error_code,
+                error_description,
+                ..
+            }) => {
+                assert_eq!(error_code, None);
+                assert_eq!(error_description, "Internal Error");
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        // 正常响应里出现 "Fault" 字样（如地址）不算 Fault
+        assert!(parse_soap_fault(
+            "GetMediaInfo",
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:GetMediaInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1"><CurrentURI>http://h/Fault.mp4</CurrentURI></u:GetMediaInfoResponse></s:Body></s:Envelope>"#
+        )
+        .is_none());
+    }
+
+    // 以下响应按各品牌设备的实际格式整理：命名空间前缀、实体、CDATA、未转义嵌入等写法各不相同
+    #[test]
+    fn test_parse_soap_response_brands() {
+        // 三星：DIDL-Lite 转义后嵌入，res 里的 & 转义了两层；sec: 前缀未声明
+        let samsung = r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:GetPositionInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1"><Track>1</Track><TrackDuration>0:04:29</TrackDuration><TrackMetaData>&lt;DIDL-Lite xmlns=&quot;urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/&quot; xmlns:dc=&quot;http://purl.org/dc/elements/1.1/&quot; xmlns:upnp=&quot;urn:schemas-upnp-org:metadata-1-0/upnp/&quot;&gt;&lt;item id=&quot;0&quot; parentID=&quot;-1&quot; restricted=&quot;1&quot;&gt;&lt;dc:title&gt;晴天 &amp;amp; 七里香&lt;/dc:title&gt;&lt;sec:CaptionInfoEx sec:type=&quot;srt&quot;&gt;&lt;/sec:CaptionInfoEx&gt;&lt;upnp:class&gt;object.item.videoItem&lt;/upnp:class&gt;&lt;res protocolInfo=&quot;http-get:*:video/mp4:*&quot; duration=&quot;0:04:29.000&quot;&gt;http://192.168.1.2:8080/BV1xx411c7mD?p=2&amp;amp;t=1&lt;/res&gt;&lt;/item&gt;&lt;/DIDL-Lite&gt;</TrackMetaData><TrackURI>http://192.168.1.2:8080/BV1xx411c7mD?p=2&amp;t=1</TrackURI><RelTime>0:01:23</RelTime><AbsTime>NOT_IMPLEMENTED</AbsTime><RelCount>2147483647</RelCount><AbsCount>2147483647</AbsCount></u:GetPositionInfoResponse></s:Body></s:Envelope>"#;
+        let out = parse_soap_response(samsung).unwrap();
+        assert_eq!(out["TrackURI"], "http://192.168.1.2:8080/BV1xx411c7mD?p=2&t=1");
+        assert_eq!(out["RelTime"], "0:01:23");
+        assert_eq!(out["AbsTime"], "NOT_IMPLEMENTED");
+        let item = parse_didl_lite(&out["TrackMetaData"]).unwrap();
+        assert_eq!(item.metadata.title.as_deref(), Some("晴天 & 七里香"));
+        assert_eq!(item.metadata.duration_secs, Some(269));
+        assert_eq!(item.res_url.as_deref(), Some("http://192.168.1.2:8080/BV1xx411c7mD?p=2&t=1"));
+
+        // Sony BRAVIA：SOAP-ENV 前缀，输出参数带默认命名空间
+        let sony = r#"<?xml version="1.0"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/" SOAP-ENV:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+  <SOAP-ENV:Body>
+    <m:GetTransportInfoResponse xmlns:m="urn:schemas-upnp-org:service:AVTransport:1">
+      <CurrentTransportState xmlns="">PAUSED_PLAYBACK</CurrentTransportState>
+      <CurrentTransportStatus xmlns="">OK</CurrentTransportStatus>
+      <CurrentSpeed xmlns="">1</CurrentSpeed>
+    </m:GetTransportInfoResponse>
+  </SOAP-ENV:Body>
+</SOAP-ENV:Envelope>"#;
+        let out = parse_soap_response(sony).unwrap();
+        assert_eq!(out["CurrentTransportState"], "PAUSED_PLAYBACK");
+        assert_eq!(out["CurrentSpeed"], "1");
+
+        // 小米盒子：元数据放在 CDATA 里，正文前有 BOM、末尾有 \0
+        let xiaomi = "\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><u:GetMediaInfoResponse xmlns:u=\"urn:schemas-upnp-org:service:AVTransport:1\"><NrTracks>1</NrTracks><MediaDuration>00:03:25</MediaDuration><CurrentURI>http://192.168.1.2:8080/BV1ab411c7mE</CurrentURI><CurrentURIMetaData><![CDATA[<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\"><item id=\"0\" parentID=\"-1\" restricted=\"1\"><dc:title>稻香</dc:title><upnp:artist>小红</upnp:artist></item></DIDL-Lite>]]></CurrentURIMetaData><NextURI></NextURI><NextURIMetaData></NextURIMetaData><PlayMedium>NETWORK</PlayMedium></u:GetMediaInfoResponse></s:Body></s:Envelope>\0";
+        let out = parse_soap_response(xiaomi).unwrap();
+        assert_eq!(out["MediaDuration"], "00:03:25");
+        assert_eq!(out["NextURI"], "");
+        let item = parse_didl_lite(&out["CurrentURIMetaData"]).unwrap();
+        assert_eq!(item.metadata.title.as_deref(), Some("稻香"));
+        assert_eq!(item.metadata.artist.as_deref(), Some("小红"));
+
+        // 海信：DIDL-Lite 不转义直接嵌入，原样取出内部 XML
+        let hisense = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:GetMediaInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1"><NrTracks>1</NrTracks><CurrentURI>http://192.168.1.2:8080/BV1cd411c7mF</CurrentURI><CurrentURIMetaData><DIDL-Lite xmlns="urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/" xmlns:dc="http://purl.org/dc/elements/1.1/"><item id="0" parentID="-1" restricted="1"><dc:title>七里香</dc:title><res protocolInfo="http-get:*:video/mp4:*" size="1024">http://192.168.1.2:8080/BV1cd411c7mF</res></item></DIDL-Lite></CurrentURIMetaData></u:GetMediaInfoResponse></s:Body></s:Envelope>"#;
+        let out = parse_soap_response(hisense).unwrap();
+        let item = parse_didl_lite(&out["CurrentURIMetaData"]).unwrap();
+        assert_eq!(item.metadata.title.as_deref(), Some("七里香"));
+        assert_eq!(item.metadata.size, Some(1024));
+        assert_eq!(item.protocol_info.as_deref(), Some("http-get:*:video/mp4:*"));
+
+        // URL 里的 & 没有转义，不是合法 XML：按标签名查找并解码其余实体
+        let broken = r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:GetPositionInfoResponse xmlns:u="urn:schemas-upnp-org:service:AVTransport:1"><TrackURI>http://192.168.1.2:8080/a?x=1&y=2</TrackURI><u:RelTime>0:00:07</u:RelTime><TrackMetaData>&lt;DIDL-Lite/&gt;</TrackMetaData></u:GetPositionInfoResponse></s:Body></s:Envelope>"#;
+        assert!(parse_soap_response(broken).is_none());
+        let out = scan_soap_response(broken, COMPAT_RESPONSE_FIELDS);
+        assert_eq!(out["TrackURI"], "http://192.168.1.2:8080/a?x=1&y=2");
+        assert_eq!(out["RelTime"], "0:00:07");
+        assert_eq!(out["TrackMetaData"], "<DIDL-Lite/>");
+        assert!(!out.contains_key("Track"));
+    }
+
+    #[test]
+    fn test_build_didl_lite_metadata() {
+        let metadata = MediaMetadata {
+            title: Some("晴天 & 七里香".to_string()),
+            artist: Some("小明".to_string()),
+            duration_secs: Some(3725),
+            size: Some(12_345_678),
+            album_art_url: Some("http://192.168.1.2:8080/_cover/BV1xx411c7mD".to_string()),
+        };
+        let url = "http://192.168.1.2:8080/BV1xx411c7mD-page2";
+        let didl = xml_unescape(&build_didl_lite_metadata(
+            "BV1xx411c7mD-page2",
+            &metadata,
+            url,
+            Some("http-get:*:video/mp4:*"),
+            MetadataStyle::Didl,
+        ));
+        assert!(didl.contains("<dc:title>晴天 &amp; 七里香</dc:title>"));
+        assert!(didl.contains("<upnp:artist>小明</upnp:artist><dc:creator>小明</dc:creator>"));
+        assert!(didl.contains(r#"<res protocolInfo="http-get:*:video/mp4:*" duration="1:02:05.000" size="12345678">"#));
+        assert!(didl.contains(
+            r#"<upnp:albumArtURI dlna:profileID="JPEG_TN">http://192.168.1.2:8080/_cover/BV1xx411c7mD</upnp:albumArtURI>"#
+        ));
+        assert!(!didl.contains('\\'));
+        // 设备回报的元数据用同一套解析读回
+        let item = parse_didl_lite(&didl).unwrap();
+        assert_eq!(item.metadata, metadata);
+        assert_eq!(item.class.as_deref(), Some("object.item.videoItem"));
+        assert_eq!(item.res_url.as_deref(), Some(url));
+
+        // 精简写法只保留标题；没有歌单信息时用代理路径作标题
+        let minimal = xml_unescape(&build_didl_lite_metadata(
+            "BV1xx411c7mD-page2",
+            &MediaMetadata::default(),
+            url,
+            None,
+            MetadataStyle::Minimal,
+        ));
+        assert!(minimal.contains("<dc:title>BV1xx411c7mD-page2</dc:title>"));
+        assert!(!minimal.contains("upnp:artist"));
+        assert!(build_didl_lite_metadata("x", &metadata, url, None, MetadataStyle::Empty).is_empty());
+    }
+
+    #[test]
+    fn test_seek_helpers() {
+        // 确认时允许少量误差和已经播放的时间
+        assert!(seek_reached(90, 90));
+        assert!(seek_reached(88, 90));
+        assert!(seek_reached(94, 90));
+        // 缓冲中忽略了 Seek：进度仍在原处
+        assert!(!seek_reached(12, 90));
+        assert!(!seek_reached(200, 90));
+
+        assert_eq!(byte_offset(60, 12_000_000, 240), Some(3_000_000));
+        assert_eq!(byte_offset(300, 12_000_000, 240), Some(12_000_000));
+        assert_eq!(byte_offset(60, 0, 240), None);
+        assert_eq!(byte_offset(60, 12_000_000, 0), None);
+
+        assert!(rejects_seek_unit(&Error::soap_fault("Seek", Some(710), "Seek mode not supported")));
+        assert!(rejects_seek_unit(&Error::soap_fault("Seek", None, "")));
+        // 设备忙（701）不换单位
+        assert!(!rejects_seek_unit(&Error::soap_fault("Seek", Some(701), "Transition not available")));
+        assert!(!rejects_seek_unit(&Error::Network("timeout".to_string())));
+    }
+
+    #[test]
+    fn test_select_protocol_info() {
+        let sinks = ProtocolInfo::parse_list(
+            "http-get:*:video/mp4:DLNA.ORG_PN=AVC_MP4_BL_CIF15_AAC_520;DLNA.ORG_OP=01,\
+             http-get:*:video/mpeg4:*,\
+             http-get:*:audio/mpeg:DLNA.ORG_PN=MP3",
+        );
+        assert_eq!(sinks.len(), 3);
+        assert_eq!(sinks[0].dlna_profile(), Some("AVC_MP4_BL_CIF15_AAC_520"));
+
+        // 优先选择不绑定 PN 的条目
+        assert_eq!(
+            select_protocol_info(&sinks, "video/mp4").as_deref(),
+            Some("http-get:*:video/mp4:DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000")
+        );
+        // 只有带 PN 的条目时沿用设备给出的 PN
+        assert_eq!(
+            select_protocol_info(&sinks, "audio/mpeg").as_deref(),
+            Some("http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000")
+        );
+        // 设备不支持 HLS
+        assert_eq!(select_protocol_info(&sinks, "application/vnd.apple.mpegurl"), None);
+        // 设备没有返回 Sink 列表时使用宽松写法
+        assert_eq!(
+            select_protocol_info(&[], "video/mp4").as_deref(),
+            Some("http-get:*:video/mp4:*")
+        );
+        // 别名表里没有的格式，设备原样列出时也能匹配
+        let sinks = ProtocolInfo::parse_list("http-get:*:video/x-matroska:*,http-get:*:audio/flac:*");
+        assert_eq!(
+            select_protocol_info(&sinks, "video/x-matroska").as_deref(),
+            Some("http-get:*:video/x-matroska:DLNA.ORG_OP=01;DLNA.ORG_FLAGS=01700000000000000000000000000000")
+        );
+        assert_eq!(select_protocol_info(&sinks, "video/quicktime"), None);
+        // 猜不出格式时不拒绝，交给设备
+        assert_eq!(
+            select_protocol_info(&sinks, "application/octet-stream").as_deref(),
+            Some("http-get:*:application/octet-stream:*")
+        );
     }
 }
@@ -0,0 +1,357 @@
+use crate::SharedState;
+use crate::dlna_controller::{
+    DlnaController, DlnaDevice, EventService, EventSubscription, TransportState,
+    element_text, extract_xml_attr_value, find_element, scan_tag_text, with_xml, xml_unescape,
+};
+use crate::renderer::Subscription;
+use actix_web::{HttpRequest, HttpResponse, route, web};
+use log::{debug, info, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// 向设备申请的订阅时长（秒）
+const SUBSCRIPTION_TIMEOUT_SECS: u32 = 300;
+// 续订至少提前这么多秒
+const RENEW_MARGIN_SECS: u32 = 30;
+
+/// LastChange 中解析出的单个状态变化
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    TransportState(TransportState),
+    TransportStatus(String),
+    CurrentTrackUri(String),
+    AvTransportUri(String),
+    CurrentTrackDuration(String),
+    Volume(u32),
+    Mute(bool),
+}
+
+/// 设备推送的一次 NOTIFY
+#[derive(Debug, Clone)]
+pub struct RendererEvent {
+    pub service: EventService,
+    pub sid: String,
+    pub seq: u32,
+    pub changes: Vec<StateChange>,
+}
+
+/// 解析 NOTIFY 的 propertyset 正文。
+/// AVTransport/RenderingControl 把所有状态变量打包在 LastChange 里（转义后的 XML，
+/// 也有设备用 CDATA 或直接嵌入未转义的 XML）。
+pub fn parse_property_set(body: &str) -> Vec<StateChange> {
+    let last_change = with_xml(body, |doc| {
+        find_element(doc.root(), "LastChange").map(|n| element_text(n, doc.input_text()))
+    })
+    .unwrap_or_else(|| scan_tag_text(body, "LastChange"));
+    match last_change {
+        Some(last_change) => parse_last_change(&last_change),
+        None => Vec::new(),
+    }
+}
+
+/// 解析反转义后的 LastChange `<Event><InstanceID val="0">...</InstanceID></Event>`
+pub fn parse_last_change(event_xml: &str) -> Vec<StateChange> {
+    with_xml(event_xml, |doc| {
+        // 只看第一个 InstanceID（渲染器只有实例 0）
+        let Some(instance) = find_element(doc.root(), "InstanceID") else {
+            return Vec::new();
+        };
+        let vars: Vec<_> = instance.children().filter(|n| n.is_element()).collect();
+        let val = |name: &str| {
+            vars.iter()
+                .filter(|n| n.tag_name().name() == name)
+                // 多声道的 Volume/Mute 只取 Master（没写 channel 的设备视为 Master）
+                .find(|n| {
+                    n.attribute("channel")
+                        .is_none_or(|c| c.eq_ignore_ascii_case("Master"))
+                })
+                .and_then(|n| n.attribute("val"))
+                .map(str::to_string)
+        };
+        state_changes(val)
+    })
+    .unwrap_or_else(|| {
+        // 设备只转义了一层时，URL 里的 & 解开后是裸字符，内层不是合法 XML
+        debug!("LastChange 不是合法 XML，按标签名查找");
+        state_changes(|name| {
+            extract_xml_attr_value(event_xml, name, "val", Some(("channel", "Master")))
+                .map(|v| xml_unescape(&v))
+        })
+    })
+}
+
+fn state_changes(val: impl Fn(&str) -> Option<String>) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+
+    if let Some(v) = val("TransportState") {
+        changes.push(StateChange::TransportState(TransportState::parse(&v)));
+    }
+    if let Some(v) = val("TransportStatus") {
+        changes.push(StateChange::TransportStatus(v));
+    }
+    if let Some(v) = val("CurrentTrackURI") {
+        changes.push(StateChange::CurrentTrackUri(v));
+    }
+    if let Some(v) = val("AVTransportURI") {
+        changes.push(StateChange::AvTransportUri(v));
+    }
+    if let Some(v) = val("CurrentTrackDuration") {
+        changes.push(StateChange::CurrentTrackDuration(v));
+    }
+    if let Some(v) = val("Volume")
+        && let Ok(volume) = v.trim().parse::<u32>()
+    {
+        changes.push(StateChange::Volume(volume));
+    }
+    if let Some(v) = val("Mute") {
+        let v = v.trim();
+        changes.push(StateChange::Mute(v == "1" || v.eq_ignore_ascii_case("true")));
+    }
+
+    changes
+}
+
+/// 设备的 NOTIFY 回调入口，必须注册在 proxy_handler 之前
+#[route("/_dlna/event/{service}", method = "NOTIFY")]
+pub async fn notify_handler(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    body: web::Bytes,
+    shared_state: web::Data<SharedState>,
+) -> HttpResponse {
+    let (segment,) = path.into_inner();
+    let Some(service) = EventService::from_path_segment(&segment) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+    };
+    let Some(sid) = header("SID") else {
+        // UPnP 规定缺少 SID 时返回 412
+        return HttpResponse::PreconditionFailed().finish();
+    };
+    let seq = header("SEQ").and_then(|v| v.parse().ok()).unwrap_or(0);
+
+    let text = String::from_utf8_lossy(&body);
+    let changes = parse_property_set(&text);
+    debug!("收到 {} 事件 sid={} seq={}: {:?}", segment, sid, seq, changes);
+
+    if !changes.is_empty() {
+        // 没有订阅者时发送失败是正常的
+        let _ = shared_state.renderer_events.send(RendererEvent {
+            service,
+            sid,
+            seq,
+            changes,
+        });
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+/// 维护 AVTransport / RenderingControl 订阅并按时续订。
+/// Drop 时停止续订任务。
+pub struct EventSubscriber {
+    active: Arc<AtomicBool>,
+    // 当前有效的 SID，重新订阅后更新
+    sids: Arc<std::sync::Mutex<Vec<String>>>,
+    renew_task: tokio::task::JoinHandle<()>,
+}
+
+impl EventSubscriber {
+    /// 订阅设备事件。AVTransport 订阅失败视为设备不支持事件，返回错误由调用方回退到轮询；
+    /// RenderingControl 失败只影响音量事件。
+    pub async fn start(
+        controller: DlnaController,
+        device: DlnaDevice,
+        callback_base: String,
+    ) -> Result<Self, crate::Error> {
+        let callback = |service: EventService| {
+            format!("{}/_dlna/event/{}", callback_base, service.path_segment())
+        };
+
+        let avt = controller
+            .subscribe_events(
+                &device,
+                EventService::AVTransport,
+                &callback(EventService::AVTransport),
+                SUBSCRIPTION_TIMEOUT_SECS,
+            )
+            .await?;
+
+        let mut subscriptions = vec![avt];
+        match controller
+            .subscribe_events(
+                &device,
+                EventService::RenderingControl,
+                &callback(EventService::RenderingControl),
+                SUBSCRIPTION_TIMEOUT_SECS,
+            )
+            .await
+        {
+            Ok(rc) => subscriptions.push(rc),
+            Err(e) => warn!("RenderingControl 事件订阅失败，音量变化将不会上报: {}", e),
+        }
+
+        let active = Arc::new(AtomicBool::new(true));
+        let sids = Arc::new(std::sync::Mutex::new(
+            subscriptions.iter().map(|s| s.sid.clone()).collect(),
+        ));
+        let active_task = active.clone();
+        let sids_task = sids.clone();
+        let renew_task = tokio::spawn(async move {
+            renew_loop(controller, device, callback_base, subscriptions, active_task, sids_task).await;
+        });
+
+        Ok(Self {
+            active,
+            sids,
+            renew_task,
+        })
+    }
+
+    /// 订阅是否仍然有效（续订彻底失败后变为 false，调用方应回退到轮询）
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+impl Subscription for EventSubscriber {
+    fn is_active(&self) -> bool {
+        EventSubscriber::is_active(self)
+    }
+
+    fn owns(&self, sid: &str) -> bool {
+        self.sids.lock().unwrap().iter().any(|s| s == sid)
+    }
+}
+
+impl Drop for EventSubscriber {
+    fn drop(&mut self) {
+        self.renew_task.abort();
+    }
+}
+
+async fn renew_loop(
+    controller: DlnaController,
+    device: DlnaDevice,
+    callback_base: String,
+    mut subscriptions: Vec<EventSubscription>,
+    active: Arc<AtomicBool>,
+    sids: Arc<std::sync::Mutex<Vec<String>>>,
+) {
+    loop {
+        let min_timeout = subscriptions
+            .iter()
+            .map(|s| s.timeout_secs)
+            .min()
+            .unwrap_or(SUBSCRIPTION_TIMEOUT_SECS);
+        let wait = min_timeout
+            .saturating_sub(RENEW_MARGIN_SECS)
+            .max(min_timeout / 2)
+            .max(1);
+        tokio::time::sleep(Duration::from_secs(wait as u64)).await;
+
+        for sub in subscriptions.iter_mut() {
+            if controller
+                .renew_subscription(sub, SUBSCRIPTION_TIMEOUT_SECS)
+                .await
+                .is_ok()
+            {
+                continue;
+            }
+
+            // 续订失败（常见为 412：设备重启后 SID 失效），尝试重新订阅
+            warn!("续订 {} 事件失败，尝试重新订阅", sub.service.path_segment());
+            let callback = format!("{}/_dlna/event/{}", callback_base, sub.service.path_segment());
+            match controller
+                .subscribe_events(&device, sub.service, &callback, SUBSCRIPTION_TIMEOUT_SECS)
+                .await
+            {
+                Ok(new_sub) => {
+                    let mut sids = sids.lock().unwrap();
+                    sids.retain(|s| *s != sub.sid);
+                    sids.push(new_sub.sid.clone());
+                    *sub = new_sub;
+                }
+                Err(e) => {
+                    if sub.service == EventService::AVTransport {
+                        warn!("重新订阅 AVTransport 事件失败，回退到轮询: {}", e);
+                        active.store(false, Ordering::SeqCst);
+                        return;
+                    }
+                    warn!("重新订阅 {} 事件失败: {}", sub.service.path_segment(), e);
+                }
+            }
+        }
+        info!("事件订阅已续订");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_avtransport_last_change() {
+        let body = r#"<?xml version="1.0"?>
+<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange>&lt;Event xmlns=&quot;urn:schemas-upnp-org:metadata-1-0/AVT/&quot;&gt;&lt;InstanceID val=&quot;0&quot;&gt;&lt;TransportState val=&quot;PLAYING&quot;/&gt;&lt;TransportStatus val=&quot;OK&quot;/&gt;&lt;CurrentTrackURI val=&quot;http://192.168.1.2:8080/BV1xx?a=1&amp;amp;b=2&quot;/&gt;&lt;/InstanceID&gt;&lt;/Event&gt;</LastChange></e:property></e:propertyset>"#;
+
+        let changes = parse_property_set(body);
+        assert!(changes.contains(&StateChange::TransportState(TransportState::Playing)));
+        assert!(changes.contains(&StateChange::TransportStatus("OK".to_string())));
+        assert!(changes.contains(&StateChange::CurrentTrackUri(
+            "http://192.168.1.2:8080/BV1xx?a=1&b=2".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_parse_rendering_control_last_change() {
+        let event = r#"<Event xmlns="urn:schemas-upnp-org:metadata-1-0/RCS/"><InstanceID val="0"><Volume channel="LF" val="10"/><Volume channel="Master" val="35"/><Mute channel="Master" val="0"/></InstanceID></Event>"#;
+
+        let changes = parse_last_change(event);
+        assert_eq!(changes, vec![StateChange::Volume(35), StateChange::Mute(false)]);
+    }
+
+    #[test]
+    fn test_parse_last_change_brands() {
+        // 三星：LastChange 带 e: 前缀的 propertyset，内层 Event 用 avt: 前缀
+        let samsung = r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange>&lt;avt:Event xmlns:avt=&quot;urn:schemas-upnp-org:metadata-1-0/AVT/&quot;&gt;&lt;avt:InstanceID val=&quot;0&quot;&gt;&lt;avt:TransportState val=&quot;STOPPED&quot;/&gt;&lt;avt:AVTransportURI val=&quot;http://192.168.1.2:8080/BV1xx?p=1&amp;amp;t=0&quot;/&gt;&lt;/avt:InstanceID&gt;&lt;/avt:Event&gt;</LastChange></e:property></e:propertyset>"#;
+        assert_eq!(
+            parse_property_set(samsung),
+            vec![
+                StateChange::TransportState(TransportState::Stopped),
+                StateChange::AvTransportUri("http://192.168.1.2:8080/BV1xx?p=1&t=0".to_string()),
+            ]
+        );
+
+        // 小米盒子：LastChange 放在 CDATA 里
+        let xiaomi = r#"<?xml version="1.0" encoding="UTF-8"?><e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange><![CDATA[<Event xmlns="urn:schemas-upnp-org:metadata-1-0/RCS/"><InstanceID val="0"><Volume val="42" channel="Master"/><Mute val="true" channel="Master"/></InstanceID></Event>]]></LastChange></e:property></e:propertyset>"#;
+        assert_eq!(
+            parse_property_set(xiaomi),
+            vec![StateChange::Volume(42), StateChange::Mute(true)]
+        );
+
+        // 海信：Event 未转义直接嵌入 LastChange；Volume 没写 channel
+        let hisense = r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange><Event xmlns="urn:schemas-upnp-org:metadata-1-0/RCS/"><InstanceID val="0"><Volume val="18"/></InstanceID></Event></LastChange></e:property></e:propertyset>"#;
+        assert_eq!(parse_property_set(hisense), vec![StateChange::Volume(18)]);
+
+        // 只转义了一层：解开后 URL 里的 & 是裸字符，退回按标签名查找
+        let single_escaped = r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><LastChange>&lt;Event&gt;&lt;InstanceID val="0"&gt;&lt;TransportState val="PLAYING"/&gt;&lt;CurrentTrackURI val="http://192.168.1.2:8080/BV1xx?a=1&amp;b=2"/&gt;&lt;/InstanceID&gt;&lt;/Event&gt;</LastChange></e:property></e:propertyset>"#;
+        assert_eq!(
+            parse_property_set(single_escaped),
+            vec![
+                StateChange::TransportState(TransportState::Playing),
+                StateChange::CurrentTrackUri("http://192.168.1.2:8080/BV1xx?a=1&b=2".to_string()),
+            ]
+        );
+
+        // 没有 LastChange 的 propertyset
+        assert!(parse_property_set(r#"<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property><SinkProtocolInfo>http-get:*:video/mp4:*</SinkProtocolInfo></e:property></e:propertyset>"#).is_empty());
+    }
+}
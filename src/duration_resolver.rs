@@ -0,0 +1,199 @@
+use crate::dlna_controller::TransportState;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// 解析 UPnP 时长/时间字符串。
+///
+/// 支持 `H+:MM:SS`、`H+:MM:SS.FFF`、`H+:MM:SS.F0/F1`（分数秒）、`MM:SS`、纯秒数，
+/// 以及可选的 `+`/`-` 前缀（负值按 0 处理）。
+/// `NOT_IMPLEMENTED`、空串或无法解析时返回 None。
+pub fn parse_upnp_duration(value: &str) -> Option<Duration> {
+    let trimmed = value.trim();
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("NOT_IMPLEMENTED") {
+        return None;
+    }
+
+    let (negative, body) = match trimmed.as_bytes()[0] {
+        b'-' => (true, &trimmed[1..]),
+        b'+' => (false, &trimmed[1..]),
+        _ => (false, trimmed),
+    };
+
+    // 先把小数部分拆出来：".500" 或 ".1/3"
+    let (clock, fraction) = match body.split_once('.') {
+        Some((clock, frac)) => (clock, Some(frac)),
+        None => (body, None),
+    };
+
+    let parts: Vec<&str> = clock.split(':').collect();
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    let mut nums = Vec::with_capacity(parts.len());
+    for p in &parts {
+        nums.push(p.parse::<u64>().ok()?);
+    }
+    let secs = match nums.as_slice() {
+        [h, m, s] if *m < 60 && *s < 60 => h * 3600 + m * 60 + s,
+        [m, s] if *s < 60 => m * 60 + s,
+        [s] => *s,
+        _ => return None,
+    };
+
+    let nanos = match fraction {
+        None => 0,
+        Some(frac) => parse_fraction_nanos(frac)?,
+    };
+
+    if negative {
+        return Some(Duration::ZERO);
+    }
+    Some(Duration::new(secs, nanos))
+}
+
+fn parse_fraction_nanos(frac: &str) -> Option<u32> {
+    if let Some((num, den)) = frac.split_once('/') {
+        // F0/F1 形式，要求 F0 < F1
+        let num: u64 = num.parse().ok()?;
+        let den: u64 = den.parse().ok()?;
+        if den == 0 || num >= den {
+            return None;
+        }
+        return Some((num * 1_000_000_000 / den) as u32);
+    }
+    if frac.is_empty() || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // 只保留纳秒精度
+    let digits = &frac[..frac.len().min(9)];
+    let scale = 10u32.pow(9 - digits.len() as u32);
+    Some(digits.parse::<u32>().ok()? * scale)
+}
+
+/// 时长信息的来源，按可信度从低到高排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DurationSource {
+    /// 渲染器 GetPositionInfo 的 TrackDuration，不少电视会返回 0 或固定的假值
+    Renderer,
+    /// 歌曲来源的元数据（如 bilibili 分P信息里的 duration，精确到秒）
+    SongSource,
+    /// mp4_util 从容器头（moov）解析出的时长
+    Mp4Probe,
+}
+
+impl DurationSource {
+    /// 给 Android 侧使用的整数编码
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Renderer => 1,
+            Self::SongSource => 2,
+            Self::Mp4Probe => 3,
+        }
+    }
+}
+
+/// 汇总同一首歌从不同来源得到的时长，按可信度取值。
+#[derive(Debug, Default)]
+pub struct DurationResolver {
+    entries: HashMap<String, SongDurations>,
+}
+
+#[derive(Debug, Default)]
+struct SongDurations {
+    candidates: HashMap<DurationSource, u32>,
+    probe_started: bool,
+}
+
+impl DurationResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录一个来源给出的时长（秒）；0 视为无效
+    pub fn report(&mut self, key: &str, source: DurationSource, secs: u32) {
+        if secs == 0 {
+            return;
+        }
+        self.entries
+            .entry(key.to_string())
+            .or_default()
+            .candidates
+            .insert(source, secs);
+    }
+
+    /// 标记开始探测 mp4 时长；已经探测过（或正在探测）时返回 false，防止并发重复下载
+    pub fn begin_probe(&mut self, key: &str) -> bool {
+        let entry = self.entries.entry(key.to_string()).or_default();
+        !std::mem::replace(&mut entry.probe_started, true)
+    }
+
+    /// 取可信度最高的时长。`renderer_secs` 是设备当前报告的 TrackDuration，
+    /// 只有在没有其他来源时才会采用。
+    pub fn resolve(&self, key: &str, renderer_secs: Option<u32>) -> Option<(u32, DurationSource)> {
+        let best = self.entries.get(key).and_then(|e| {
+            e.candidates
+                .iter()
+                .max_by_key(|(source, _)| **source)
+                .map(|(source, secs)| (*secs, *source))
+        });
+        best.or_else(|| {
+            renderer_secs
+                .filter(|s| *s > 0)
+                .map(|s| (s, DurationSource::Renderer))
+        })
+    }
+}
+
+/// CLI 与 Android 共用的播放进度
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaybackPosition {
+    pub position_secs: u32,
+    /// 未知时为 None（直播流、尚未探测完成等）
+    pub duration_secs: Option<u32>,
+    pub duration_source: Option<DurationSource>,
+    pub state: Option<TransportState>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_upnp_duration() {
+        assert_eq!(parse_upnp_duration("0:03:25"), Some(Duration::from_secs(205)));
+        assert_eq!(parse_upnp_duration("00:03:25"), Some(Duration::from_secs(205)));
+        assert_eq!(
+            parse_upnp_duration("1:02:03.500"),
+            Some(Duration::from_millis(3_723_500))
+        );
+        assert_eq!(
+            parse_upnp_duration("0:00:01.1/4"),
+            Some(Duration::from_millis(1_250))
+        );
+        assert_eq!(parse_upnp_duration("125:00:00"), Some(Duration::from_secs(450_000)));
+        assert_eq!(parse_upnp_duration("+0:00:10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_upnp_duration("-0:00:10"), Some(Duration::ZERO));
+        assert_eq!(parse_upnp_duration("03:25"), Some(Duration::from_secs(205)));
+        assert_eq!(parse_upnp_duration("NOT_IMPLEMENTED"), None);
+        assert_eq!(parse_upnp_duration(""), None);
+        assert_eq!(parse_upnp_duration("0:61:00"), None);
+        assert_eq!(parse_upnp_duration("abc"), None);
+    }
+
+    #[test]
+    fn test_resolver_prefers_higher_confidence() {
+        let mut resolver = DurationResolver::new();
+        assert_eq!(resolver.resolve("BV1", Some(36000)), Some((36000, DurationSource::Renderer)));
+
+        resolver.report("BV1", DurationSource::SongSource, 200);
+        assert_eq!(resolver.resolve("BV1", Some(36000)), Some((200, DurationSource::SongSource)));
+
+        resolver.report("BV1", DurationSource::Mp4Probe, 201);
+        resolver.report("BV1", DurationSource::Renderer, 0);
+        assert_eq!(resolver.resolve("BV1", None), Some((201, DurationSource::Mp4Probe)));
+
+        assert!(resolver.begin_probe("BV2"));
+        assert!(!resolver.begin_probe("BV2"));
+        assert_eq!(resolver.resolve("BV2", Some(0)), None);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+/// 库内统一的错误类型。
+///
+/// 按来源分类，方便 CLI / Android 决定是重试、重新搜索设备还是直接提示用户。
+#[derive(Debug)]
+pub enum Error {
+    /// 与设备之间的网络错误（连接失败、超时、读取响应失败）
+    Network(String),
+    /// 设备返回了非预期的 HTTP 状态码
+    HttpStatus { status: u16, context: String },
+    /// 设备返回 SOAP Fault，附带 UPnP errorCode / errorDescription
+    SoapFault {
+        action: String,
+        error_code: Option<u16>,
+        error_description: String,
+    },
+    /// 设备接受了 Seek 但多次确认后进度仍未变化（常见于缓冲中的电视）
+    SeekNotApplied { target_secs: u32, position_secs: u32 },
+    /// 浏览器播放器执行命令失败（自动播放被拦截、格式不支持等）
+    Player { action: String, message: String },
+    /// 设备没有提供所需的 UPnP 服务（如 AVTransport）
+    UnsupportedService(&'static str),
+    /// 设备不能播放该媒体格式
+    UnsupportedFormat {
+        content_type: String,
+        supported: Vec<String>,
+    },
+    /// 上游解析失败（bilibili API 等），code 为 API 返回的错误码
+    Upstream { code: Option<i64>, message: String },
+    /// ktv-song-web 歌单服务出错
+    PlaylistServer(String),
+    /// 引擎未初始化、锁异常等状态错误
+    EngineState(&'static str),
+    /// 本地媒体服务器无法监听端口（端口都被占用、没有权限等）
+    MediaServer { port: u16, message: String },
+    /// 响应、URL 等无法解析
+    Parse(String),
+    /// rupnp 的其他错误（SSDP、XML 等）
+    Upnp(rupnp::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// 网络抖动、设备忙等暂时性错误，稍后重试可能成功
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Network(_) | Self::PlaylistServer(_) => true,
+            Self::HttpStatus { status, .. } => *status >= 500 || *status == 408 || *status == 429,
+            // 501 Action Failed、715 Resource not found（设备还在准备资源）
+            Self::SoapFault { error_code, .. } => matches!(error_code, Some(501) | Some(715)),
+            Self::SeekNotApplied { .. } => true,
+            // -412 / -509：bilibili 的请求过快
+            Self::Upstream { code, .. } => code.is_none_or(|c| c == -412 || c == -509),
+            _ => false,
+        }
+    }
+
+    /// 设备可能已经离线或换了地址，需要重新搜索
+    pub fn needs_rediscovery(&self) -> bool {
+        match self {
+            Self::Network(_) => true,
+            Self::HttpStatus { status, .. } => *status == 404,
+            _ => false,
+        }
+    }
+
+    /// 给 Android 侧使用的分类编码，0 保留给“无错误”
+    pub fn kind_code(&self) -> i32 {
+        match self {
+            Self::Network(_) => 1,
+            Self::HttpStatus { .. } => 2,
+            Self::SoapFault { .. } => 3,
+            Self::UnsupportedService(_) => 4,
+            Self::UnsupportedFormat { .. } => 5,
+            Self::Upstream { .. } => 6,
+            Self::PlaylistServer(_) => 7,
+            Self::EngineState(_) => 8,
+            Self::Parse(_) => 9,
+            Self::Upnp(_) => 10,
+            Self::SeekNotApplied { .. } => 11,
+            Self::Player { .. } => 12,
+            Self::MediaServer { .. } => 13,
+        }
+    }
+
+    pub(crate) fn soap_fault(action: &str, error_code: Option<u16>, error_description: &str) -> Self {
+        Self::SoapFault {
+            action: action.to_string(),
+            error_code,
+            error_description: error_description.to_string(),
+        }
+    }
+
+    /// rupnp 的错误；action 为出错的 UPnP 动作（加载描述文件时为 "GetDescription"），
+    /// 设备返回的 SOAP Fault 据此报告是哪个动作失败
+    pub(crate) fn upnp(action: &str, e: rupnp::Error) -> Self {
+        match e {
+            rupnp::Error::UPnPError(fault) => {
+                Self::soap_fault(action, Some(fault.err_code()), fault.err_code_description())
+            }
+            rupnp::Error::NetworkError(_)
+            | rupnp::Error::NetworkClientError(_)
+            | rupnp::Error::IO(_) => Self::Network(e.to_string()),
+            rupnp::Error::HttpErrorCode(status) => Self::HttpStatus {
+                status: status.as_u16(),
+                context: format!("设备 {}", action),
+            },
+            rupnp::Error::InvalidUrl(_) | rupnp::Error::ParseError(_) => Self::Parse(e.to_string()),
+            e => Self::Upnp(e),
+        }
+    }
+
+    pub(crate) fn upstream(code: Option<i64>, message: impl Into<String>) -> Self {
+        Self::Upstream {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network(msg) => write!(f, "网络错误: {}", msg),
+            Self::HttpStatus { status, context } => write!(f, "{} 返回 HTTP {}", context, status),
+            Self::SoapFault {
+                action,
+                error_code,
+                error_description,
+            } => match error_code {
+                Some(code) => write!(f, "{} 失败: UPnP 错误 {} {}", action, code, error_description),
+                None => write!(f, "{} 失败: {}", action, error_description),
+            },
+            Self::SeekNotApplied {
+                target_secs,
+                position_secs,
+            } => write!(
+                f,
+                "设备没有跳转到 {} 秒（当前 {} 秒），可能仍在缓冲",
+                target_secs, position_secs
+            ),
+            Self::Player { action, message } => write!(f, "浏览器执行 {} 失败: {}", action, message),
+            Self::UnsupportedService(service) => write!(f, "设备不支持{}服务", service),
+            Self::UnsupportedFormat {
+                content_type,
+                supported,
+            } => write!(
+                f,
+                "设备不支持该媒体格式: {}（设备支持: {}）",
+                content_type,
+                supported.join(", ")
+            ),
+            Self::Upstream { code: Some(code), message } => {
+                write!(f, "解析媒体失败: {} (code={})", message, code)
+            }
+            Self::Upstream { code: None, message } => write!(f, "解析媒体失败: {}", message),
+            Self::PlaylistServer(msg) => write!(f, "歌单服务错误: {}", msg),
+            Self::EngineState(msg) => write!(f, "引擎状态错误: {}", msg),
+            Self::MediaServer { port, message } => write!(f, "媒体服务器无法监听端口 {}: {}", port, message),
+            Self::Parse(msg) => write!(f, "解析失败: {}", msg),
+            Self::Upnp(e) => write!(f, "UPnP 错误: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Upnp(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        match e.status() {
+            Some(status) => Self::HttpStatus {
+                status: status.as_u16(),
+                context: e.url().map(|u| u.to_string()).unwrap_or_default(),
+            },
+            None => Self::Network(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_classification() {
+        let fault = Error::soap_fault("Seek", Some(710), "Seek mode not supported");
+        assert_eq!(fault.to_string(), "Seek 失败: UPnP 错误 710 Seek mode not supported");
+        assert!(!fault.is_retryable());
+        assert_eq!(fault.kind_code(), 3);
+
+        let busy = Error::soap_fault("Play", Some(501), "Action Failed");
+        assert!(busy.is_retryable());
+
+        let gone = Error::Network("connection refused".to_string());
+        assert!(gone.needs_rediscovery());
+
+        let rate_limited = Error::upstream(Some(-412), "请求被拦截");
+        assert!(rate_limited.is_retryable());
+        assert!(!Error::upstream(Some(-404), "啥都木有").is_retryable());
+
+        let e = Error::upnp("Play", rupnp::Error::HttpErrorCode(rupnp::http::StatusCode::PRECONDITION_FAILED));
+        assert!(matches!(e, Error::HttpStatus { status: 412, .. }));
+        assert!(!Error::UnsupportedService("AVTransport").needs_rediscovery());
+    }
+}
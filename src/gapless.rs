@@ -0,0 +1,351 @@
+use crate::dlna_controller::{MediaMetadata, TransportState};
+use crate::error::{Error, Result};
+use crate::playback_monitor::PositionTracker;
+use crate::playlist_manager::PlaylistManager;
+use crate::renderer::{MediaLoad, Renderer};
+use crate::renderer_group::RendererGroup;
+use crate::media_server::UpstreamInfo;
+use crate::{EngineEvent, SharedState, media_server, volume_policy};
+use actix_web::web;
+use log::{debug, error, info, warn};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, broadcast, watch};
+
+// 新设备加入（或重连）后等待多久再跳转进度
+const JOIN_SEEK_DELAY: Duration = Duration::from_secs(2);
+
+/// 负责切歌：优先用 SetNextAVTransportURI 预加载队列中的下一首，让设备无缝切换；
+/// 设备不支持时回退到 Stop → SetAVTransportURI → Play（可按音量策略淡出/淡入）。
+/// 派对模式（多台设备）下各设备各自切换会不同步，因此只做硬切。
+pub struct GaplessPreloader {
+    group: Arc<RendererGroup>,
+    playlist_manager: PlaylistManager,
+    shared_state: web::Data<SharedState>,
+    client: reqwest::Client,
+    local_ip: IpAddr,
+    port: u16,
+    position: Arc<PositionTracker>,
+    // 设备拒绝过 SetNextAVTransportURI 后不再尝试
+    supported: AtomicBool,
+    // 同一时刻只允许一个切歌/预加载动作，避免 SetAVTransportURI 清掉刚设置的 NextURI
+    inner: Mutex<PreloadState>,
+}
+
+#[derive(Default)]
+struct PreloadState {
+    // 设备当前正在播放的歌曲（代理路径）
+    current: Option<String>,
+    // 已通过 SetNextAVTransportURI 交给设备的歌曲
+    preloaded: Option<String>,
+    // 恢复上次会话时要跳到的 (歌曲, 秒)，只对第一次投屏生效
+    resume_point: Option<(String, u32)>,
+}
+
+impl GaplessPreloader {
+    pub fn new(
+        group: Arc<RendererGroup>,
+        playlist_manager: PlaylistManager,
+        shared_state: web::Data<SharedState>,
+        local_ip: IpAddr,
+        port: u16,
+        position: Arc<PositionTracker>,
+    ) -> Self {
+        Self {
+            group,
+            playlist_manager,
+            shared_state,
+            client: reqwest::Client::new(),
+            local_ip,
+            port,
+            position,
+            supported: AtomicBool::new(true),
+            inner: Mutex::new(PreloadState::default()),
+        }
+    }
+
+    /// 歌单里正在演唱的歌曲变化时调用。设备已经自动切到这首时只补预加载，否则硬切。
+    pub async fn switch_to(&self, uri_path: &str, next: Option<String>) {
+        let mut state = self.inner.lock().await;
+        if state.current.as_deref() == Some(uri_path) {
+            info!("设备已无缝切换到 {}，跳过重新投屏", uri_path);
+        } else {
+            // 主动切歌产生的 STOPPED 不应被当作播放结束，标记覆盖淡出到 Play 返回的整个过程
+            self.position.begin_switch();
+            // SetAVTransportURI 会清掉设备上的 NextURI
+            state.preloaded = None;
+            let result = self.cast(self.group.devices(), uri_path).await;
+            self.position.end_switch();
+            if let Err(e) = result {
+                error!("投屏失败 {}: {}", uri_path, e);
+                return;
+            }
+            state.current = Some(uri_path.to_string());
+            let resume = state.resume_point.take();
+            if let Some((_, secs)) = resume.filter(|(song, _)| song == uri_path) {
+                info!("从上次的进度 {} 秒继续播放", secs);
+                self.seek_after_start(self.group.devices(), Some(secs)).await;
+            }
+        }
+        self.preload_locked(&mut state, next).await;
+    }
+
+    /// 新设备加入投屏组时，把当前歌曲投过去并跳到主设备的进度
+    pub async fn join(&self, device: Arc<dyn Renderer>, position_secs: Option<u32>) -> Result<()> {
+        let state = self.inner.lock().await;
+        let Some(current) = state.current.clone() else {
+            return Ok(());
+        };
+        self.cast(vec![device.clone()], &current).await?;
+        self.seek_after_start(vec![device], position_secs).await;
+        Ok(())
+    }
+
+    /// 设备重连后重新投当前歌曲并跳回断线时的进度。
+    /// 引擎还没投过歌时使用 fallback（歌单里正在演唱的歌曲）
+    pub async fn resume(
+        &self,
+        device: Arc<dyn Renderer>,
+        fallback: Option<String>,
+        is_primary: bool,
+        position_secs: Option<u32>,
+    ) -> Result<()> {
+        let mut state = self.inner.lock().await;
+        let Some(current) = state.current.clone().or(fallback) else {
+            return Ok(());
+        };
+        info!("在重连的设备 {} 上恢复播放 {}，进度 {:?}", device.name(), current, position_secs);
+        if is_primary {
+            // 重投产生的 STOPPED 不算播放结束；设备重启后 NextURI 也已丢失
+            self.position.begin_switch();
+            state.preloaded = None;
+        }
+        let result = self.cast(vec![device.clone()], &current).await;
+        if is_primary {
+            self.position.end_switch();
+        }
+        result?;
+        state.current = Some(current);
+        self.seek_after_start(vec![device], position_secs).await;
+        Ok(())
+    }
+
+    /// 恢复上次会话：该歌曲投屏后跳到 secs；若已经在播这首则立即跳转
+    pub async fn set_resume_point(&self, uri_path: &str, secs: u32) {
+        let mut state = self.inner.lock().await;
+        if state.current.as_deref() == Some(uri_path) {
+            self.seek_after_start(self.group.devices(), Some(secs)).await;
+        } else {
+            state.resume_point = Some((uri_path.to_string(), secs));
+        }
+    }
+
+    async fn seek_after_start(&self, devices: Vec<Arc<dyn Renderer>>, position_secs: Option<u32>) {
+        if let Some(secs) = position_secs.filter(|s| *s > 0) {
+            // 刚 Play 时设备多半还在 TRANSITIONING，稍等再 Seek
+            tokio::time::sleep(JOIN_SEEK_DELAY).await;
+            let _ = self
+                .group
+                .fan_out_to(devices, "Seek", |d| async move { d.seek(secs).await })
+                .await;
+        }
+    }
+
+    /// 当前投屏的歌曲（代理路径）
+    pub async fn current(&self) -> Option<String> {
+        self.inner.lock().await.current.clone()
+    }
+
+    // 对指定设备执行 Stop → 加载 → Play，各设备按自己支持的格式协商；
+    // 音量策略开启淡入淡出时，Stop 前淡出、Play 后淡入
+    async fn cast(&self, devices: Vec<Arc<dyn Renderer>>, uri_path: &str) -> Result<()> {
+        info!("通知设备准备拉取路径: {}", uri_path);
+        let playing = self.position.state() == Some(TransportState::Playing);
+        self.group
+            .fan_out_to(devices, "SetAVTransportURI", |d| async move {
+                let (protocol_info, upstream) = self.negotiate(&*d, uri_path).await?;
+                // 派对模式下设备可能在不同网卡上
+                let server_ip = d.local_addr().unwrap_or(self.local_ip);
+                let metadata = self.metadata(uri_path, upstream.as_ref(), server_ip).await;
+                let fade_to = volume_policy::fade_out(&*d, playing).await;
+                // 部分设备 Stop 后会退出投屏界面，可在设备配置中关闭
+                if d.quirks().stop_before_set.unwrap_or(true) {
+                    let _ = d.stop().await;
+                }
+                let result = d
+                    .load(MediaLoad {
+                        uri_path,
+                        metadata: &metadata,
+                        protocol_info: protocol_info.as_deref(),
+                        server_ip,
+                        server_port: self.port,
+                    })
+                    .await;
+                let result = match result {
+                    Ok(()) => d.play().await,
+                    Err(e) => Err(e),
+                };
+                // 投屏失败也要把音量恢复，否则设备停在静音
+                if let Some(volume) = fade_to {
+                    volume_policy::fade_in(&*d, volume).await;
+                }
+                result
+            })
+            .await
+    }
+
+    /// 队列中的下一首变化时调用
+    pub async fn preload(&self, next: Option<String>) {
+        let mut state = self.inner.lock().await;
+        self.preload_locked(&mut state, next).await;
+    }
+
+    /// 设备报告的当前曲目变化；若正是预加载的歌曲，说明设备已自动切换，返回 true
+    pub async fn on_track_changed(&self, track_uri: &str) -> bool {
+        let mut state = self.inner.lock().await;
+        let Some(preloaded) = state.preloaded.as_deref() else {
+            return false;
+        };
+        if !track_uri.ends_with(&format!("/{}", preloaded)) {
+            return false;
+        }
+        info!("设备已自动切换到预加载的歌曲: {}", preloaded);
+        self.position.restart();
+        state.current = state.preloaded.take();
+        // 预加载只发给了主设备；切换期间有设备加入时，把其他设备也切过来
+        if self.group.is_party()
+            && let Some(current) = state.current.clone()
+            && let Err(e) = self.cast(self.group.secondaries(), &current).await
+        {
+            warn!("同步其他设备失败: {}", e);
+        }
+        true
+    }
+
+    async fn preload_locked(&self, state: &mut PreloadState, next: Option<String>) {
+        if !self.supported.load(Ordering::SeqCst) || state.current.is_none() || self.group.is_party() {
+            return;
+        }
+        let Some(next) = next else {
+            return;
+        };
+        if state.current.as_ref() == Some(&next) || state.preloaded.as_ref() == Some(&next) {
+            return;
+        }
+        let primary = self.group.primary();
+        let Ok((protocol_info, upstream)) = self.negotiate(&*primary, &next).await else {
+            return;
+        };
+        let server_ip = primary.local_addr().unwrap_or(self.local_ip);
+        let metadata = self.metadata(&next, upstream.as_ref(), server_ip).await;
+
+        match primary
+            .load_next(MediaLoad {
+                uri_path: &next,
+                metadata: &metadata,
+                protocol_info: protocol_info.as_deref(),
+                server_ip,
+                server_port: self.port,
+            })
+            .await
+        {
+            Ok(()) => {
+                info!("已预加载下一首: {}", next);
+                state.preloaded = Some(next);
+            }
+            // 设备明确拒绝（非暂时性的 SOAP Fault / HTTP 错误、目标不支持）才放弃；
+            // 网络抖动、设备忙（5xx、UPnP 501/715 等）下次队列变化时再试
+            Err(e @ (Error::SoapFault { .. } | Error::HttpStatus { .. } | Error::UnsupportedService(_)))
+                if !e.is_retryable() =>
+            {
+                warn!("设备不支持 SetNextAVTransportURI，之后切歌改用 Stop/Play: {}", e);
+                self.supported.store(false, Ordering::SeqCst);
+                state.preloaded = None;
+            }
+            Err(e) => {
+                warn!("预加载下一首失败: {}", e);
+                state.preloaded = None;
+            }
+        }
+    }
+
+    // 探测上游格式并协商 protocolInfo；设备不能播放时返回 Error::UnsupportedFormat
+    async fn negotiate(&self, device: &dyn Renderer, uri_path: &str) -> Result<(Option<String>, Option<UpstreamInfo>)> {
+        match media_server::probe_upstream(&self.shared_state, &self.client, uri_path).await {
+            Ok(upstream) => device
+                .negotiate(&upstream.content_type)
+                .map(|p| (p, Some(upstream)))
+                .inspect_err(|e| error!("无法投屏 {}: {}", uri_path, e)),
+            Err(e) => {
+                warn!("{}，使用默认 protocolInfo", e);
+                Ok((None, None))
+            }
+        }
+    }
+
+    // 投屏元数据：歌单里的标题与点歌人、已知时长、上游文件大小和封面
+    async fn metadata(&self, uri_path: &str, upstream: Option<&UpstreamInfo>, server_ip: IpAddr) -> MediaMetadata {
+        let song = self.playlist_manager.song_info(uri_path).await;
+        // bilibili 分P时长在探测上游时已记录
+        let duration_secs = self
+            .shared_state
+            .duration_cache
+            .lock()
+            .await
+            .resolve(uri_path, None)
+            .map(|(secs, _)| secs);
+        MediaMetadata {
+            title: song.as_ref().map(|s| s.title.clone()),
+            artist: song.and_then(|s| s.added_by),
+            duration_secs,
+            size: upstream.and_then(|u| u.content_length),
+            album_art_url: media_server::cover_path(uri_path)
+                .map(|path| format!("{}/{}", media_server::base_url(server_ip, self.port), path)),
+        }
+    }
+}
+
+/// 监听下一首的变化与设备的曲目切换，设备自动切到预加载歌曲后通知 ktv-song-web 前进
+pub(crate) async fn run(
+    preloader: Arc<GaplessPreloader>,
+    playlist_manager: PlaylistManager,
+    mut song_next: watch::Receiver<Option<String>>,
+    mut engine_events: broadcast::Receiver<EngineEvent>,
+) {
+    loop {
+        tokio::select! {
+            changed = song_next.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let next = song_next.borrow_and_update().clone();
+                debug!("队列下一首变化: {:?}", next);
+                preloader.preload(next).await;
+            }
+            event = engine_events.recv() => {
+                match event {
+                    Ok(EngineEvent::TrackChanged(uri)) => {
+                        if preloader.on_track_changed(&uri).await {
+                            let mut pm = playlist_manager.clone();
+                            if let Err(e) = pm.next_song().await {
+                                warn!("通知歌单切到下一首失败: {}", e);
+                            }
+                        }
+                    }
+                    // 设备自然播完（PLAYING → STOPPED，非引擎主动切歌），CLI 与 Android 共用
+                    Ok(EngineEvent::TrackEnded) => {
+                        info!("歌曲播放结束，自动切换下一首");
+                        let mut pm = playlist_manager.clone();
+                        if let Err(e) = pm.next_song().await {
+                            warn!("自动切歌失败: {}", e);
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
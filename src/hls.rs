@@ -0,0 +1,259 @@
+use std::sync::RwLock;
+use url::Url;
+
+// 显式指定的码率选择方式，未设置时读取环境变量 KTV_HLS_VARIANT
+static VARIANT_POLICY: RwLock<Option<VariantPolicy>> = RwLock::new(None);
+
+/// 遇到主播放列表（多码率）时如何处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VariantPolicy {
+    /// 原样交给设备，由设备自己选码率（默认）
+    #[default]
+    Master,
+    /// 只给设备码率最高的媒体播放列表
+    Highest,
+    /// 只给设备码率最低的媒体播放列表
+    Lowest,
+    /// 不超过该码率（bit/s）中最高的一个，都超过时取最低的
+    MaxBandwidth(u64),
+}
+
+impl VariantPolicy {
+    /// "master" / "highest" / "lowest"，或码率上限（kbit/s，如 "3000"）
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "master" => Some(Self::Master),
+            "highest" => Some(Self::Highest),
+            "lowest" => Some(Self::Lowest),
+            v => v.parse::<u64>().ok().map(|kbps| Self::MaxBandwidth(kbps * 1000)),
+        }
+    }
+
+    /// 按策略选出一个码率；Master 或没有码率时返回 None
+    pub fn select<'a>(&self, variants: &'a [Variant]) -> Option<&'a Variant> {
+        let bandwidth = |v: &&Variant| v.bandwidth.unwrap_or(0);
+        match self {
+            Self::Master => None,
+            Self::Highest => variants.iter().max_by_key(bandwidth),
+            Self::Lowest => variants.iter().min_by_key(bandwidth),
+            Self::MaxBandwidth(cap) => variants
+                .iter()
+                .filter(|v| bandwidth(v) <= *cap)
+                .max_by_key(bandwidth)
+                .or_else(|| variants.iter().min_by_key(bandwidth)),
+        }
+    }
+}
+
+/// 指定码率选择方式，None 表示按环境变量决定
+pub fn set_variant_policy(policy: Option<VariantPolicy>) {
+    if let Ok(mut guard) = VARIANT_POLICY.write() {
+        *guard = policy;
+    }
+}
+
+/// 当前生效的码率选择方式：显式指定 > 环境变量 KTV_HLS_VARIANT > 交给设备
+pub fn variant_policy() -> VariantPolicy {
+    VARIANT_POLICY
+        .read()
+        .ok()
+        .and_then(|p| *p)
+        .or_else(|| std::env::var("KTV_HLS_VARIANT").ok().and_then(|v| VariantPolicy::parse(&v)))
+        .unwrap_or_default()
+}
+
+/// 主播放列表中的一个码率（#EXT-X-STREAM-INF 及其后的 URI）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub uri: String,
+    pub bandwidth: Option<u64>,
+}
+
+/// 按 Content-Type 或扩展名判断是否为 m3u8 播放列表
+pub fn is_playlist(content_type: Option<&str>, url: &str) -> bool {
+    let mime = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase());
+    matches!(
+        mime.as_deref(),
+        Some("application/vnd.apple.mpegurl" | "application/x-mpegurl" | "audio/mpegurl" | "audio/x-mpegurl")
+    ) || url.split(['?', '#']).next().is_some_and(|path| path.to_ascii_lowercase().ends_with(".m3u8"))
+}
+
+/// 是否为主播放列表（含多个码率）
+pub fn is_master(text: &str) -> bool {
+    text.lines().any(|line| line.trim_start().starts_with("#EXT-X-STREAM-INF"))
+}
+
+/// 主播放列表中的所有码率，按出现顺序
+pub fn variants(text: &str) -> Vec<Variant> {
+    let mut result = Vec::new();
+    let mut bandwidth: Option<Option<u64>> = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            bandwidth = Some(attribute(attrs, "BANDWIDTH").and_then(|b| b.parse().ok()));
+        } else if !line.is_empty() && !line.starts_with('#')
+            && let Some(bandwidth) = bandwidth.take()
+        {
+            result.push(Variant {
+                uri: line.to_string(),
+                bandwidth,
+            });
+        }
+    }
+    result
+}
+
+// 属性列表中某个属性的值（去掉引号）；BANDWIDTH 不能匹配到 AVERAGE-BANDWIDTH
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().next_back();
+        let after = &rest[pos + name.len()..];
+        if before.is_none_or(|c| c == ',' || c == ':') && let Some(value) = after.strip_prefix('=') {
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => &quoted[..quoted.find('"').unwrap_or(quoted.len())],
+                None => &value[..value.find(',').unwrap_or(value.len())],
+            };
+            return Some(value);
+        }
+        rest = after;
+    }
+    None
+}
+
+/// 把 uri（绝对或相对于 playlist_url）解析成上游的完整地址；
+/// inherit_query 为播放列表上的签名参数，只补给与播放列表同一主机且本身没有参数的地址，
+/// 不泄露给第三方的分片、密钥主机
+pub fn resolve(uri: &str, playlist_url: &Url, inherit_query: Option<&str>) -> Option<Url> {
+    let mut url = playlist_url.join(uri.trim()).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    url.set_fragment(None);
+    if url.query().is_none()
+        && url.host_str() == playlist_url.host_str()
+        && let Some(query) = inherit_query
+    {
+        url.set_query(Some(query));
+    }
+    Some(url)
+}
+
+// 本地代理上对应的地址：代理路径就是上游的完整地址
+fn proxied(uri: &str, playlist_url: &Url, proxy_base: &str, inherit_query: Option<&str>) -> Option<String> {
+    resolve(uri, playlist_url, inherit_query).map(|url| format!("{}/{}", proxy_base, url))
+}
+
+/// 改写播放列表：码率、分片、密钥（#EXT-X-KEY）、初始化分片（#EXT-X-MAP）等所有地址都指向本地代理。
+/// data:、skd: 等非 http 地址保持不变。
+pub fn rewrite(text: &str, playlist_url: &Url, proxy_base: &str, inherit_query: Option<&str>) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            out.push_str(line);
+        } else if trimmed.starts_with('#') {
+            out.push_str(&rewrite_tag(line, playlist_url, proxy_base, inherit_query));
+        } else {
+            match proxied(trimmed, playlist_url, proxy_base, inherit_query) {
+                Some(uri) => out.push_str(&uri),
+                None => out.push_str(line),
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// 改写标签中的 URI="..." 属性
+fn rewrite_tag(line: &str, playlist_url: &Url, proxy_base: &str, inherit_query: Option<&str>) -> String {
+    const ATTR: &str = "URI=\"";
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(pos) = rest.find(ATTR) {
+        let start = pos + ATTR.len();
+        let Some(len) = rest[start..].find('"') else {
+            break;
+        };
+        let is_attr = rest[..pos].ends_with([':', ',']);
+        out.push_str(&rest[..start]);
+        let uri = &rest[start..start + len];
+        match proxied(uri, playlist_url, proxy_base, inherit_query).filter(|_| is_attr) {
+            Some(proxied) => out.push_str(&proxied),
+            None => out.push_str(uri),
+        }
+        rest = &rest[start + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://192.168.1.5:8080";
+
+    #[test]
+    fn test_rewrite_playlists() {
+        let master = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"ja\",URI=\"audio/ja.m3u8\"\n\
+            #EXT-X-STREAM-INF:AVERAGE-BANDWIDTH=900000,BANDWIDTH=1280000,RESOLUTION=1280x720,AUDIO=\"aud\"\n\
+            720p/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=640000\n\
+            https://other.example.com/360p/index.m3u8?token=abc\n";
+        let playlist_url = Url::parse("https://cdn.example.com/live/master.m3u8?Policy=p&Signature=s").unwrap();
+        assert!(is_master(master));
+        let all = variants(master);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].bandwidth, Some(1_280_000));
+        assert_eq!(VariantPolicy::Highest.select(&all), Some(&all[0]));
+        assert_eq!(VariantPolicy::Lowest.select(&all), Some(&all[1]));
+        assert_eq!(VariantPolicy::parse("1000").unwrap().select(&all), Some(&all[1]));
+        assert_eq!(VariantPolicy::parse("100").unwrap().select(&all), Some(&all[1]));
+        assert_eq!(VariantPolicy::Master.select(&all), None);
+
+        let rewritten = rewrite(master, &playlist_url, BASE, playlist_url.query());
+        assert!(rewritten.contains(
+            "URI=\"http://192.168.1.5:8080/https://cdn.example.com/live/audio/ja.m3u8?Policy=p&Signature=s\""
+        ));
+        assert!(rewritten.contains(
+            "\nhttp://192.168.1.5:8080/https://cdn.example.com/live/720p/index.m3u8?Policy=p&Signature=s\n"
+        ));
+        // 分片自带的参数保留，不再补签名
+        assert!(rewritten.contains("\nhttp://192.168.1.5:8080/https://other.example.com/360p/index.m3u8?token=abc\n"));
+        // 其他主机上没有参数的地址也不补，签名只属于播放列表所在的主机
+        assert_eq!(
+            resolve("https://keys.example.net/k1", &playlist_url, playlist_url.query()).unwrap().as_str(),
+            "https://keys.example.net/k1"
+        );
+
+        let media = "#EXTM3U\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"/keys/k1?kid=1\",IV=0x01\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-SESSION-KEY:METHOD=SAMPLE-AES,KEYFORMAT=\"com.apple.streamingkeydelivery\",URI=\"skd://key\"\n\
+            #EXTINF:4.0,\n\
+            seg1.m4s\n\
+            #EXTINF:4.0,\n\
+            ../seg2.ts?sig=x\n\
+            #EXT-X-ENDLIST\n";
+        let playlist_url = Url::parse("https://cdn.example.com/vod/hd/index.m3u8").unwrap();
+        assert!(!is_master(media));
+        let rewritten = rewrite(media, &playlist_url, BASE, None);
+        let lines: Vec<&str> = rewritten.lines().collect();
+        assert_eq!(
+            lines[1],
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"http://192.168.1.5:8080/https://cdn.example.com/keys/k1?kid=1\",IV=0x01"
+        );
+        assert_eq!(lines[2], "#EXT-X-MAP:URI=\"http://192.168.1.5:8080/https://cdn.example.com/vod/hd/init.mp4\"");
+        assert!(lines[3].ends_with("URI=\"skd://key\""));
+        assert_eq!(lines[5], "http://192.168.1.5:8080/https://cdn.example.com/vod/hd/seg1.m4s");
+        assert_eq!(lines[7], "http://192.168.1.5:8080/https://cdn.example.com/vod/seg2.ts?sig=x");
+        assert_eq!(lines[8], "#EXT-X-ENDLIST");
+
+        assert!(is_playlist(Some("application/vnd.apple.mpegURL; charset=utf-8"), "https://a/b"));
+        assert!(is_playlist(None, "https://a/b/index.M3U8?x=1"));
+        assert!(!is_playlist(Some("video/mp2t"), "https://a/b/seg.ts"));
+    }
+}
@@ -0,0 +1,239 @@
+use log::{info, warn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::sync::RwLock;
+
+// 用户指定的搜索网卡（名称或 IP），未设置时读取环境变量 KTV_INTERFACE
+static PREFERRED: RwLock<Option<String>> = RwLock::new(None);
+
+/// 本机网卡上的一个地址（IPv4 或 IPv6），同一网卡可能有多个
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetInterface {
+    pub name: String,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    /// 网卡序号，IPv6 链路本地地址的 scope id 与组播网卡都用它
+    pub index: u32,
+}
+
+impl NetInterface {
+    /// ip 是否与该地址在同一网段（不同地址族时为 false）
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(addr) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(addr) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// 链路本地地址需要带上网卡的 scope id 才能收发
+    pub fn scope_id(&self) -> u32 {
+        match self.addr {
+            IpAddr::V6(v6) if is_link_local(v6) => self.index,
+            _ => 0,
+        }
+    }
+
+    /// 在该地址上绑定端口时使用的套接字地址（带 scope id）
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V4(v4) => SocketAddr::from((v4, port)),
+            IpAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, self.scope_id())),
+        }
+    }
+}
+
+impl std::fmt::Display for NetInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}/{})", self.name, self.addr, self.prefix_len)
+    }
+}
+
+/// fe80::/10
+pub fn is_link_local(ip: Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// 本机所有非回环网卡上的地址，IPv4 在前
+pub fn list() -> Vec<NetInterface> {
+    match if_addrs::get_if_addrs() {
+        Ok(ifaces) => {
+            let mut all: Vec<NetInterface> = ifaces
+                .into_iter()
+                .filter(|i| !i.is_loopback())
+                .map(|i| {
+                    let (addr, prefix_len) = match i.addr {
+                        if_addrs::IfAddr::V4(v4) => (IpAddr::V4(v4.ip), v4.prefixlen),
+                        if_addrs::IfAddr::V6(v6) => (IpAddr::V6(v6.ip), v6.prefixlen),
+                    };
+                    NetInterface {
+                        name: i.name,
+                        addr,
+                        prefix_len,
+                        index: i.index.unwrap_or(0),
+                    }
+                })
+                .collect();
+            all.sort_by_key(|i| i.addr.is_ipv6());
+            all
+        }
+        Err(e) => {
+            warn!("获取网卡列表失败: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// 指定只在某个网卡上搜索设备（名称如 "wlan0"，或该网卡的 IP），None 表示所有网卡
+pub fn set_preferred(interface: Option<String>) {
+    if let Ok(mut guard) = PREFERRED.write() {
+        *guard = interface.filter(|s| !s.trim().is_empty());
+    }
+}
+
+fn preferred() -> Option<String> {
+    PREFERRED
+        .read()
+        .ok()
+        .and_then(|p| p.clone())
+        .or_else(|| std::env::var("KTV_INTERFACE").ok())
+        .filter(|s| !s.trim().is_empty())
+}
+
+/// 用于 SSDP 搜索的网卡地址：指定了网卡时只用它，否则为所有网卡。
+/// IPv4 每个地址一项；IPv6 组播按链路发送，每张网卡只保留一个地址（优先链路本地地址）
+pub fn search_interfaces() -> Vec<NetInterface> {
+    dedup_ipv6(select(list(), preferred().as_deref()))
+}
+
+fn select(all: Vec<NetInterface>, preferred: Option<&str>) -> Vec<NetInterface> {
+    let Some(preferred) = preferred.map(str::trim) else {
+        return all;
+    };
+    // 按 IP 指定时只用这个地址，按名称指定时用该网卡的所有地址
+    let chosen: Vec<NetInterface> = all
+        .iter()
+        .filter(|i| i.name == preferred || i.addr.to_string() == preferred)
+        .cloned()
+        .collect();
+    if chosen.is_empty() {
+        warn!("找不到指定的网卡 {}，改为在所有网卡上搜索", preferred);
+        return all;
+    }
+    chosen
+}
+
+fn dedup_ipv6(all: Vec<NetInterface>) -> Vec<NetInterface> {
+    let mut result: Vec<NetInterface> = Vec::new();
+    for iface in all {
+        let IpAddr::V6(v6) = iface.addr else {
+            result.push(iface);
+            continue;
+        };
+        match result.iter_mut().find(|i| i.addr.is_ipv6() && i.index == iface.index) {
+            Some(existing) if is_link_local(v6) => *existing = iface,
+            Some(_) => {}
+            None => result.push(iface),
+        }
+    }
+    result
+}
+
+/// 访问 target 时使用的本机地址：同一地址族中优先同网段，其次按系统路由表，最后取前缀最长的。
+/// scope 为收到设备报文的网卡序号，给出时只在该网卡上选择（IPv6 链路本地地址必须给出）
+pub fn interface_for(target: IpAddr, scope: Option<u32>) -> Option<NetInterface> {
+    let all: Vec<NetInterface> = list()
+        .into_iter()
+        .filter(|i| i.addr.is_ipv4() == target.is_ipv4())
+        .filter(|i| scope.is_none_or(|s| i.index == s))
+        .collect();
+    if let Some(iface) = best_match(&all, target) {
+        return Some(iface.clone());
+    }
+    if let Some(source) = route_source(target, scope.unwrap_or(0))
+        && let Some(iface) = all.iter().find(|i| i.addr == source)
+    {
+        info!("{} 不在本机任何网段，按路由使用网卡 {}", target, iface);
+        return Some(iface.clone());
+    }
+    all.into_iter().max_by_key(|i| common_prefix(i.addr, target))
+}
+
+// 同网段的地址中选前缀最长的（链路本地目标自然落在 fe80::/64 上）
+fn best_match(all: &[NetInterface], target: IpAddr) -> Option<&NetInterface> {
+    all.iter().filter(|i| i.contains(target)).max_by_key(|i| i.prefix_len)
+}
+
+fn common_prefix(a: IpAddr, b: IpAddr) -> u32 {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u32::from(a) ^ u32::from(b)).leading_zeros(),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a) ^ u128::from(b)).leading_zeros(),
+        _ => 0,
+    }
+}
+
+// UDP connect 不发包，只让系统按路由表选出源地址
+fn route_source(target: IpAddr, scope: u32) -> Option<IpAddr> {
+    let (bind, target): (SocketAddr, SocketAddr) = match target {
+        IpAddr::V4(v4) => ((Ipv4Addr::UNSPECIFIED, 0).into(), (v4, 1900).into()),
+        IpAddr::V6(v6) => (
+            (Ipv6Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(SocketAddrV6::new(v6, 1900, 0, scope)),
+        ),
+    };
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(target).ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_unspecified())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iface(name: &str, addr: &str, prefix_len: u8, index: u32) -> NetInterface {
+        NetInterface {
+            name: name.to_string(),
+            addr: addr.parse().unwrap(),
+            prefix_len,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_interface_selection() {
+        let wlan = iface("wlan0", "192.168.1.23", 24, 2);
+        let docker = iface("docker0", "172.17.0.1", 16, 3);
+        assert!(wlan.contains("192.168.1.200".parse().unwrap()));
+        assert!(!wlan.contains("192.168.2.200".parse().unwrap()));
+        assert!(docker.contains("172.17.5.9".parse().unwrap()));
+        assert!(iface("tun0", "10.0.0.2", 0, 4).contains("8.8.8.8".parse().unwrap()));
+
+        let all = vec![wlan.clone(), docker.clone()];
+        assert_eq!(select(all.clone(), None), all);
+        assert_eq!(select(all.clone(), Some("docker0")), vec![docker]);
+        assert_eq!(select(all.clone(), Some("192.168.1.23")), vec![wlan.clone()]);
+        // 指定的网卡不存在时回退到全部
+        assert_eq!(select(all.clone(), Some("eth9")), all);
+
+        // IPv6：链路本地地址带 scope id，每张网卡搜索时只保留链路本地地址
+        let ula = iface("wlan0", "fd00:1::23", 64, 2);
+        let link_local = iface("wlan0", "fe80::1c2b:3aff:fe4d:5e6f", 64, 2);
+        assert!(ula.contains("fd00:1::c0de".parse().unwrap()));
+        assert!(!ula.contains("192.168.1.200".parse().unwrap()));
+        assert!(!wlan.contains("fd00:1::c0de".parse().unwrap()));
+        assert_eq!(ula.scope_id(), 0);
+        assert_eq!(link_local.socket_addr(0).to_string(), "[fe80::1c2b:3aff:fe4d:5e6f%2]:0");
+        let all = vec![wlan.clone(), ula.clone(), link_local.clone()];
+        assert_eq!(dedup_ipv6(all.clone()), vec![wlan.clone(), link_local.clone()]);
+
+        // 同网段时链路本地目标用链路本地地址，ULA 目标用 ULA 地址
+        assert_eq!(best_match(&all, "fe80::aa:bb".parse().unwrap()), Some(&link_local));
+        assert_eq!(best_match(&all, "fd00:1::c0de".parse().unwrap()), Some(&ula));
+        assert_eq!(best_match(&all, "192.168.1.200".parse().unwrap()), Some(&wlan));
+    }
+}
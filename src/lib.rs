@@ -1,63 +1,137 @@
-use crate::dlna_controller::{DlnaController, DlnaDevice};
+use crate::dlna_controller::{DlnaController, DlnaDevice, DlnaRenderer, TransportState};
+use crate::dlna_events::RendererEvent;
+use crate::duration_resolver::{DurationResolver, PlaybackPosition};
+pub use crate::error::{Error, Result};
+use crate::gapless::GaplessPreloader;
+use crate::media_server::UpstreamInfo;
+use crate::playback_monitor::PositionTracker;
 use crate::playlist_manager::PlaylistManager;
+use crate::renderer::Renderer;
+use crate::renderer_group::{RendererGroup, RendererStatus};
 use actix_web::{App, HttpServer, web};
 use log::{info, debug};
-use std::net::Ipv4Addr;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock}; // 改用 RwLock 以支持重置
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 
 #[cfg(target_os = "android")]
 pub mod android;
 
 pub mod bilibili_parser;
+pub mod browser_renderer;
+pub mod discovery;
 pub mod dlna_controller;
+pub mod dlna_events;
+pub mod duration_resolver;
+pub mod error;
+pub mod gapless;
+pub mod hls;
+pub mod interfaces;
+pub mod media_cache;
 pub mod media_server;
+#[cfg(test)]
+pub(crate) mod mock_renderer;
 pub mod mp4_util;
+pub mod playback_monitor;
 pub mod playlist_manager;
+pub mod quirks;
+pub mod reconnect;
+pub mod renderer;
+pub mod renderer_group;
+pub mod session;
+pub mod signed_url;
+pub mod volume_policy;
 
 // --- 全局静态容器：改为 RwLock<Option<...>> 以支持重新初始化 ---
 pub static ENGINE_STATE: RwLock<Option<Arc<EngineContext>>> = RwLock::new(None);
 
+// 持久化文件（设备配置等）所在目录，未设置时读取环境变量 KTV_DATA_DIR，再回退到当前目录
+static DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// 引擎向 CLI / Android 上报的事件（来自设备的 GENA 通知）
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    TransportStateChanged(TransportState),
+    /// 例如用电视遥控器调了音量
+    VolumeChanged(u32),
+    MuteChanged(bool),
+    TrackChanged(String),
+    /// 设备从 PLAYING 自然转为 STOPPED/NO_MEDIA_PRESENT（非引擎主动切歌）
+    TrackEnded,
+    /// 设备连续多次无响应，开始自动重连
+    RendererLost { friendly_name: String, location: String },
+    /// 设备重新连接（location 可能已变化），已恢复当前歌曲与进度
+    RendererReconnected { friendly_name: String, location: String },
+}
+
 pub struct EngineContext {
-    pub controller: DlnaController,
-    pub device: DlnaDevice,
+    /// 同时投屏的所有设备（首个为主设备，进度与事件订阅以它为准）；重连后设备地址会更新
+    pub renderers: Arc<RendererGroup>,
     pub playlist_manager: PlaylistManager,
-    pub duration_cache: Arc<Mutex<std::collections::HashMap<String, u32>>>,
+    pub duration_cache: Arc<Mutex<DurationResolver>>,
     pub local_ip: std::net::IpAddr,
     pub server_port: u16,
     pub is_playing: AtomicBool,
+    pub position: Arc<PositionTracker>,
+    pub gapless: Arc<GaplessPreloader>,
+    pub events: broadcast::Sender<EngineEvent>,
     pub rt: tokio::runtime::Runtime,
 }
 
 pub struct SharedState {
-    pub duration_cache: Arc<Mutex<std::collections::HashMap<String, u32>>>,
-    pub eplus_auth: Arc<tokio::sync::Mutex<Option<String>>>,
+    pub duration_cache: Arc<Mutex<DurationResolver>>,
+    /// 签名地址的鉴权，按媒体会话保存（见 signed_url）
+    pub auth_sessions: Mutex<signed_url::AuthSessions>,
+    /// 设备 NOTIFY 回调解析后的事件
+    pub renderer_events: broadcast::Sender<RendererEvent>,
+    /// 代理观察到的上游媒体格式，key 为代理路径
+    pub upstream_info: Mutex<HashMap<String, UpstreamInfo>>,
 }
 
 // --- 辅助工具函数 ---
-pub(crate) fn get_best_local_ip(target_device_ip: &str) -> String {
-    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
-    let target_u32 = target_device_ip.parse::<Ipv4Addr>().map(u32::from).ok();
-    if let Some(target) = target_u32 {
-        let best = interfaces
-            .iter()
-            .filter_map(|(name, ip)| {
-                if let std::net::IpAddr::V4(v4) = ip {
-                    let m_bits = (target ^ u32::from(*v4)).leading_zeros();
-                    Some((m_bits, ip.to_string(), name))
-                } else {
-                    None
-                }
-            })
-            .max_by_key(|(bits, _, _)| *bits);
-        if let Some((_, ip_str, _)) = best {
-            return ip_str;
-        }
+
+/// 持久化文件所在目录
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR.read().ok().and_then(|d| d.clone()) {
+        return dir;
+    }
+    std::env::var("KTV_DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("."))
+}
+
+/// 设置持久化目录（Android 传入应用的 files 目录），并重新加载设备配置
+pub fn set_data_dir(dir: impl Into<PathBuf>) {
+    let dir = dir.into();
+    info!("数据目录: {}", dir.display());
+    if let Ok(mut guard) = DATA_DIR.write() {
+        *guard = Some(dir.clone());
+    }
+    quirks::store().load_from(&dir);
+}
+
+/// 访问目标设备时使用的本机地址（IPv4 或 IPv6），目标未知时取默认网卡的地址
+pub(crate) fn get_best_local_ip(target_device_ip: &str) -> IpAddr {
+    if let Ok(target) = target_device_ip.trim_matches(['[', ']']).parse::<IpAddr>()
+        && let Some(iface) = interfaces::interface_for(target, None)
+    {
+        return iface.addr;
     }
     local_ip_address::local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "127.0.0.1".to_string())
+        .or_else(|_| local_ip_address::local_ipv6())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/// 取出当前引擎上下文；克隆 Arc 后立即释放读锁，避免锁跨越 await
+pub fn engine_context() -> Result<Arc<EngineContext>> {
+    ENGINE_STATE
+        .read()
+        .map_err(|_| Error::EngineState("全局状态锁异常"))?
+        .clone()
+        .ok_or(Error::EngineState("引擎未初始化"))
 }
 
 /// 重置引擎，释放资源
@@ -68,71 +142,88 @@ pub fn reset_engine() {
     }
 }
 
-/// 获取当前播放进度（秒）
+/// 订阅引擎事件；引擎未启动时返回 None
+pub fn subscribe_engine_events() -> Option<broadcast::Receiver<EngineEvent>> {
+    let guard = ENGINE_STATE.read().ok()?;
+    guard.as_ref().map(|ctx| ctx.events.subscribe())
+}
+
+/// 获取当前播放进度与时长（合并设备、mp4 探测与歌曲来源的时长）
+pub async fn get_playback_position() -> Option<PlaybackPosition> {
+    let ctx = ENGINE_STATE.read().ok()?.clone()?;
+    // 进度来自后台监听维护的本地时钟，不再每次都向设备发 SOAP
+    let (position_secs, renderer_total) = ctx.position.snapshot()?;
+    let playing = ctx.playlist_manager.get_song_playing().await;
+    let resolved = match &playing {
+        Some(key) => ctx.duration_cache.lock().await.resolve(key, renderer_total),
+        None => renderer_total.map(|s| (s, duration_resolver::DurationSource::Renderer)),
+    };
+    debug!(
+        "progress: curr={} renderer_total={:?} resolved={:?} playing={:?}",
+        position_secs, renderer_total, resolved, playing
+    );
+
+    Some(PlaybackPosition {
+        position_secs,
+        duration_secs: resolved.map(|(secs, _)| secs),
+        duration_source: resolved.map(|(_, source)| source),
+        state: ctx.position.state(),
+    })
+}
+
+/// 获取当前播放进度（秒），返回 (当前, 总时长)；总时长未知时为 0，引擎未就绪时为 (-1, -1)
 pub async fn get_current_progress() -> (i32, i32) {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            return match ctx.controller.get_secs(&ctx.device).await {
-                Ok((curr, total)) => {
-                    let cached_total = get_total_duration().await;
-                    let playing = ctx.playlist_manager.get_song_playing().await;
-                    debug!(
-                        "progress: curr={} total={} cached_total={} playing={:?}",
-                        curr,
-                        total,
-                        cached_total,
-                        playing
-                    );
-                    if cached_total > 0 && cached_total != total {
-                        debug!(
-                            "progress: override device total {} -> cached {}",
-                            total,
-                            cached_total
-                        );
-                        (curr as i32, cached_total as i32)
-                    } else {
-                        debug!(
-                            "progress: keep device total {} (cached_total={})",
-                            total,
-                            cached_total
-                        );
-                        (curr as i32, total as i32)
-                    }
-                    
-                }
-                Err(_) => (-1 , -1),
-            };
-        }
+    match get_playback_position().await {
+        Some(p) => (p.position_secs as i32, p.duration_secs.unwrap_or(0) as i32),
+        None => (-1, -1),
     }
-    (-1, -1)
 }
 
 /// 切换下一首歌曲
 pub fn trigger_next_song() {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            let ctx_task = Arc::clone(ctx);
-            ctx.rt.spawn(async move {
-                let mut pm = ctx_task.playlist_manager.clone();
-                let _ = pm.next_song().await;
-            });
-        }
+    if let Ok(ctx) = engine_context() {
+        let ctx_task = Arc::clone(&ctx);
+        ctx.rt.spawn(async move {
+            let mut pm = ctx_task.playlist_manager.clone();
+            if let Err(e) = pm.next_song().await {
+                log::warn!("切歌失败: {}", e);
+            }
+        });
     }
 }
 
-/// 跳转到指定秒数
-pub async fn jump_to_secs(target_secs: u32) -> Result<(), Box<dyn std::error::Error>> {
+/// 跳转到指定秒数（各设备确认进度后才返回）
+pub async fn jump_to_secs(target_secs: u32) -> Result<()> {
+    let ctx = engine_context()?;
+    ctx.renderers
+        .fan_out("Seek", |d| async move { d.seek(target_secs).await })
+        .await?;
+    // 不等下一次校准，立即更新本地时钟
+    if let Some((_, total)) = ctx.position.snapshot() {
+        ctx.position.sync(target_secs, total);
+    }
+    Ok(())
+}
 
-    let ctx = {
-        let guard = ENGINE_STATE.read().map_err(|_| "Lock error")?;
-        guard.as_ref().cloned().ok_or("Engine not initialized")?
+/// 相对当前进度跳转 delta_secs 秒（负数为后退），返回跳转的目标秒数
+pub async fn seek_relative_core(delta_secs: i32) -> Result<u32> {
+    let ctx = engine_context()?;
+    let (current, total) = match ctx.position.snapshot() {
+        Some(snapshot) => snapshot,
+        None => {
+            let track = ctx.renderers.primary().position().await?;
+            (track.position_secs, track.duration_secs)
+        }
     };
-
-    ctx.controller.seek(&ctx.device, target_secs)
-        .await
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-
-    Ok(())
+    let target = (current as i64 + delta_secs as i64).max(0) as u32;
+    // 不直接跳到结尾，避免立刻触发播放结束
+    let target = match total {
+        Some(total) if total > 1 => target.min(total - 1),
+        _ => target,
+    };
+    info!("相对跳转 {:+} 秒: {} → {}", delta_secs, current, target);
+    jump_to_secs(target).await?;
+    Ok(target)
 }
 
 /// 启动引擎核心逻辑
@@ -141,65 +232,93 @@ pub async fn start_engine_core(
     room_id: String,
     loc_str: String,
     rt: tokio::runtime::Runtime,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<()> {
     let _ = rustls::crypto::ring::default_provider().install_default();
     info!("开始初始化核心引擎: {}, Room: {}", loc_str, room_id);
 
     // A. 如果已有旧引擎，先清理掉
-    if let Ok(mut guard) = ENGINE_STATE.write() {
-        if guard.is_some() {
-            info!("检测到旧引擎正在运行，正在重置以连接新设备...");
-            *guard = None;
-            // 给系统时间释放端口
-            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
-        }
+    let had_engine = ENGINE_STATE
+        .write()
+        .map(|mut guard| guard.take().is_some())
+        .unwrap_or(false);
+    if had_engine {
+        info!("检测到旧引擎正在运行，正在重置以连接新设备...");
+        // 给系统时间释放端口
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
     }
 
     // B. 连接DLNA设备
     let handle = rt.handle().clone();
-    let (controller, device, local_ip_addr, port, cache, _shared_state) = connect_dlna_device(loc_str, handle).await?;
+    let (renderer, local_ip_addr, port, _cache, shared_state) = connect_dlna_device(loc_str, handle).await?;
+    volume_policy::apply_start_volume(&*renderer).await;
 
     // C. 连接房间
-    connect_room(base_url_str, room_id, controller, device, local_ip_addr, port, cache, rt).await?;
+    connect_room(base_url_str, room_id, renderer, local_ip_addr, port, shared_state.clone(), rt).await?;
+
+    // D. 监听设备状态（事件订阅，失败时轮询），设备掉线时自动重连
+    start_renderer_monitor(shared_state.renderer_events.subscribe());
+
+    // E. 预加载下一首，设备支持时无缝切歌
+    start_gapless_preloader();
+
+    // F. 预先下载排队中的歌曲到本地缓存
+    start_prefetcher(shared_state);
+
+    // G. 记录会话，进程重启后可恢复
+    start_session_recorder();
+
+    // H. 音量超过上限（安静时段、遥控器调大）时调回
+    start_volume_guard();
 
     info!("Rust Engine 已重新初始化，设备连接成功");
     Ok(())
 }
 
-/// 连接DLNA设备
+/// 连接DLNA设备，返回绑定到该设备的播放目标
 pub async fn connect_dlna_device(
     loc_str: String,
     handle: tokio::runtime::Handle,
-) -> Result<(DlnaController, DlnaDevice, std::net::IpAddr, u16, Arc<Mutex<std::collections::HashMap<String, u32>>>, web::Data<SharedState>), Box<dyn std::error::Error>> {
+) -> Result<(Arc<dyn Renderer>, std::net::IpAddr, u16, Arc<Mutex<DurationResolver>>, web::Data<SharedState>)> {
     let _ = rustls::crypto::ring::default_provider().install_default();
     info!("开始连接DLNA设备: {}", loc_str);
 
-    let controller = DlnaController::new();
-    let uri = loc_str.parse().expect("解析 URL 失败");
-    let device_obj = rupnp::Device::from_url(uri).await.expect("连接设备失败");
-
-    let device = DlnaDevice {
-        friendly_name: device_obj.friendly_name().to_string(),
-        location: loc_str.clone(),
-        device: device_obj,
-        services: vec![],
+    // 浏览器播放器要等媒体服务器启动后才能连上，DLNA 设备则先加载，失败时不启动服务器
+    let browser = browser_renderer::parse_location(&loc_str).is_some();
+    let dlna = if browser {
+        None
+    } else {
+        let controller = DlnaController::new();
+        let device = load_device(&controller, &loc_str).await?;
+        Some((controller, device))
     };
 
-    let target_ip = loc_str
-        .split('/')
-        .nth(2)
-        .and_then(|hp| hp.split(':').next())
-        .unwrap_or("127.0.0.1");
+    // IPv6 地址形如 http://[fd00::5]:49152/，host_str 带方括号
+    let target_ip = url::Url::parse(&loc_str)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_else(|| "127.0.0.1".to_string());
 
     info!("目标设备 IP 地址: {}", target_ip);
 
-    let cache = Arc::new(Mutex::new(std::collections::HashMap::new()));
+    let cache = Arc::new(Mutex::new(DurationResolver::new()));
 
+    let (renderer_events, _) = broadcast::channel(64);
     let shared_state = web::Data::new(SharedState {
         duration_cache: cache.clone(),
-        eplus_auth: Arc::new(tokio::sync::Mutex::new(None)),
+        auth_sessions: Mutex::new(signed_url::AuthSessions::default()),
+        renderer_events,
+        upstream_info: Mutex::new(HashMap::new()),
     });
-    let port = 8080u16;
+    // 先在当前线程监听端口，端口不可用时直接返回错误，而不是让服务器在后台悄悄退出
+    let listener = media_server::bind(media_server::configured_port())?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| Error::MediaServer {
+            port: 0,
+            message: e.to_string(),
+        })?
+        .port();
+    info!("媒体服务器端口: {}", port);
 
     // 启动 HttpServer (使用提供的 handle)
     let shared_state_clone = shared_state.clone();
@@ -209,62 +328,112 @@ pub async fn connect_dlna_device(
             App::new()
                 .app_data(web::Data::new(reqwest::Client::new()))
                 .app_data(shared_state_clone.clone())
+                .service(dlna_events::notify_handler)
+                .service(browser_renderer::player_page)
+                .service(browser_renderer::player_ws)
+                .service(media_server::cover_handler)
                 .service(media_server::proxy_handler)
         };
-        let _ = HttpServer::new(app_factory)
-            .workers(1)
-            .bind(("0.0.0.0", port))
-            .unwrap()
-            .run()
-            .await;
+        let server = match HttpServer::new(app_factory).workers(1).listen(listener) {
+            Ok(server) => server,
+            Err(e) => {
+                log::error!("媒体服务器启动失败: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = server.run().await {
+            log::error!("媒体服务器异常退出: {}", e);
+        }
     });
 
-    let local_ip_addr: std::net::IpAddr = get_best_local_ip(target_ip).parse().unwrap();
+    let Some((controller, device)) = dlna else {
+        let local_ip_addr = get_best_local_ip("");
+        info!("请在浏览器打开 {}/player", media_server::base_url(local_ip_addr, port));
+        let renderer = browser_renderer::connect(&loc_str, browser_renderer::CONNECT_TIMEOUT).await?;
+        return Ok((renderer, local_ip_addr, port, cache, shared_state));
+    };
+
+    // 媒体服务器地址使用发现设备的那张网卡，未知时才按 IP 前缀猜测
+    let local_ip_addr: IpAddr = match device.local_addr() {
+        Some(addr) => addr,
+        None => get_best_local_ip(&target_ip),
+    };
+    info!("媒体服务器地址: {} (网卡: {:?})", local_ip_addr, device.interface.as_ref().map(|i| &i.name));
 
-    Ok((controller, device, local_ip_addr, port, cache, shared_state))
+    let renderer: Arc<dyn Renderer> = Arc::new(DlnaRenderer::new(controller, device));
+    Ok((renderer, local_ip_addr, port, cache, shared_state))
+}
+
+/// 根据描述文件地址构建设备
+pub(crate) async fn load_device(controller: &DlnaController, loc_str: &str) -> Result<DlnaDevice> {
+    let uri = loc_str
+        .parse()
+        .map_err(|_| Error::Parse(format!("无法解析设备地址: {}", loc_str)))?;
+    let device_obj = rupnp::Device::from_url(uri).await.map_err(|e| Error::upnp("GetDescription", e))?;
+
+    // 优先使用发现服务记录的网卡，手动输入的地址按网段/路由推断
+    let interface = discovery::running()
+        .and_then(|s| s.interface_of(loc_str))
+        .or_else(|| discovery::interface_for_location(loc_str, None));
+    let mut device = DlnaDevice {
+        location: loc_str.to_string(),
+        ..DlnaDevice::from_rupnp(device_obj)
+    }
+    .with_interface(interface);
+    // 缓存设备支持的播放格式，投屏时据此协商 protocolInfo
+    controller.load_sink_protocols(&mut device).await;
+    Ok(device)
 }
 
-/// 连接房间
+/// 连接房间，renderer 为主设备
 pub async fn connect_room(
     base_url_str: String,
     room_id: String,
-    controller: DlnaController,
-    device: DlnaDevice,
+    renderer: Arc<dyn Renderer>,
     local_ip_addr: std::net::IpAddr,
     port: u16,
-    cache: Arc<Mutex<std::collections::HashMap<String, u32>>>,
+    shared_state: web::Data<SharedState>,
     rt: tokio::runtime::Runtime,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<()> {
     info!("开始连接房间: {}", room_id);
 
     let pm = PlaylistManager::new(&base_url_str, room_id);
+    let position = Arc::new(PositionTracker::new());
+    let renderers = Arc::new(RendererGroup::new(renderer));
+
+    let gapless = Arc::new(GaplessPreloader::new(
+        renderers.clone(),
+        pm.clone(),
+        shared_state.clone(),
+        local_ip_addr,
+        port,
+        position.clone(),
+    ));
 
     // 配置同步回调
-    let ctrl_sync = controller.clone();
-    let dev_sync = device.clone();
+    let gapless_sync = gapless.clone();
+    let song_next = pm.subscribe_song_next();
     pm.start_sync(move |video_url| {
-        let c = ctrl_sync.clone();
-        let d = dev_sync.clone();
-        let ip_obj = local_ip_addr;
-        let uri_path = video_url.clone();
+        let g = gapless_sync.clone();
+        let next = song_next.borrow().clone();
         Box::pin(async move {
-            info!("通知设备准备拉取路径: {}", uri_path);
-            let _ = c.stop(&d).await;
-            if let Ok(_) = c.set_avtransport_uri(&d, &uri_path, "", ip_obj, port).await {
-                let _ = c.play(&d).await;
-            }
+            g.switch_to(&video_url, next).await;
         })
     });
 
+    let (events, _) = broadcast::channel(64);
+
     // 打包存入全局状态
     let ctx = Arc::new(EngineContext {
-        controller,
-        device,
+        renderers,
         playlist_manager: pm,
-        duration_cache: cache,
+        duration_cache: shared_state.duration_cache.clone(),
         local_ip: local_ip_addr,
         server_port: port,
         is_playing: std::sync::atomic::AtomicBool::new(true),
+        position,
+        gapless,
+        events,
         rt,
     });
 
@@ -276,77 +445,255 @@ pub async fn connect_room(
     Ok(())
 }
 
+/// 在引擎 Runtime 上启动设备状态监听与掉线重连
+fn start_renderer_monitor(renderer_events: broadcast::Receiver<RendererEvent>) {
+    let Ok(guard) = ENGINE_STATE.read() else {
+        return;
+    };
+    let Some(ctx) = guard.as_ref() else {
+        return;
+    };
+    let callback_base = media_server::base_url(ctx.local_ip, ctx.server_port);
+    ctx.rt.spawn(playback_monitor::run(
+        ctx.renderers.clone(),
+        ctx.position.clone(),
+        callback_base,
+        renderer_events,
+        ctx.events.clone(),
+    ));
+    ctx.rt.spawn(reconnect::run(
+        ctx.renderers.clone(),
+        ctx.gapless.clone(),
+        ctx.position.clone(),
+        ctx.playlist_manager.clone(),
+        ctx.events.clone(),
+    ));
+}
+
+/// 在引擎 Runtime 上启动下一首预加载
+fn start_gapless_preloader() {
+    let Ok(guard) = ENGINE_STATE.read() else {
+        return;
+    };
+    let Some(ctx) = guard.as_ref() else {
+        return;
+    };
+    ctx.rt.spawn(gapless::run(
+        ctx.gapless.clone(),
+        ctx.playlist_manager.clone(),
+        ctx.playlist_manager.subscribe_song_next(),
+        ctx.events.subscribe(),
+    ));
+}
+
+/// 在引擎 Runtime 上跟随歌单预取即将播放的歌曲
+fn start_prefetcher(shared_state: web::Data<SharedState>) {
+    let Ok(guard) = ENGINE_STATE.read() else {
+        return;
+    };
+    let Some(ctx) = guard.as_ref() else {
+        return;
+    };
+    ctx.rt.spawn(media_cache::run(
+        ctx.playlist_manager.clone(),
+        shared_state,
+        ctx.playlist_manager.subscribe_queue(),
+    ));
+}
+
+/// 在引擎 Runtime 上记录当前会话（房间、设备、同步模式、音量与进度）
+fn start_session_recorder() {
+    let Ok(guard) = ENGINE_STATE.read() else {
+        return;
+    };
+    let Some(ctx) = guard.as_ref() else {
+        return;
+    };
+    let primary = ctx.renderers.primary();
+    // 换房间/设备时沿用上次的音量
+    let volume = session::load().and_then(|s| s.volume);
+    session::begin(session::Session {
+        base_url: ctx.playlist_manager.base_url().to_string(),
+        room_id: ctx.playlist_manager.room_id().to_string(),
+        device_udn: primary.udn().to_string(),
+        device_location: primary.id().to_string(),
+        device_name: primary.name().to_string(),
+        sync_mode: playlist_manager::sync_mode(),
+        volume,
+        song: None,
+        position_secs: 0,
+    });
+    ctx.rt.spawn(session::run(
+        ctx.renderers.clone(),
+        ctx.gapless.clone(),
+        ctx.position.clone(),
+        ctx.playlist_manager.clone(),
+        ctx.events.subscribe(),
+    ));
+}
+
+/// 在引擎 Runtime 上按音量策略限制各设备的最大音量
+fn start_volume_guard() {
+    let Ok(guard) = ENGINE_STATE.read() else {
+        return;
+    };
+    let Some(ctx) = guard.as_ref() else {
+        return;
+    };
+    ctx.rt.spawn(volume_policy::run(
+        ctx.renderers.clone(),
+        ctx.events.subscribe(),
+    ));
+}
+
+/// 恢复上次保存的会话：按 UDN 重新发现设备（地址可能已变化），连接房间，
+/// 恢复同步模式与音量，并在当前歌曲仍是上次那首时从保存的进度继续
+pub async fn resume_session_core(rt: tokio::runtime::Runtime) -> Result<session::Session> {
+    let saved = session::load().ok_or(Error::EngineState("没有可恢复的会话"))?;
+    info!("恢复上次的会话: 房间 {}，设备 {}", saved.room_id, saved.device_name);
+
+    // 浏览器播放器用同一个 id 重新打开播放页即可，不需要重新发现
+    let location = if browser_renderer::parse_location(&saved.device_location).is_some() {
+        saved.device_location.clone()
+    } else {
+        reconnect::rediscover(&saved.device_udn, &saved.device_name, &saved.device_location)
+            .await
+            .unwrap_or_else(|| saved.device_location.clone())
+    };
+    playlist_manager::set_sync_mode(Some(saved.sync_mode));
+    start_engine_core(saved.base_url.clone(), saved.room_id.clone(), location, rt).await?;
+
+    let ctx = engine_context()?;
+    if let Some(song) = &saved.song {
+        ctx.gapless.set_resume_point(song, saved.position_secs).await;
+    }
+    if let Some(volume) = saved.volume
+        && let Err(e) = set_volume_core(volume).await
+    {
+        log::warn!("恢复音量失败: {}", e);
+    }
+    Ok(saved)
+}
+
 // 获取当前歌曲总时长
 pub async fn get_total_duration() -> u32 {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            if let Some(playing) = ctx.playlist_manager.get_song_playing().await {
-                if let Some(&d) = ctx.duration_cache.lock().await.get(&playing) {
-                    return d;
-                }
-            }
-        }
-    }
-    0
+    get_playback_position()
+        .await
+        .and_then(|p| p.duration_secs)
+        .unwrap_or(0)
 }
 
 // 切换播放/暂停状态
-pub async fn toggle_pause_core() -> Result<bool, Box<dyn std::error::Error>> {
-    let (target_state, _) = {
-        let guard = ENGINE_STATE.read().map_err(|_| "Lock error")?;
-        let ctx = guard.as_ref().ok_or("Engine not initialized")?;
-        let curr = ctx.is_playing.load(Ordering::SeqCst);
-        (!curr, curr)
-    };
+pub async fn toggle_pause_core() -> Result<bool> {
+    let ctx = engine_context()?;
+    let target_state = !ctx.is_playing.load(Ordering::SeqCst);
 
     // 执行 DLNA 操作
-    {
-        let guard = ENGINE_STATE.read().map_err(|_| "Lock error")?;
-        let ctx = guard.as_ref().unwrap();
-        if target_state {
-            ctx.controller.play(&ctx.device).await?;
-        } else {
-            ctx.controller.pause(&ctx.device).await?;
-        }
-        ctx.is_playing.store(target_state, Ordering::SeqCst);
+    if target_state {
+        ctx.renderers.fan_out("Play", |d| async move { d.play().await }).await?;
+    } else {
+        ctx.renderers.fan_out("Pause", |d| async move { d.pause().await }).await?;
     }
+    ctx.is_playing.store(target_state, Ordering::SeqCst);
 
     Ok(target_state)
 }
 
-// 设置音量
-pub async fn set_volume_core(volume: u32) -> Result<u32, Box<dyn std::error::Error>> {
-    let guard = ENGINE_STATE.read().map_err(|_| "Lock error")?;
-    let ctx = guard.as_ref().ok_or("Engine not initialized")?;
+// 设置音量，各设备不超过音量策略的上限；返回主设备实际设置的音量
+pub async fn set_volume_core(volume: u32) -> Result<u32> {
+    let ctx = engine_context()?;
     let target = volume.clamp(0, 100);
-    ctx.controller.set_volume(&ctx.device, target).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-    Ok(target)
+    ctx.renderers
+        .fan_out("SetVolume", |d| async move {
+            let volume = target.min(volume_policy::cap_for(&*d));
+            d.set_volume(volume).await
+        })
+        .await?;
+    session::update(|s| s.volume = Some(target));
+    Ok(target.min(volume_policy::cap_for(&*ctx.renderers.primary())))
 }
 
 // 获取音量
-pub async fn get_volume_core() -> Result<u32, Box<dyn std::error::Error>> {
-    let guard = ENGINE_STATE.read().map_err(|_| "Lock error")?;
-    let ctx = guard.as_ref().ok_or("Engine not initialized")?;
-    let v = ctx.controller.get_volume(&ctx.device).await.map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-    Ok(v)
+pub async fn get_volume_core() -> Result<u32> {
+    let ctx = engine_context()?;
+    ctx.renderers.primary().get_volume().await
+}
+
+/// 派对模式：把设备加入当前投屏组，并同步当前歌曲与进度
+pub async fn add_renderer_core(loc_str: String) -> Result<()> {
+    let ctx = engine_context()?;
+    let device: Arc<dyn Renderer> = if browser_renderer::parse_location(&loc_str).is_some() {
+        browser_renderer::connect(&loc_str, std::time::Duration::from_secs(5)).await?
+    } else {
+        let controller = DlnaController::new();
+        let device = load_device(&controller, &loc_str).await?;
+        Arc::new(DlnaRenderer::new(controller, device))
+    };
+    if !ctx.renderers.add(device.clone()) {
+        info!("设备已在投屏组中: {}", device.name());
+        return Ok(());
+    }
+    let position = ctx.position.snapshot().map(|(secs, _)| secs);
+    ctx.gapless.join(device, position).await
+}
+
+/// 媒体服务器实际使用的地址（端口可能因占用而改变），引擎未启动时为 None
+pub fn media_server_url() -> Option<String> {
+    engine_context()
+        .ok()
+        .map(|ctx| media_server::base_url(ctx.local_ip, ctx.server_port))
+}
+
+/// 本地媒体缓存的使用情况（已缓存与正在下载的歌曲），引擎未启动时也可查询
+pub fn media_cache_status() -> media_cache::CacheStatus {
+    media_cache::cache().status()
 }
 
-// 搜索设备
+/// 已打开播放页的浏览器，元素为 (名称, 地址)，地址可用于 start_engine_core / add_renderer_core
+pub fn browser_players() -> Vec<(String, String)> {
+    browser_renderer::hub().players()
+}
+
+/// 把设备移出投屏组并停止它的播放
+pub async fn remove_renderer_core(loc_str: String) -> Result<()> {
+    let ctx = engine_context()?;
+    let device = ctx.renderers.remove(&loc_str)?;
+    if let Err(e) = device.stop().await {
+        log::warn!("停止被移除的设备失败: {}", e);
+    }
+    Ok(())
+}
+
+/// 投屏组内各设备的状态，引擎未就绪时为空
+pub fn renderer_status() -> Vec<RendererStatus> {
+    engine_context()
+        .map(|ctx| ctx.renderers.status())
+        .unwrap_or_default()
+}
+
+// 搜索设备：返回后台发现服务的设备列表，首个设备出现后稍等片刻即返回，不必等满超时
 pub async fn discover_devices_core() -> Vec<DlnaDevice> {
-    DlnaController::new()
-        .discover_devices()
+    let service = discovery::service();
+    service.search_now();
+    service
+        .wait_for_devices(std::time::Duration::from_secs(5), std::time::Duration::from_millis(800))
         .await
-        .unwrap_or_default()
+}
+
+/// 按 IP / 主机名（或描述文件 URL）手动添加设备，返回的设备可直接用于 start_engine_core
+pub async fn add_device_by_host_core(host: String) -> Result<DlnaDevice> {
+    discovery::probe_host(&host).await
 }
 
 /// 获取当前正在播放的歌曲标题
 pub async fn get_current_song_title_core() -> String {
-    if let Ok(guard) = ENGINE_STATE.read() {
-        if let Some(ctx) = guard.as_ref() {
-            // 调用 PlaylistManager 中我们之前添加的 get_song_title
-            return ctx.playlist_manager.get_song_title().await
-                .unwrap_or_else(|| "暂无歌曲".to_string());
-        }
+    match engine_context() {
+        // 调用 PlaylistManager 中我们之前添加的 get_song_title
+        Ok(ctx) => ctx
+            .playlist_manager
+            .get_song_title()
+            .await
+            .unwrap_or_else(|| "暂无歌曲".to_string()),
+        Err(_) => "未连接".to_string(),
     }
-    "未连接".to_string()
 }
\ No newline at end of file
@@ -3,14 +3,24 @@ use anyhow::{Context, Result, bail};
 use crossterm::event::{self};
 use crossterm::terminal;
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
-use ktv_casting_lib::dlna_controller::{DlnaController, DlnaDevice};
-use ktv_casting_lib::{ENGINE_STATE, start_engine_core, toggle_pause_core, trigger_next_song};
+use ktv_casting_lib::browser_renderer;
+use ktv_casting_lib::discovery::{self, DiscoveryEvent};
+use ktv_casting_lib::dlna_controller::DlnaDevice;
+use ktv_casting_lib::{interfaces, session};
+use ktv_casting_lib::{
+    ENGINE_STATE, EngineEvent, Error, add_device_by_host_core, add_renderer_core, browser_players, get_playback_position, media_cache_status, media_server_url, resume_session_core, seek_relative_core, start_engine_core, subscribe_engine_events,
+    toggle_pause_core, trigger_next_song,
+};
 use log::{Log, Metadata, Record, info};
 use std::fmt::Write;
 use std::io;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use url::Url;
 
+// 方向键左右每次跳转的秒数
+const SEEK_STEP_SECS: i32 = 10;
+
 struct ProgressLogger {
     inner: Box<dyn Log>,
     pb: ProgressBar,
@@ -36,21 +46,40 @@ impl Log for ProgressLogger {
 async fn main() -> Result<()> {
     let pb = setup_env();
 
-    // 1. 交互式获取配置
-    let (base_url, room_id) = get_room_config_interactively()?;
-    let controller = DlnaController::new();
-    let device = select_dlna_device_interactively(&controller).await?;
+    // 0. 有上次的会话时可以直接恢复
+    if let Some(saved) = session::load()
+        && ask_resume_session(&saved)?
+    {
+        let engine_rt = tokio::runtime::Runtime::new().context("Failed to create engine runtime")?;
+        resume_session_core(engine_rt).await.context("恢复会话失败")?;
+    } else {
+        // 1. 交互式获取配置
+        let (base_url, room_id) = get_room_config_interactively()?;
+        let mut devices = select_dlna_devices_interactively().await?;
+        let (_, location) = devices.remove(0);
+
+        // 2. 准备 Runtime 传给引擎
+        let engine_rt = tokio::runtime::Runtime::new().context("Failed to create engine runtime")?;
 
-    // 2. 准备 Runtime 传给引擎
-    let engine_rt = tokio::runtime::Runtime::new().context("Failed to create engine runtime")?;
+        // 3. 启动引擎逻辑 (调用 lib.rs 中的异步函数)
+        start_engine_core(base_url, room_id, location, engine_rt)
+            .await
+            .context("启动引擎失败")?;
 
-    // 3. 启动引擎逻辑 (调用 lib.rs 中的异步函数)
-    start_engine_core(base_url, room_id, device.location.clone(), engine_rt).await.expect("Failed to start engine");
+        // 选了多个设备时进入派对模式，第一个为主设备
+        for (name, location) in devices {
+            match add_renderer_core(location).await {
+                Ok(()) => info!("已加入投屏组: {}", name),
+                Err(e) => report_error(&format!("加入设备 {}", name), &e),
+            }
+        }
+    }
 
     pb.set_draw_target(ProgressDrawTarget::stdout());
 
     // 4. 键盘监听处理
     spawn_keyboard_handler();
+    spawn_event_logger();
 
     // 5. 调用封装好的监控函数，传入回调更新进度条
     let pb_for_len = pb.clone();
@@ -67,22 +96,105 @@ async fn main() -> Result<()> {
 }
 
 // --- 辅助逻辑函数 ---
-async fn select_dlna_device_interactively(controller: &DlnaController) -> Result<DlnaDevice> {
-    let devices = controller.discover_devices().await.unwrap_or_default();
-    if devices.is_empty() {
-        bail!("未发现任何 DLNA 设备");
+fn ask_resume_session(saved: &session::Session) -> Result<bool> {
+    println!(
+        "上次的会话：房间 {}（{}），设备 {}",
+        saved.room_id, saved.base_url, saved.device_name
+    );
+    println!("直接回车恢复，输入 n 重新选择房间和设备:");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(!input.trim().eq_ignore_ascii_case("n"))
+}
+
+// 返回选中设备的 (名称, 地址)，第一个为主设备
+async fn select_dlna_devices_interactively() -> Result<Vec<(String, String)>> {
+    // 设备陆续出现时即时打印，编号按出现顺序固定，不必等搜索超时
+    let service = discovery::service();
+    let mut events = service.subscribe();
+    let found: Arc<Mutex<Vec<DlnaDevice>>> = Arc::new(Mutex::new(Vec::new()));
+
+    let ifaces = interfaces::search_interfaces();
+    if ifaces.len() > 1 {
+        let names: Vec<String> = ifaces.iter().map(|i| i.to_string()).collect();
+        println!("在以下网卡上搜索（可用 KTV_INTERFACE 指定）：{}", names.join(", "));
     }
+    println!("正在搜索设备，发现后会陆续列出：");
+    for device in service.devices() {
+        print_found_device(&found, device);
+    }
+    let found_printer = found.clone();
+    let printer = tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(DiscoveryEvent::Added(device)) => print_found_device(&found_printer, *device),
+                Ok(DiscoveryEvent::Removed { friendly_name, location, .. }) => {
+                    println!("设备已离线: {} at {}", friendly_name, location);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+    });
+    service.search_now();
 
-    println!("发现以下设备：");
-    for (i, d) in devices.iter().enumerate() {
-        println!("{}: {} at {}", i, d.friendly_name, d.location);
+    let input = tokio::task::spawn_blocking(|| {
+        println!("输入设备编号（多个用逗号分隔，同时投屏），搜不到的设备可直接输入 IP，回车确认：");
+        println!("输入 b 投屏到浏览器（启动后按提示在浏览器打开播放页）");
+        let mut input = String::new();
+        io::stdin().read_line(&mut input).map(|_| input)
+    })
+    .await??;
+    printer.abort();
+
+    let devices = found.lock().map_err(|_| anyhow::anyhow!("设备列表锁异常"))?.clone();
+    let online: Vec<String> = service.devices().into_iter().map(|d| d.location).collect();
+    let mut selected = Vec::new();
+    for part in input.split([',', '，']).map(str::trim).filter(|s| !s.is_empty()) {
+        if part.eq_ignore_ascii_case("b") {
+            let browser = ("浏览器播放器".to_string(), browser_renderer::LOCATION_PREFIX.to_string());
+            if !selected.contains(&browser) {
+                selected.push(browser);
+            }
+            continue;
+        }
+        let device = match part.parse::<usize>() {
+            Ok(idx) => {
+                let device = devices.get(idx).cloned().with_context(|| format!("编号无效: {}", idx))?;
+                if !online.contains(&device.location) {
+                    bail!("设备已离线: {}", device.friendly_name);
+                }
+                device
+            }
+            // 不是编号时按 IP / 主机名手动添加
+            Err(_) => {
+                println!("正在探测 {} ...", part);
+                let device = add_device_by_host_core(part.to_string())
+                    .await
+                    .with_context(|| format!("无法添加设备 {}", part))?;
+                println!("已找到设备: {} at {}", device.friendly_name, device.location);
+                device
+            }
+        };
+        if !selected.iter().any(|(_, location)| *location == device.location) {
+            selected.push((device.friendly_name, device.location));
+        }
     }
-    print!("输入设备编号：");
-    io::Write::flush(&mut io::stdout())?; // 确保提示文字先打印
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let idx: usize = input.trim().parse()?;
-    devices.get(idx).cloned().context("编号无效")
+    if selected.is_empty() {
+        bail!("未选择设备");
+    }
+    Ok(selected)
+}
+
+fn print_found_device(found: &Mutex<Vec<DlnaDevice>>, device: DlnaDevice) {
+    let Ok(mut found) = found.lock() else {
+        return;
+    };
+    if found.iter().any(|d| d.location == device.location) {
+        return;
+    }
+    println!("{}: {} at {}", found.len(), device.friendly_name, device.location);
+    found.push(device);
 }
 
 fn spawn_keyboard_handler() {
@@ -104,8 +216,8 @@ fn spawn_keyboard_handler() {
 
                 rt.block_on(async {
                     match key.code {
-                        event::KeyCode::Char('p') => {
-                            if let Ok(state) = toggle_pause_core().await {
+                        event::KeyCode::Char('p') => match toggle_pause_core().await {
+                            Ok(state) => {
                                 info!(
                                     "{}",
                                     if state {
@@ -115,11 +227,56 @@ fn spawn_keyboard_handler() {
                                     }
                                 );
                             }
+                            Err(e) => report_error("播放/暂停", &e),
+                        },
+                        // 把已打开播放页的浏览器加入投屏组
+                        event::KeyCode::Char('b') => {
+                            let players = browser_players();
+                            if players.is_empty() {
+                                info!(
+                                    "没有已连接的浏览器，请先在浏览器打开 {}/player",
+                                    media_server_url().unwrap_or_default()
+                                );
+                            }
+                            for (name, location) in players {
+                                match add_renderer_core(location).await {
+                                    Ok(()) => info!("已加入投屏组: {}", name),
+                                    Err(e) => report_error(&format!("加入浏览器 {}", name), &e),
+                                }
+                            }
+                        }
+                        // 查看本地缓存：已缓存与正在预取的歌曲
+                        event::KeyCode::Char('c') => {
+                            let status = media_cache_status();
+                            info!(
+                                "缓存 {} / {} MB，共 {} 首",
+                                status.used_bytes / 1024 / 1024,
+                                status.max_bytes / 1024 / 1024,
+                                status.songs.len()
+                            );
+                            for song in status.songs.iter().filter(|s| !s.complete) {
+                                info!(
+                                    "  正在下载 {}: {}%",
+                                    song.key,
+                                    song.downloaded * 100 / song.total.max(1)
+                                );
+                            }
                         }
                         event::KeyCode::Char('n') => {
                             trigger_next_song();
                             info!("⏭ 切歌");
                         }
+                        event::KeyCode::Left | event::KeyCode::Right => {
+                            let delta = if key.code == event::KeyCode::Left {
+                                -SEEK_STEP_SECS
+                            } else {
+                                SEEK_STEP_SECS
+                            };
+                            match seek_relative_core(delta).await {
+                                Ok(target) => info!("{} 跳转到 {} 秒", if delta < 0 { "⏪" } else { "⏩" }, target),
+                                Err(e) => report_error("跳转", &e),
+                            }
+                        }
                         _ => {}
                     }
                 });
@@ -128,6 +285,44 @@ fn spawn_keyboard_handler() {
     });
 }
 
+// 按错误类别给出下一步提示
+fn report_error(what: &str, e: &Error) {
+    if e.needs_rediscovery() {
+        log::error!("{}失败: {}；设备可能已离线，请检查网络后重新选择设备", what, e);
+    } else if e.is_retryable() {
+        log::warn!("{}失败: {}；请稍后重试", what, e);
+    } else {
+        log::error!("{}失败: {}", what, e);
+    }
+}
+
+// 打印设备主动上报的状态变化（例如遥控器调音量）
+fn spawn_event_logger() {
+    let Some(mut rx) = subscribe_engine_events() else {
+        return;
+    };
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(EngineEvent::VolumeChanged(v)) => info!("🔊 设备音量: {}", v),
+                Ok(EngineEvent::MuteChanged(m)) => {
+                    info!("{}", if m { "🔇 设备已静音" } else { "🔊 设备取消静音" })
+                }
+                Ok(EngineEvent::TransportStateChanged(state)) => info!("设备状态: {}", state),
+                Ok(EngineEvent::RendererLost { friendly_name, .. }) => {
+                    log::warn!("⚠ 设备 {} 失去响应，正在自动重连...", friendly_name)
+                }
+                Ok(EngineEvent::RendererReconnected { friendly_name, location }) => {
+                    info!("✅ 设备 {} 已重新连接 ({})，已恢复播放", friendly_name, location)
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    });
+}
+
 fn setup_pb_style(pb: &ProgressBar) {
     pb.set_style(
         ProgressStyle::with_template("{bar:40.green/blue} {my_pos} / {my_len}")
@@ -196,8 +391,7 @@ fn get_room_config_interactively() -> Result<(String, String)> {
         .or_else(|| {
             parsed
                 .path_segments()?
-                .filter(|s| !s.is_empty())
-                .last()
+                .rfind(|s| !s.is_empty())
                 .map(|s| s.to_string())
         })
         .with_context(|| "URL 中未找到房间号")?;
@@ -206,47 +400,23 @@ fn get_room_config_interactively() -> Result<(String, String)> {
     Ok((base_url, room_str))
 }
 
-/// 负责进度查询、自动切歌，并通过回调更新 UI
+/// 负责进度查询，并通过回调更新 UI（播放结束后的自动切歌由引擎处理）
 async fn run_cli_monitor<FL, FP>(mut set_len: FL, mut set_pos: FP) -> anyhow::Result<()>
 where
     FL: FnMut(u64),
     FP: FnMut(u64),
 {
-    loop {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-
-        let guard = ENGINE_STATE.read().unwrap();
-        if let Some(ctx) = guard.as_ref() {
-            // 1. 查询 DLNA 进度
-            if let Ok((curr, _)) = ctx.controller.get_secs(&ctx.device).await {
-                let curr_u64 = curr as u64;
-
-                // 2. 获取总时长
-                if let Some(playing) = ctx.playlist_manager.get_song_playing().await {
-                    let cache = ctx.duration_cache.lock().await;
-                    if let Some(&total) = cache.get(&playing) {
-                        let total_u64 = total as u64;
-
-                        set_len(total_u64);
-                        set_pos(curr_u64);
-
-                        // 3. 自动切歌逻辑
-                        if total_u64 > 0
-                            && curr_u64 > 5
-                            && total_u64 > curr_u64
-                            && (total_u64 - curr_u64) <= 2
-                        {
-                            log::info!(">> 歌曲即将结束，自动切换下一首...");
-                            let mut pm = ctx.playlist_manager.clone();
-                            let _ = pm.next_song().await;
-
-                            tokio::time::sleep(Duration::from_secs(5)).await;
-                        }
-                    }
-                }
-            }
-        } else {
-            break;
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+    while ENGINE_STATE.read().unwrap().is_some() {
+        ticker.tick().await;
+        // 1. 读取播放进度（由后台的事件订阅/轮询维护，不直接请求设备）
+        // 2. 总时长由引擎合并设备、mp4 探测与歌曲来源的结果
+        if let Some(position) = get_playback_position().await
+            && let Some(total) = position.duration_secs
+        {
+            set_len(total as u64);
+            set_pos(position.position_secs as u64);
         }
     }
     Ok(())
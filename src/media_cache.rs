@@ -0,0 +1,681 @@
+use crate::SharedState;
+use crate::bilibili_parser::get_bilibili_media;
+use crate::duration_resolver::DurationSource;
+use crate::error::{Error, Result};
+use crate::media_server::{self, UpstreamInfo};
+use crate::playlist_manager::PlaylistManager;
+use actix_web::{HttpRequest, HttpResponse, web};
+use futures_util::{Stream, StreamExt};
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::SystemTime;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::watch;
+
+const DEFAULT_MAX_MB: u64 = 2048;
+const DEFAULT_PREFETCH_COUNT: usize = 2;
+// 从缓存文件读取时每块的大小
+const READ_CHUNK: u64 = 64 * 1024;
+
+// 显式指定的缓存上限（MB）与预取数量，未设置时读取环境变量 KTV_CACHE_MAX_MB / KTV_PREFETCH_COUNT
+static MAX_MB: RwLock<Option<u64>> = RwLock::new(None);
+static PREFETCH_COUNT: RwLock<Option<usize>> = RwLock::new(None);
+static CACHE: OnceLock<MediaCache> = OnceLock::new();
+
+/// 指定缓存目录的大小上限（MB），0 表示不缓存，None 表示按环境变量决定
+pub fn set_max_mb(mb: Option<u64>) {
+    if let Ok(mut guard) = MAX_MB.write() {
+        *guard = mb;
+    }
+}
+
+/// 当前生效的缓存上限（字节）：显式指定 > 环境变量 KTV_CACHE_MAX_MB > 2048 MB
+pub fn max_bytes() -> u64 {
+    MAX_MB
+        .read()
+        .ok()
+        .and_then(|m| *m)
+        .or_else(|| std::env::var("KTV_CACHE_MAX_MB").ok().and_then(|v| v.trim().parse().ok()))
+        .unwrap_or(DEFAULT_MAX_MB)
+        .saturating_mul(1024 * 1024)
+}
+
+/// 指定预先下载队列中的前几首，0 表示不预取，None 表示按环境变量决定
+pub fn set_prefetch_count(count: Option<usize>) {
+    if let Ok(mut guard) = PREFETCH_COUNT.write() {
+        *guard = count;
+    }
+}
+
+/// 当前生效的预取数量：显式指定 > 环境变量 KTV_PREFETCH_COUNT > 2
+pub fn prefetch_count() -> usize {
+    PREFETCH_COUNT
+        .read()
+        .ok()
+        .and_then(|c| *c)
+        .or_else(|| std::env::var("KTV_PREFETCH_COUNT").ok().and_then(|v| v.trim().parse().ok()))
+        .unwrap_or(DEFAULT_PREFETCH_COUNT)
+}
+
+/// 全局缓存，目录为数据目录下的 media_cache（首次使用时打开）
+pub fn cache() -> &'static MediaCache {
+    CACHE.get_or_init(|| MediaCache::open(crate::data_dir().join("media_cache")))
+}
+
+/// 只缓存 bilibili 歌曲；直链（直播、HLS 等）原样代理
+pub fn is_cacheable(origin_url: &str) -> bool {
+    !media_server::is_direct_url(origin_url) && !media_server::parse_bilibili_path(origin_url).0.is_empty()
+}
+
+/// 缓存中的一首歌
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedSong {
+    /// 代理路径
+    pub key: String,
+    pub downloaded: u64,
+    pub total: u64,
+    pub complete: bool,
+}
+
+/// 缓存目录的使用情况
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheStatus {
+    pub max_bytes: u64,
+    pub used_bytes: u64,
+    /// 最近使用的在前
+    pub songs: Vec<CachedSong>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    // 文件名（不含扩展名），由代理路径哈希得到
+    stem: String,
+    content_type: String,
+    total: u64,
+    downloaded: u64,
+    complete: bool,
+    duration_secs: Option<u32>,
+    last_access: SystemTime,
+}
+
+/// 歌曲的本地磁盘缓存（按最近使用淘汰）。
+/// 每首歌一个 `<stem>.media`，下载完成后写入 `<stem>.json` 作为完成标记；
+/// 启动时没有完成标记的文件视为上次没下载完，直接删除。
+pub struct MediaCache {
+    dir: PathBuf,
+    entries: Mutex<HashMap<String, Entry>>,
+    // 正在演唱与即将播放的歌曲，淘汰时跳过
+    pinned: Mutex<HashSet<String>>,
+}
+
+impl MediaCache {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("无法创建缓存目录 {}: {}", dir.display(), e);
+        }
+        let entries = load_entries(&dir);
+        info!("媒体缓存目录: {}（{} 首）", dir.display(), entries.len());
+        Self {
+            dir,
+            entries: Mutex::new(entries),
+            pinned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn media_path(&self, stem: &str) -> PathBuf {
+        self.dir.join(format!("{}.media", stem))
+    }
+
+    fn meta_path(&self, stem: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", stem))
+    }
+
+    pub fn status(&self) -> CacheStatus {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let mut list: Vec<(&String, &Entry)> = entries.iter().collect();
+        list.sort_by_key(|(_, e)| std::cmp::Reverse(e.last_access));
+        CacheStatus {
+            max_bytes: max_bytes(),
+            used_bytes: used_bytes(&entries),
+            songs: list
+                .into_iter()
+                .map(|(key, e)| CachedSong {
+                    key: key.clone(),
+                    downloaded: e.downloaded,
+                    total: e.total,
+                    complete: e.complete,
+                })
+                .collect(),
+        }
+    }
+
+    /// 歌曲已完整缓存
+    pub fn is_complete(&self, key: &str) -> bool {
+        self.lookup(key).is_some_and(|e| e.complete)
+    }
+
+    fn lookup(&self, key: &str) -> Option<Entry> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned()
+    }
+
+    /// 设置淘汰时要保留的歌曲
+    pub fn pin(&self, keys: impl IntoIterator<Item = String>) {
+        *self.pinned.lock().unwrap_or_else(|e| e.into_inner()) = keys.into_iter().collect();
+    }
+
+    fn touch(&self, key: &str) {
+        let now = SystemTime::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.get_mut(key) {
+            entry.last_access = now;
+            // 访问时间记在文件修改时间上，重启后仍按它淘汰
+            if entry.complete
+                && let Err(e) = std::fs::File::options()
+                    .write(true)
+                    .open(self.media_path(&entry.stem))
+                    .and_then(|f| f.set_modified(now))
+            {
+                debug!("更新缓存访问时间失败: {}", e);
+            }
+        }
+    }
+
+    /// 淘汰最久未使用的歌曲，直到放得下 incoming 字节；放不下时返回 false
+    fn make_room(&self, incoming: u64, max: u64) -> bool {
+        if incoming > max {
+            return false;
+        }
+        let pinned = self.pinned.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        while used_bytes(&entries) + incoming > max {
+            // 正在下载的与要保留的不淘汰
+            let Some(key) = entries
+                .iter()
+                .filter(|(key, e)| e.complete && !pinned.contains(*key))
+                .min_by_key(|(_, e)| e.last_access)
+                .map(|(key, _)| key.clone())
+            else {
+                return false;
+            };
+            if let Some(entry) = entries.remove(&key) {
+                info!("缓存已满，移除最久未播放的歌曲: {}", key);
+                self.remove_files(&entry.stem);
+            }
+        }
+        true
+    }
+
+    fn remove_files(&self, stem: &str) {
+        for path in [self.meta_path(stem), self.media_path(stem)] {
+            if let Err(e) = std::fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!("删除缓存文件失败 {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// 登记一次下载，返回要写入的文件；已在缓存或正在下载时返回 None
+    fn begin(&self, key: &str, content_type: String, total: u64, duration_secs: Option<u32>) -> Option<PathBuf> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.contains_key(key) {
+            return None;
+        }
+        let stem = file_stem(key);
+        let path = self.media_path(&stem);
+        entries.insert(
+            key.to_string(),
+            Entry {
+                stem,
+                content_type,
+                total,
+                downloaded: 0,
+                complete: false,
+                duration_secs,
+                last_access: SystemTime::now(),
+            },
+        );
+        Some(path)
+    }
+
+    fn progress(&self, key: &str, downloaded: u64) {
+        if let Some(entry) = self.entries.lock().unwrap_or_else(|e| e.into_inner()).get_mut(key) {
+            entry.downloaded = downloaded;
+        }
+    }
+
+    /// 写入完成标记
+    fn finish(&self, key: &str) -> std::io::Result<()> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(entry) = entries.get_mut(key) else {
+            return Ok(());
+        };
+        let meta = json!({
+            "key": key,
+            "content_type": entry.content_type,
+            "total": entry.total,
+            "duration_secs": entry.duration_secs,
+        });
+        std::fs::write(self.meta_path(&entry.stem), meta.to_string())?;
+        entry.complete = true;
+        entry.last_access = SystemTime::now();
+        Ok(())
+    }
+
+    fn abandon(&self, key: &str) {
+        let entry = self.entries.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+        if let Some(entry) = entry {
+            self.remove_files(&entry.stem);
+        }
+    }
+
+    /// 从上游完整下载一首歌到缓存
+    async fn download(
+        &self,
+        client: &reqwest::Client,
+        key: &str,
+        target_url: &str,
+        duration_secs: Option<u32>,
+    ) -> Result<()> {
+        let response = media_server::apply_upstream_headers(client.get(target_url), target_url)
+            .send()
+            .await
+            .map_err(|e| Error::upstream(None, format!("下载歌曲失败: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(Error::upstream(
+                None,
+                format!("下载歌曲失败，状态码: {}", response.status()),
+            ));
+        }
+        let Some(total) = response.content_length().filter(|&n| n > 0) else {
+            info!("上游未给出文件大小，不缓存: {}", key);
+            return Ok(());
+        };
+        if !self.make_room(total, max_bytes()) {
+            info!("缓存空间不足（需要 {} MB），不缓存: {}", total / 1024 / 1024, key);
+            return Ok(());
+        }
+        let content_type = media_server::normalize_content_type(
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok()),
+            target_url,
+        )
+        .unwrap_or_else(|| "video/mp4".to_string());
+        let Some(path) = self.begin(key, content_type, total, duration_secs) else {
+            return Ok(());
+        };
+
+        info!("开始缓存 {}（{} MB）", key, total / 1024 / 1024);
+        let written: std::io::Result<()> = async {
+            let mut file = tokio::fs::File::create(&path).await?;
+            let mut body = response.bytes_stream();
+            let mut downloaded = 0u64;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(std::io::Error::other)?;
+                file.write_all(&chunk).await?;
+                // tokio 的文件写入在后台完成，落盘后才能让边下边播读到这一段
+                file.flush().await?;
+                downloaded += chunk.len() as u64;
+                self.progress(key, downloaded);
+            }
+            file.flush().await?;
+            if downloaded != total {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("只下载了 {}/{} 字节", downloaded, total),
+                ));
+            }
+            Ok(())
+        }
+        .await;
+
+        match written.and_then(|()| self.finish(key)) {
+            Ok(()) => {
+                info!("缓存完成: {}", key);
+                Ok(())
+            }
+            Err(e) => {
+                self.abandon(key);
+                Err(Error::upstream(None, format!("下载歌曲失败: {}", e)))
+            }
+        }
+    }
+
+    /// 把缓存中记录的时长与格式告诉引擎，省去探测上游
+    pub(crate) async fn remember(&self, shared_state: &SharedState, key: &str) {
+        let Some(entry) = self.lookup(key).filter(|e| e.complete) else {
+            return;
+        };
+        if let Some(secs) = entry.duration_secs {
+            shared_state
+                .duration_cache
+                .lock()
+                .await
+                .report(key, DurationSource::SongSource, secs);
+        }
+        shared_state.upstream_info.lock().await.insert(
+            key.to_string(),
+            UpstreamInfo {
+                content_type: entry.content_type,
+                content_length: Some(entry.total),
+            },
+        );
+    }
+
+    /// 用缓存回应代理请求：完整缓存时直接读文件；只下载了一部分时，已下载的部分读文件，
+    /// 其余从 target_url 取（没给出 target_url 时不处理）。无法处理时返回 None，由调用方照常代理。
+    pub(crate) async fn serve(
+        &self,
+        req: &HttpRequest,
+        key: &str,
+        target_url: Option<&str>,
+        client: &reqwest::Client,
+    ) -> Option<HttpResponse> {
+        let entry = self.lookup(key)?;
+        let range_hdr = req
+            .headers()
+            .get(actix_web::http::header::RANGE)
+            .and_then(|v| v.to_str().ok());
+        let (start, end) = match range_hdr {
+            Some(value) => parse_range(value, entry.total)?,
+            None => (0, entry.total.checked_sub(1)?),
+        };
+        let path = self.media_path(&entry.stem);
+        let is_head = *req.method() == actix_web::http::Method::HEAD;
+
+        let body: std::pin::Pin<Box<dyn Stream<Item = std::io::Result<web::Bytes>>>> = if entry.complete {
+            Box::pin(file_stream(path, start, end - start + 1))
+        } else {
+            // 请求的起点还没下载到时照常代理
+            let downloaded = entry.downloaded;
+            let target_url = target_url.filter(|_| start < downloaded)?;
+            let from_file = file_stream(path, start, downloaded.min(end + 1) - start);
+            if end < downloaded || is_head {
+                Box::pin(from_file)
+            } else {
+                let rest = media_server::apply_upstream_headers(client.get(target_url), target_url)
+                    .header("Range", format!("bytes={}-{}", downloaded, end))
+                    .send()
+                    .await
+                    .ok()
+                    .filter(|r| r.status() == reqwest::StatusCode::PARTIAL_CONTENT)?;
+                debug!("缓存 {}：{}-{} 读文件，之后从上游取", key, start, downloaded - 1);
+                Box::pin(from_file.chain(rest.bytes_stream().map(|item| item.map_err(std::io::Error::other))))
+            }
+        };
+        self.touch(key);
+
+        let mut resp = if range_hdr.is_some() {
+            let mut resp = HttpResponse::PartialContent();
+            resp.insert_header((
+                "content-range",
+                format!("bytes {}-{}/{}", start, end, entry.total),
+            ));
+            resp
+        } else {
+            HttpResponse::Ok()
+        };
+        resp.insert_header(("content-type", entry.content_type.as_str()))
+            .insert_header(("accept-ranges", "bytes"));
+        let len = end - start + 1;
+        if is_head {
+            resp.insert_header(("content-length", len));
+            return Some(resp.finish());
+        }
+        Some(resp.no_chunking(len).streaming(body))
+    }
+}
+
+// 重启后重建索引：有完成标记且大小一致的保留，其余删除
+fn load_entries(dir: &Path) -> HashMap<String, Entry> {
+    let mut entries = HashMap::new();
+    let Ok(read_dir) = std::fs::read_dir(dir) else {
+        return entries;
+    };
+    let mut kept: HashSet<PathBuf> = HashSet::new();
+    let files: Vec<PathBuf> = read_dir.flatten().map(|e| e.path()).collect();
+    for meta_path in files.iter().filter(|p| p.extension().is_some_and(|e| e == "json")) {
+        let media_path = meta_path.with_extension("media");
+        let meta: Option<Value> = std::fs::read_to_string(meta_path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok());
+        let file = std::fs::metadata(&media_path).ok();
+        let parsed = meta.as_ref().zip(file.as_ref()).and_then(|(meta, file)| {
+            let key = meta["key"].as_str()?.to_string();
+            let total = meta["total"].as_u64().filter(|&t| t == file.len())?;
+            let stem = meta_path.file_stem()?.to_str()?.to_string();
+            Some((
+                key,
+                Entry {
+                    stem,
+                    content_type: meta["content_type"].as_str().unwrap_or("video/mp4").to_string(),
+                    total,
+                    downloaded: total,
+                    complete: true,
+                    duration_secs: meta["duration_secs"].as_u64().map(|s| s as u32),
+                    last_access: file.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                },
+            ))
+        });
+        if let Some((key, entry)) = parsed {
+            kept.insert(meta_path.clone());
+            kept.insert(media_path);
+            entries.insert(key, entry);
+        }
+    }
+    for path in files.iter().filter(|p| !kept.contains(*p)) {
+        debug!("删除未完成的缓存文件: {}", path.display());
+        let _ = std::fs::remove_file(path);
+    }
+    entries
+}
+
+fn used_bytes(entries: &HashMap<String, Entry>) -> u64 {
+    // 正在下载的按完整大小计算，避免下载完成后超出上限
+    entries.values().map(|e| e.total).sum()
+}
+
+// FNV-1a，文件名在不同版本、不同平台间保持一致
+fn file_stem(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// 解析单段的 Range 头（`bytes=a-b` / `bytes=a-` / `bytes=-n`），返回闭区间；
+/// 多段或超出文件范围时返回 None
+fn parse_range(value: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || total == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let n: u64 = suffix.parse().ok().filter(|&n| n > 0)?;
+            (total.saturating_sub(n), total - 1)
+        }
+        (start, "") => (start.parse().ok()?, total - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(total - 1)),
+    };
+    (start <= end && start < total).then_some((start, end))
+}
+
+fn file_stream(path: PathBuf, start: u64, len: u64) -> impl Stream<Item = std::io::Result<web::Bytes>> {
+    futures_util::stream::try_unfold(
+        (None::<tokio::fs::File>, start, len),
+        move |(file, pos, remaining)| {
+            let path = path.clone();
+            async move {
+                if remaining == 0 {
+                    return Ok(None);
+                }
+                let mut file = match file {
+                    Some(file) => file,
+                    None => {
+                        let mut file = tokio::fs::File::open(&path).await?;
+                        file.seek(SeekFrom::Start(pos)).await?;
+                        file
+                    }
+                };
+                let mut buf = vec![0u8; READ_CHUNK.min(remaining) as usize];
+                let n = file.read(&mut buf).await?;
+                if n == 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("缓存文件不完整: {}", path.display()),
+                    ));
+                }
+                buf.truncate(n);
+                Ok(Some((web::Bytes::from(buf), (Some(file), pos + n as u64, remaining - n as u64))))
+            }
+        },
+    )
+}
+
+/// 跟随歌单预先下载排队中的前几首（见 prefetch_count），一次只下载一首，不与正在播放的歌抢带宽
+pub async fn run(
+    playlist_manager: PlaylistManager,
+    shared_state: web::Data<SharedState>,
+    mut queue: watch::Receiver<Vec<String>>,
+) {
+    let client = reqwest::Client::new();
+    let cache = cache();
+    loop {
+        let wanted: Vec<String> = queue
+            .borrow_and_update()
+            .iter()
+            .filter(|key| is_cacheable(key))
+            .take(prefetch_count())
+            .cloned()
+            .collect();
+        let playing = playlist_manager.get_song_playing().await;
+        cache.pin(wanted.iter().cloned().chain(playing));
+
+        if max_bytes() > 0 {
+            for key in &wanted {
+                // 歌单又变了，按新的队列重新安排
+                if queue.has_changed().unwrap_or(true) {
+                    break;
+                }
+                if let Err(e) = prefetch(cache, &client, &shared_state, key).await {
+                    warn!("预取歌曲失败 {}: {}", key, e);
+                }
+            }
+        }
+
+        if queue.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn prefetch(
+    cache: &MediaCache,
+    client: &reqwest::Client,
+    shared_state: &SharedState,
+    key: &str,
+) -> Result<()> {
+    if cache.lookup(key).is_none() {
+        let (bv_id, page) = media_server::parse_bilibili_path(key);
+        let media = get_bilibili_media(bv_id, page).await?;
+        cache.download(client, key, &media.url, media.duration_secs).await?;
+    }
+    cache.remember(shared_state, key).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_index_and_eviction() {
+        assert_eq!(parse_range("bytes=0-", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=100-199", 1000), Some((100, 199)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(file_stem("BV1xx411c7mD-page2"), file_stem("BV1xx411c7mD-page2"));
+        assert_ne!(file_stem("BV1xx411c7mD-page2"), file_stem("BV1xx411c7mD"));
+        assert!(is_cacheable("BV1xx411c7mD-page2"));
+        assert!(!is_cacheable("https://example.com/live/index.m3u8"));
+
+        let dir = std::env::temp_dir().join(format!("ktv-cache-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = MediaCache::open(&dir);
+        for (key, size) in [("BVold", 400u64), ("BVnew", 400)] {
+            let path = cache.begin(key, "video/mp4".into(), size, Some(200)).unwrap();
+            std::fs::write(path, vec![0u8; size as usize]).unwrap();
+            cache.progress(key, size);
+            cache.finish(key).unwrap();
+        }
+        assert!(cache.begin("BVold", "video/mp4".into(), 400, None).is_none());
+        // 未完成的下载重启后被丢弃
+        std::fs::write(cache.media_path(&file_stem("BVhalf")), b"partial").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(cache.media_path(&file_stem("BVold")))
+            .and_then(|f| f.set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000)))
+            .unwrap();
+
+        let cache = MediaCache::open(&dir);
+        assert!(cache.is_complete("BVold") && cache.is_complete("BVnew"));
+        assert!(!cache.media_path(&file_stem("BVhalf")).exists());
+        assert_eq!(cache.status().used_bytes, 800);
+
+        // 放不下时淘汰最久未用的，被保留的歌曲不淘汰
+        cache.pin(["BVold".to_string(), "BVnew".to_string()]);
+        assert!(!cache.make_room(500, 1000));
+        cache.pin([]);
+        assert!(cache.make_room(500, 1000));
+        assert!(!cache.is_complete("BVold") && cache.is_complete("BVnew"));
+        assert!(!cache.make_room(2000, 1000));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_serve_partial_entry() {
+        let dir = std::env::temp_dir().join(format!("ktv-cache-partial-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = MediaCache::open(&dir);
+        let data: Vec<u8> = (0..=255u8).collect();
+        let path = cache.begin("BVpartial", "video/mp4".into(), 1000, None).unwrap();
+        std::fs::write(path, &data).unwrap();
+        cache.progress("BVpartial", data.len() as u64);
+
+        // 请求的范围已全部下载，直接读文件，不访问上游
+        let client = reqwest::Client::new();
+        let req = actix_web::test::TestRequest::get()
+            .insert_header(("Range", "bytes=100-199"))
+            .to_http_request();
+        let resp = cache
+            .serve(&req, "BVpartial", Some("http://127.0.0.1:9/unreachable"), &client)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), actix_web::http::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 100-199/1000");
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], &data[100..200]);
+
+        // 起点还没下载到时交给代理
+        let req = actix_web::test::TestRequest::get()
+            .insert_header(("Range", "bytes=300-399"))
+            .to_http_request();
+        assert!(cache.serve(&req, "BVpartial", Some("http://127.0.0.1:9/unreachable"), &client).await.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,147 @@
 // 使用示例
 use crate::SharedState;
-use crate::bilibili_parser::get_bilibili_direct_link;
+use crate::bilibili_parser::{get_bilibili_cover, get_bilibili_media};
+use crate::duration_resolver::DurationSource;
+use crate::error::{Error, Result};
+use crate::hls;
+use crate::media_cache;
+use crate::signed_url;
 use crate::mp4_util::get_mp4_duration;
 use actix_web::{HttpRequest, HttpResponse, get, web};
 use futures_util::StreamExt;
-use log::info;
+use log::{debug, info, warn};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::sync::RwLock;
+
+// 封面走本地代理的路径前缀
+const COVER_PREFIX: &str = "_cover/";
+const DEFAULT_PORT: u16 = 8080;
+// 端口被占用时依次尝试之后的端口数
+const PORT_FALLBACK_ATTEMPTS: u16 = 20;
+
+// 用户指定的媒体服务器端口，未设置时读取环境变量 KTV_MEDIA_PORT
+static PORT: RwLock<Option<u16>> = RwLock::new(None);
+
+/// 指定媒体服务器端口，0 表示由系统分配，None 恢复默认；下次启动引擎时生效
+pub fn set_port(port: Option<u16>) {
+    if let Ok(mut guard) = PORT.write() {
+        *guard = port;
+    }
+}
+
+/// 配置的媒体服务器端口，默认 8080
+pub fn configured_port() -> u16 {
+    PORT.read()
+        .ok()
+        .and_then(|p| *p)
+        .or_else(|| std::env::var("KTV_MEDIA_PORT").ok().and_then(|v| v.trim().parse().ok()))
+        .unwrap_or(DEFAULT_PORT)
+}
+
+/// 监听 port；被占用时依次尝试之后的端口，0 表示由系统分配。实际端口从返回的 listener 取得
+pub fn bind(port: u16) -> Result<TcpListener> {
+    let error = |port: u16, e: std::io::Error| Error::MediaServer {
+        port,
+        message: e.to_string(),
+    };
+    if port == 0 {
+        return bind_listener(0).map_err(|e| error(0, e));
+    }
+    let mut last_error = None;
+    for candidate in (port..=u16::MAX).take(PORT_FALLBACK_ATTEMPTS as usize) {
+        match bind_listener(candidate) {
+            Ok(listener) => {
+                if candidate != port {
+                    warn!("端口 {} 已被占用，媒体服务器改用 {}", port, candidate);
+                }
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                debug!("端口 {} 已被占用: {}", candidate, e);
+                last_error = Some(e);
+            }
+            // 没有权限等情况换端口也没用
+            Err(e) => return Err(error(candidate, e)),
+        }
+    }
+    Err(Error::MediaServer {
+        port,
+        message: format!(
+            "{} 起的 {} 个端口都被占用 ({})",
+            port,
+            PORT_FALLBACK_ATTEMPTS,
+            last_error.map(|e| e.to_string()).unwrap_or_default()
+        ),
+    })
+}
+
+/// 设备访问本地媒体服务器的地址；IPv6 地址加方括号，scope id 只对本机有效，不写入 URL
+pub fn base_url(ip: IpAddr, port: u16) -> String {
+    format!("http://{}", SocketAddr::new(ip, port))
+}
+
+/// 监听媒体服务器端口：优先 `[::]` 双栈，同时接受 IPv4 与 IPv6 设备；系统不支持 IPv6 时退回 0.0.0.0
+pub fn bind_listener(port: u16) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Socket, Type};
+    let dual_stack = || -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+        socket.set_only_v6(false)?;
+        // Windows 上 SO_REUSEADDR 允许抢占正在使用的端口，会让占用检测失效
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+        socket.listen(1024)?;
+        Ok(socket.into())
+    };
+    dual_stack().or_else(|e| {
+        debug!("无法以双栈监听 {}，只监听 IPv4: {}", port, e);
+        TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+    })
+}
+
+/// 歌曲封面在本地媒体服务上的路径；只有 bilibili 歌曲有封面
+pub fn cover_path(origin_url: &str) -> Option<String> {
+    if is_direct_url(origin_url) {
+        return None;
+    }
+    let (bv_id, _) = parse_bilibili_path(origin_url);
+    (!bv_id.is_empty()).then(|| format!("{}{}", COVER_PREFIX, bv_id))
+}
+
+/// 代理 bilibili 封面（部分电视无法直接访问 https 图片），必须注册在 proxy_handler 之前
+#[get("/_cover/{bv_id}")]
+pub async fn cover_handler(
+    path: web::Path<(String,)>,
+    client: web::Data<reqwest::Client>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (bv_id,) = path.into_inner();
+    let cover_url = get_bilibili_cover(&bv_id)
+        .await
+        .map_err(actix_web::error::ErrorNotFound)?;
+    let response = client
+        .get(&cover_url)
+        .header("Referer", "https://www.bilibili.com/")
+        .send()
+        .await
+        .map_err(actix_web::error::ErrorBadGateway)?;
+    if !response.status().is_success() {
+        return Err(actix_web::error::ErrorBadGateway(format!(
+            "获取封面失败，状态码: {}",
+            response.status()
+        )));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("image/jpeg")
+        .to_string();
+    let body = response
+        .bytes()
+        .await
+        .map_err(actix_web::error::ErrorBadGateway)?;
+    Ok(HttpResponse::Ok().content_type(content_type).body(body))
+}
 
 #[get("/{url:.*}")]
 pub async fn proxy_handler(
@@ -20,24 +157,21 @@ pub async fn proxy_handler(
         origin_url.push_str(query_string);
     }
     
-    // 针对 eplus 的特殊鉴权参数保存与补全逻辑
-    if origin_url.contains("eplus") {
-        let mut session = shared_state.eplus_auth.lock().await;
-        if origin_url.contains("Policy=") && origin_url.contains("Signature=") {
-            // 如果链接带了完整鉴权参数（如 index.m3u8），保存它供后续切片使用
-            if let Some(query_start) = origin_url.find('?') {
-                *session = Some(origin_url[query_start + 1..].to_string());
-                info!("Eplus: Saved auth parameters from playlist request");
-            }
-        } else if (origin_url.ends_with(".ts") || origin_url.contains(".ts?")) && !origin_url.contains("Signature=") {
-            // 如果是切片请求（.ts）且缺少鉴权，将保存的参数拼接到切片 URL 后
-            if let Some(ref auth_params) = *session {
-                let connector = if origin_url.contains('?') { "&" } else { "?" };
-                origin_url.push_str(connector);
-                origin_url.push_str(auth_params);
-                info!("Eplus: Injected saved auth parameters into segment request");
-            }
+    // 签名地址（CloudFront、Akamai 等）：记录播放列表上的签名，补给同一会话中没有签名的分片/密钥请求
+    let auth_rules = signed_url::rules();
+    let mut auth_cookie = None;
+    if is_direct_url(&origin_url)
+        && let Ok(url) = url::Url::parse(&origin_url)
+    {
+        let mut sessions = shared_state.auth_sessions.lock().await;
+        sessions.capture_url(&auth_rules, &url);
+        let injection = sessions.inject(&auth_rules, &url);
+        if let Some(params) = injection.params {
+            origin_url.push(if url.query().is_some() { '&' } else { '?' });
+            origin_url.push_str(&params);
+            info!("签名: 已为子请求补上鉴权参数");
         }
+        auth_cookie = injection.cookie;
     }
 
     info!("Received proxy request for URL: {}", origin_url);
@@ -61,32 +195,33 @@ pub async fn proxy_handler(
         if_range_hdr
     );
 
-    let is_direct = origin_url.starts_with("http://") || origin_url.starts_with("https://");
-    let (bv_id, page) = if is_direct {
-        (origin_url.as_str(), None)
-    } else {
-        let path_without_query = origin_url.split('?').next().unwrap_or(origin_url.as_str());
-        let bv_id = &path_without_query[..path_without_query.find('-').unwrap_or(path_without_query.len())];
-        let page: Option<u32> = if let Some(pos) = path_without_query.find("-page") {
-            path_without_query[pos + 5..].parse().ok()
-        } else {
-            None
-        };
-        (bv_id, page)
-    };
-
-    info!("Proxy parsed: bv_id={} page={:?}", bv_id, page);
+    // 已完整缓存的歌曲直接读本地文件，不再解析上游地址
+    let cacheable = media_cache::is_cacheable(&origin_url);
+    if cacheable
+        && let Some(resp) = media_cache::cache().serve(&req, &origin_url, None, &client).await
+    {
+        media_cache::cache().remember(&shared_state, &origin_url).await;
+        info!("从本地缓存提供: {}", origin_url);
+        return Ok(resp);
+    }
 
-    let target_url = if is_direct {
-        origin_url.clone()
-    } else {
-        get_bilibili_direct_link(bv_id, page)
-            .await
-            .map_err(actix_web::error::ErrorInternalServerError)?
-    };
+    let is_direct = is_direct_url(&origin_url);
+    let target_url = resolve_target_url(&shared_state, &origin_url)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
 
     info!("Proxy resolved target_url={}", target_url);
 
+    // 正在预取的歌曲：已下载的部分读本地文件，其余从上游取
+    if cacheable
+        && let Some(resp) = media_cache::cache()
+            .serve(&req, &origin_url, Some(&target_url), &client)
+            .await
+    {
+        info!("部分从本地缓存提供: {}", origin_url);
+        return Ok(resp);
+    }
+
     // 异步获取视频时长并存入缓存（m3u8 直播流跳过，为了避免抓取 HLS 分片时产生大量无用且失败的网络请求，直接地址也需要跳过对 ts/m4s/fmp4 分片或带有结尾查询串切片的探测）
     let is_hls = origin_url
         .split('?')
@@ -108,19 +243,18 @@ pub async fn proxy_handler(
         let origin_url_clone = origin_url.clone();
         let target_url_clone = target_url.clone();
         tokio::spawn(async move {
-            // 先检查缓存中是否已有该视频的时长，若无则先用 0 占位，防止并发产生大量多余下载请求
-            {
-                let mut cache = duration_cache.lock().await;
-                if cache.contains_key(&origin_url_clone) {
-                    return;
-                }
-                cache.insert(origin_url_clone.clone(), 0);
+            // 每个视频只探测一次，防止并发产生大量多余下载请求
+            if !duration_cache.lock().await.begin_probe(&origin_url_clone) {
+                return;
             }
 
             match get_mp4_duration(&target_url_clone).await {
                 Ok(duration) => {
-                    let mut cache = duration_cache.lock().await;
-                    cache.insert(origin_url_clone, duration.as_secs() as u32);
+                    duration_cache.lock().await.report(
+                        &origin_url_clone,
+                        DurationSource::Mp4Probe,
+                        duration.as_secs() as u32,
+                    );
                     info!(
                         "成功获取并缓存视频时长: {} -> {}s",
                         target_url_clone,
@@ -128,10 +262,9 @@ pub async fn proxy_handler(
                     );
                 }
                 Err(e) => {
-                    let mut cache = duration_cache.lock().await;
-                    cache.insert(origin_url_clone, 0); // 缓存失败结果，避免后续 HLS 分片反复请求下载 2MB
+                    // 失败也已标记为探测过，避免后续 HLS 分片反复请求下载 2MB
                     if is_direct {
-                        log::debug!("无法获取(直链/分片)视频时长: {} (静默跳过并缓存为0)", e);
+                        log::debug!("无法获取(直链/分片)视频时长: {} (静默跳过)", e);
                     } else {
                         log::warn!("无法获取 bilibili 视频时长: {}", e);
                     }
@@ -148,30 +281,14 @@ pub async fn proxy_handler(
         _ => client.get(&target_url),
     };
 
-    if target_url.contains("eplus") {
-        upstream = upstream
-            .header("accept", "*/*")
-            .header("accept-language", "zh-CN,zh;q=0.9,en;q=0.8")
-            .header("cache-control", "no-cache")
-            .header("origin", "https://live.nulla.top")
-            .header("pragma", "no-cache")
-            .header("priority", "u=1, i")
-            .header("sec-ch-ua", "\"Not:A-Brand\";v=\"99\", \"Google Chrome\";v=\"145\", \"Chromium\";v=\"145\"")
-            .header("sec-ch-ua-mobile", "?1")
-            .header("sec-ch-ua-platform", "\"iOS\"")
-            .header("sec-fetch-dest", "empty")
-            .header("sec-fetch-mode", "cors")
-            .header("sec-fetch-site", "cross-site")
-            .header("user-agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 18_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.5 Mobile/15E148 Safari/604.1");
-    } else {
-        upstream = upstream
-            .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36")
-            .header("Referer", "https://www.bilibili.com/");
+    upstream = apply_upstream_headers(upstream, &target_url);
+    if let Some(cookie) = &auth_cookie {
+        upstream = upstream.header(reqwest::header::COOKIE, cookie.as_str());
     }
-        
 
     // Forward Range-related headers to support seek/probe.
-    if let Some(range) = req.headers().get(actix_web::http::header::RANGE) {
+    // 播放列表要整份改写，不转发 Range
+    if !is_hls && let Some(range) = req.headers().get(actix_web::http::header::RANGE) {
         upstream = upstream.header("Range", range.as_bytes());
     }
     if let Some(if_range) = req.headers().get(actix_web::http::header::IF_RANGE) {
@@ -213,6 +330,26 @@ pub async fn proxy_handler(
         cr
     );
 
+    if let Ok(url) = url::Url::parse(&target_url) {
+        shared_state
+            .auth_sessions
+            .lock()
+            .await
+            .capture_cookies(&auth_rules, &url, response.headers());
+    }
+
+    // 记录上游的真实格式，供 protocolInfo 协商使用
+    if response.status().is_success()
+        && let Some(info) = UpstreamInfo::from_response(&response, &target_url)
+    {
+        shared_state.upstream_info.lock().await.insert(origin_url.clone(), info);
+    }
+
+    // m3u8 改写成所有地址都经过本地代理，不依赖设备自己拼接相对地址
+    if response.status().is_success() && (is_hls || hls::is_playlist(Some(ct), &target_url)) {
+        return serve_playlist(&req, &client, &shared_state, response, &target_url).await;
+    }
+
     let status_u16 = response.status().as_u16();
     let mut client_resp = HttpResponse::build(
         actix_web::http::StatusCode::from_u16(status_u16)
@@ -246,12 +383,284 @@ pub async fn proxy_handler(
     Ok(client_resp.streaming(body_stream))
 }
 
+/// 改写上游的 m3u8 后返回；按 hls::variant_policy 可只给设备一个码率的媒体播放列表
+async fn serve_playlist(
+    req: &HttpRequest,
+    client: &reqwest::Client,
+    shared_state: &SharedState,
+    response: reqwest::Response,
+    target_url: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut resp = HttpResponse::Ok();
+    resp.content_type("application/vnd.apple.mpegurl")
+        .insert_header(("cache-control", "no-cache"));
+    if *req.method() == actix_web::http::Method::HEAD {
+        return Ok(resp.finish());
+    }
+
+    // 设备访问本机时用的地址，改写后的分片地址沿用它
+    let proxy_base = {
+        let info = req.connection_info();
+        format!("{}://{}", info.scheme(), info.host())
+    };
+    let mut playlist_url = url::Url::parse(target_url).map_err(actix_web::error::ErrorInternalServerError)?;
+    // 该会话记录到的签名参数（见 signed_url）补给本身没有参数的子地址
+    let auth_rules = signed_url::rules();
+    let inherit_query = shared_state
+        .auth_sessions
+        .lock()
+        .await
+        .query_for(&auth_rules, &playlist_url);
+    let mut text = response.text().await.map_err(actix_web::error::ErrorBadGateway)?;
+
+    let policy = hls::variant_policy();
+    let pinned = hls::is_master(&text)
+        .then(|| policy.select(&hls::variants(&text)).cloned())
+        .flatten()
+        .and_then(|v| Some((hls::resolve(&v.uri, &playlist_url, inherit_query.as_deref())?, v)));
+    if let Some((mut variant_url, variant)) = pinned {
+        info!("HLS: 按 {:?} 只使用码率 {:?}: {}", policy, variant.bandwidth, variant_url);
+        // 与代理的子请求一样补上会话的签名参数与 Cookie
+        let injection = shared_state.auth_sessions.lock().await.inject(&auth_rules, &variant_url);
+        if let Some(params) = injection.params {
+            let query = match variant_url.query() {
+                Some(query) => format!("{}&{}", query, params),
+                None => params,
+            };
+            variant_url.set_query(Some(&query));
+        }
+        let mut upstream = apply_upstream_headers(client.get(variant_url.as_str()), variant_url.as_str());
+        if let Some(cookie) = &injection.cookie {
+            upstream = upstream.header(reqwest::header::COOKIE, cookie.as_str());
+        }
+        let variant_resp = upstream.send().await.map_err(actix_web::error::ErrorBadGateway)?;
+        shared_state
+            .auth_sessions
+            .lock()
+            .await
+            .capture_cookies(&auth_rules, &variant_url, variant_resp.headers());
+        if !variant_resp.status().is_success() {
+            return Err(actix_web::error::ErrorBadGateway(format!(
+                "获取码率播放列表失败，状态码: {}",
+                variant_resp.status()
+            )));
+        }
+        text = variant_resp.text().await.map_err(actix_web::error::ErrorBadGateway)?;
+        playlist_url = variant_url;
+    }
+
+    debug!("HLS: 改写播放列表 {}", playlist_url);
+    Ok(resp.body(hls::rewrite(&text, &playlist_url, &proxy_base, inherit_query.as_deref())))
+}
+
+/// 上游媒体的实际格式（来自代理请求或 HEAD 探测）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamInfo {
+    /// 去掉参数、小写化后的 MIME
+    pub content_type: String,
+    /// 完整文件大小（Range 响应时取 Content-Range 中的总长度）
+    pub content_length: Option<u64>,
+}
+
+impl UpstreamInfo {
+    fn from_response(response: &reqwest::Response, target_url: &str) -> Option<Self> {
+        let header = |name: reqwest::header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        let content_type = normalize_content_type(
+            header(reqwest::header::CONTENT_TYPE).as_deref(),
+            target_url,
+        )?;
+        // Content-Range: bytes 0-0/12345
+        let content_length = header(reqwest::header::CONTENT_RANGE)
+            .and_then(|cr| cr.rsplit('/').next().and_then(|t| t.trim().parse().ok()))
+            .or_else(|| {
+                if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+                    None
+                } else {
+                    header(reqwest::header::CONTENT_LENGTH).and_then(|v| v.parse().ok())
+                }
+            });
+        Some(Self {
+            content_type,
+            content_length,
+        })
+    }
+}
+
+/// 规范化 Content-Type；上游给出 octet-stream 等笼统类型时按扩展名推断
+pub(crate) fn normalize_content_type(content_type: Option<&str>, url: &str) -> Option<String> {
+    let mime = content_type
+        .and_then(|ct| ct.split(';').next())
+        .map(|ct| ct.trim().to_ascii_lowercase())
+        .filter(|ct| !ct.is_empty());
+    match mime.as_deref() {
+        None | Some("application/octet-stream") | Some("binary/octet-stream") => {
+            guess_mime_from_url(url).map(|m| m.to_string()).or(mime)
+        }
+        Some(_) => mime,
+    }
+}
+
+fn guess_mime_from_url(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url).to_ascii_lowercase();
+    let ext = path.rsplit('.').next()?;
+    Some(match ext {
+        "mp4" | "m4v" => "video/mp4",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "flv" => "video/x-flv",
+        "ts" => "video/mp2t",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "webm" => "video/webm",
+        _ => return None,
+    })
+}
+
+pub(crate) fn is_direct_url(origin_url: &str) -> bool {
+    origin_url.starts_with("http://") || origin_url.starts_with("https://")
+}
+
+// bilibili 代理路径形如 "BV1xx411c7mD-page2"，拆出 BV 号与分P
+pub(crate) fn parse_bilibili_path(origin_url: &str) -> (&str, Option<u32>) {
+    let path_without_query = origin_url.split('?').next().unwrap_or(origin_url);
+    let bv_id = &path_without_query[..path_without_query.find('-').unwrap_or(path_without_query.len())];
+    let page: Option<u32> = if let Some(pos) = path_without_query.find("-page") {
+        path_without_query[pos + 5..].parse().ok()
+    } else {
+        None
+    };
+    (bv_id, page)
+}
+
+/// 把代理路径解析成真实的上游地址：直链原样返回，bilibili 路径解析为 CDN 直链
+async fn resolve_target_url(shared_state: &SharedState, origin_url: &str) -> Result<String> {
+    if is_direct_url(origin_url) {
+        info!("Proxy parsed: direct url={}", origin_url);
+        return Ok(origin_url.to_string());
+    }
+
+    let (bv_id, page) = parse_bilibili_path(origin_url);
+    info!("Proxy parsed: bv_id={} page={:?}", bv_id, page);
+
+    let media = get_bilibili_media(bv_id, page).await?;
+    if let Some(secs) = media.duration_secs {
+        shared_state
+            .duration_cache
+            .lock()
+            .await
+            .report(origin_url, DurationSource::SongSource, secs);
+    }
+    Ok(media.url)
+}
+
+pub(crate) fn apply_upstream_headers(upstream: reqwest::RequestBuilder, target_url: &str) -> reqwest::RequestBuilder {
+    if target_url.contains("eplus") {
+        upstream
+            .header("accept", "*/*")
+            .header("accept-language", "zh-CN,zh;q=0.9,en;q=0.8")
+            .header("cache-control", "no-cache")
+            .header("origin", "https://live.nulla.top")
+            .header("pragma", "no-cache")
+            .header("priority", "u=1, i")
+            .header("sec-ch-ua", "\"Not:A-Brand\";v=\"99\", \"Google Chrome\";v=\"145\", \"Chromium\";v=\"145\"")
+            .header("sec-ch-ua-mobile", "?1")
+            .header("sec-ch-ua-platform", "\"iOS\"")
+            .header("sec-fetch-dest", "empty")
+            .header("sec-fetch-mode", "cors")
+            .header("sec-fetch-site", "cross-site")
+            .header("user-agent", "Mozilla/5.0 (iPhone; CPU iPhone OS 18_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.5 Mobile/15E148 Safari/604.1")
+    } else {
+        upstream
+            .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36")
+            .header("Referer", "https://www.bilibili.com/")
+    }
+}
+
+/// 在投屏前探测上游媒体格式：优先使用代理已记录的信息，否则发 HEAD，
+/// 上游不支持 HEAD 时改用 `Range: bytes=0-0` 的 GET。
+pub async fn probe_upstream(
+    shared_state: &SharedState,
+    client: &reqwest::Client,
+    origin_url: &str,
+) -> Result<UpstreamInfo> {
+    if let Some(info) = shared_state.upstream_info.lock().await.get(origin_url) {
+        return Ok(info.clone());
+    }
+
+    let target_url = resolve_target_url(shared_state, origin_url).await?;
+    let head = apply_upstream_headers(client.head(&target_url), &target_url)
+        .send()
+        .await
+        .ok()
+        .filter(|r| r.status().is_success());
+    let response = match head {
+        Some(r) => r,
+        None => apply_upstream_headers(client.get(&target_url), &target_url)
+            .header("Range", "bytes=0-0")
+            .send()
+            .await
+            .map_err(|e| Error::upstream(None, format!("探测上游媒体失败: {}", e)))?,
+    };
+    if !response.status().is_success() {
+        return Err(Error::upstream(
+            None,
+            format!("探测上游媒体失败，状态码: {}", response.status()),
+        ));
+    }
+
+    let info = UpstreamInfo::from_response(&response, &target_url)
+        .ok_or_else(|| Error::upstream(None, format!("无法确定上游媒体格式: {}", target_url)))?;
+    info!("上游媒体格式: {} -> {:?}", origin_url, info);
+    shared_state
+        .upstream_info
+        .lock()
+        .await
+        .insert(origin_url.to_string(), info.clone());
+    Ok(info)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::media_server::proxy_handler;
+    use crate::media_server::{base_url, bind, normalize_content_type, proxy_handler};
     use actix_web::{App, HttpServer, web};
     use reqwest::Client;
 
+    #[test]
+    fn test_normalize_content_type() {
+        assert_eq!(
+            normalize_content_type(Some("Video/MP4; charset=binary"), "https://x/a").as_deref(),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            normalize_content_type(Some("application/octet-stream"), "https://x/live/index.m3u8?Policy=1").as_deref(),
+            Some("application/vnd.apple.mpegurl")
+        );
+        assert_eq!(
+            normalize_content_type(None, "https://upos.bilivideo.com/x-1-100026.mp4?e=1").as_deref(),
+            Some("video/mp4")
+        );
+        assert_eq!(normalize_content_type(None, "https://x/unknown"), None);
+    }
+
+    #[test]
+    fn test_bind_port_fallback() {
+        // 0 由系统分配端口
+        let taken = bind(0).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        assert_ne!(port, 0);
+        // 端口被占用时改用之后的端口
+        let fallback = bind(port).unwrap();
+        assert!(fallback.local_addr().unwrap().port() > port);
+
+        assert_eq!(base_url([192, 168, 1, 2].into(), 8080), "http://192.168.1.2:8080");
+        assert_eq!(base_url("fd00::2".parse().unwrap(), 8081), "http://[fd00::2]:8081");
+    }
+
     #[tokio::test]
     async fn test_https() {
         let client = reqwest::Client::new();
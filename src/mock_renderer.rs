@@ -0,0 +1,625 @@
+use crate::dlna_controller::{element_text, find_element, parse_didl_lite, with_xml, xml_escape};
+use crate::duration_resolver::parse_upnp_duration;
+use actix_web::dev::ServerHandle;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// 模拟设备的行为
+#[derive(Debug, Clone)]
+pub(crate) struct MockOptions {
+    pub friendly_name: String,
+    /// AVTransport 的 controlURL 不带前导斜杠（部分盒子的描述文件如此，原生方法会拼错地址）
+    pub control_without_slash: bool,
+    /// 拒绝 REL_TIME 的 Seek（UPnP 710）
+    pub reject_rel_time: bool,
+    /// 前几次 Seek 返回成功但不生效（模拟缓冲中的电视）
+    pub ignored_seeks: u32,
+    /// 是否支持 SetNextAVTransportURI
+    pub next_uri: bool,
+    /// 描述文件中 RenderingControl 的版本
+    pub rendering_control_version: u32,
+    /// 每首歌的时长（秒）
+    pub track_secs: u32,
+    /// 模拟时钟的倍速：现实中 1 秒播放多少秒
+    pub clock_speed: u32,
+    pub sink_protocols: String,
+    /// Play 先等这么久再生效（模拟起播慢的电视，期间保持 STOPPED）
+    pub play_delay: Duration,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        Self {
+            friendly_name: "Mock TV".to_string(),
+            control_without_slash: false,
+            reject_rel_time: false,
+            ignored_seeks: 0,
+            next_uri: true,
+            rendering_control_version: 1,
+            track_secs: 240,
+            clock_speed: 1,
+            sink_protocols: "http-get:*:video/mp4:*,http-get:*:audio/mpeg:*".to_string(),
+            play_delay: Duration::ZERO,
+        }
+    }
+}
+
+struct Track {
+    uri: String,
+    metadata: String,
+}
+
+struct MockState {
+    options: MockOptions,
+    udn: String,
+    transport_state: &'static str,
+    current: Option<Track>,
+    next: Option<Track>,
+    // 最近一次推进时钟时的进度与时刻
+    position: Duration,
+    position_at: Instant,
+    volume: u32,
+    mute: bool,
+    ignored_seeks: u32,
+    actions: Vec<String>,
+}
+
+type ActionResult = Result<Vec<(&'static str, String)>, (u16, &'static str)>;
+
+impl MockState {
+    // 推进模拟时钟：一首播完时有 NextURI 就切过去，否则停止
+    fn advance(&mut self) {
+        let now = Instant::now();
+        if self.transport_state == "PLAYING" {
+            self.position += now.duration_since(self.position_at) * self.options.clock_speed;
+            let track = Duration::from_secs(self.options.track_secs as u64);
+            while self.transport_state == "PLAYING" && self.position >= track {
+                match self.next.take() {
+                    Some(next) => {
+                        self.current = Some(next);
+                        self.position -= track;
+                    }
+                    None => {
+                        self.transport_state = "STOPPED";
+                        self.position = Duration::ZERO;
+                    }
+                }
+            }
+        }
+        self.position_at = now;
+    }
+
+    fn handle(&mut self, action: &str, args: &HashMap<String, String>) -> ActionResult {
+        let arg = |k: &str| args.get(k).cloned().unwrap_or_default();
+        let track_duration = format_time(self.options.track_secs);
+        match action {
+            "SetAVTransportURI" => {
+                self.current = Some(Track {
+                    uri: arg("CurrentURI"),
+                    metadata: arg("CurrentURIMetaData"),
+                });
+                self.next = None;
+                self.position = Duration::ZERO;
+                if self.transport_state != "PLAYING" {
+                    self.transport_state = "STOPPED";
+                }
+                Ok(vec![])
+            }
+            "SetNextAVTransportURI" if self.options.next_uri => {
+                self.next = Some(Track {
+                    uri: arg("NextURI"),
+                    metadata: arg("NextURIMetaData"),
+                });
+                Ok(vec![])
+            }
+            "Play" => {
+                if self.current.is_none() {
+                    return Err((701, "Transition not available"));
+                }
+                self.transport_state = "PLAYING";
+                Ok(vec![])
+            }
+            "Pause" => {
+                if self.transport_state == "PLAYING" {
+                    self.transport_state = "PAUSED_PLAYBACK";
+                }
+                Ok(vec![])
+            }
+            "Stop" => {
+                if self.current.is_some() {
+                    self.transport_state = "STOPPED";
+                }
+                self.position = Duration::ZERO;
+                Ok(vec![])
+            }
+            "Seek" => {
+                let unit = arg("Unit");
+                let target = arg("Target");
+                if self.options.reject_rel_time && unit == "REL_TIME" {
+                    return Err((710, "Seek mode not supported"));
+                }
+                let secs = match unit.as_str() {
+                    "REL_TIME" | "ABS_TIME" => parse_upnp_duration(&target).map(|d| d.as_secs()),
+                    "X_DLNA_REL_BYTE" => self.seek_bytes_to_secs(&target),
+                    _ => None,
+                };
+                let Some(secs) = secs.filter(|s| *s <= self.options.track_secs as u64) else {
+                    return Err((711, "Illegal seek target"));
+                };
+                if self.ignored_seeks > 0 {
+                    self.ignored_seeks -= 1;
+                } else {
+                    self.position = Duration::from_secs(secs);
+                }
+                Ok(vec![])
+            }
+            "GetPositionInfo" => {
+                let (uri, metadata) = self
+                    .current
+                    .as_ref()
+                    .map(|t| (t.uri.clone(), t.metadata.clone()))
+                    .unwrap_or_default();
+                let rel_time = format_time(self.position.as_secs() as u32);
+                Ok(vec![
+                    ("Track", if self.current.is_some() { "1" } else { "0" }.to_string()),
+                    ("TrackDuration", track_duration),
+                    ("TrackMetaData", metadata),
+                    ("TrackURI", uri),
+                    ("RelTime", rel_time.clone()),
+                    ("AbsTime", rel_time),
+                    ("RelCount", "2147483647".to_string()),
+                    ("AbsCount", "2147483647".to_string()),
+                ])
+            }
+            "GetTransportInfo" => Ok(vec![
+                ("CurrentTransportState", self.transport_state.to_string()),
+                ("CurrentTransportStatus", "OK".to_string()),
+                ("CurrentSpeed", "1".to_string()),
+            ]),
+            "GetMediaInfo" => {
+                let (current, next) = (self.current.as_ref(), self.next.as_ref());
+                Ok(vec![
+                    ("NrTracks", (current.is_some() as u32).to_string()),
+                    ("MediaDuration", track_duration),
+                    ("CurrentURI", current.map(|t| t.uri.clone()).unwrap_or_default()),
+                    ("CurrentURIMetaData", current.map(|t| t.metadata.clone()).unwrap_or_default()),
+                    ("NextURI", next.map(|t| t.uri.clone()).unwrap_or_default()),
+                    ("NextURIMetaData", next.map(|t| t.metadata.clone()).unwrap_or_default()),
+                    ("PlayMedium", "NETWORK".to_string()),
+                    ("RecordMedium", "NOT_IMPLEMENTED".to_string()),
+                    ("WriteStatus", "NOT_IMPLEMENTED".to_string()),
+                ])
+            }
+            "SetVolume" => {
+                let volume = arg("DesiredVolume").trim().parse::<u32>().map_err(|_| (402, "Invalid Args"))?;
+                self.volume = volume.min(100);
+                Ok(vec![])
+            }
+            "GetVolume" => Ok(vec![("CurrentVolume", self.volume.to_string())]),
+            "SetMute" => {
+                let mute = arg("DesiredMute");
+                self.mute = mute == "1" || mute.eq_ignore_ascii_case("true");
+                Ok(vec![])
+            }
+            "GetMute" => Ok(vec![("CurrentMute", (self.mute as u32).to_string())]),
+            "GetProtocolInfo" => Ok(vec![
+                ("Source", String::new()),
+                ("Sink", self.options.sink_protocols.clone()),
+            ]),
+            _ => Err((401, "Invalid Action")),
+        }
+    }
+
+    // 按当前曲目元数据中的文件大小把字节偏移折算成秒
+    fn seek_bytes_to_secs(&self, target: &str) -> Option<u64> {
+        let bytes: u64 = target.trim().parse().ok()?;
+        let size = parse_didl_lite(&self.current.as_ref()?.metadata)?.metadata.size?;
+        (size > 0).then(|| bytes * self.options.track_secs as u64 / size)
+    }
+}
+
+fn format_time(secs: u32) -> String {
+    format!("{}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60)
+}
+
+/// 在 127.0.0.1 的随机端口上运行的 MediaRenderer：提供设备描述和
+/// AVTransport / RenderingControl / ConnectionManager 的 SOAP 接口，
+/// 用模拟时钟推进播放进度。不支持 GENA 订阅，控制端会回退到轮询。
+pub(crate) struct MockRenderer {
+    pub location: String,
+    state: web::Data<Mutex<MockState>>,
+    handle: ServerHandle,
+}
+
+impl MockRenderer {
+    pub async fn start(options: MockOptions) -> Self {
+        let state = web::Data::new(Mutex::new(MockState {
+            udn: format!(
+                "uuid:ktv-mock-{}-{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+            ignored_seeks: options.ignored_seeks,
+            options,
+            transport_state: "NO_MEDIA_PRESENT",
+            current: None,
+            next: None,
+            position: Duration::ZERO,
+            position_at: Instant::now(),
+            volume: 20,
+            mute: false,
+            actions: Vec::new(),
+        }));
+
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .route("/description.xml", web::get().to(description))
+                .route("/upnp/control/{service}", web::post().to(control))
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .expect("模拟设备绑定端口失败");
+        let port = server.addrs()[0].port();
+        let server = server.run();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        Self {
+            location: format!("http://127.0.0.1:{}/description.xml", port),
+            state,
+            handle,
+        }
+    }
+
+    pub fn udn(&self) -> String {
+        self.state.lock().unwrap().udn.clone()
+    }
+
+    /// 设备收到的动作，Seek 带上 Unit（如 "Seek:ABS_TIME"）
+    pub fn actions(&self) -> Vec<String> {
+        self.state.lock().unwrap().actions.clone()
+    }
+
+    pub fn transport_state(&self) -> &'static str {
+        let mut state = self.state.lock().unwrap();
+        state.advance();
+        state.transport_state
+    }
+
+    pub fn volume(&self) -> u32 {
+        self.state.lock().unwrap().volume
+    }
+}
+
+impl Drop for MockRenderer {
+    fn drop(&mut self) {
+        // stop 会立即发出停止命令，不必等待完成
+        drop(self.handle.stop(false));
+    }
+}
+
+async fn description(state: web::Data<Mutex<MockState>>) -> HttpResponse {
+    let state = state.lock().unwrap();
+    let options = &state.options;
+    let service = |typ: &str, version: u32, control: &str| {
+        format!(
+            "<service><serviceType>urn:schemas-upnp-org:service:{typ}:{version}</serviceType>\
+             <serviceId>urn:upnp-org:serviceId:{typ}</serviceId><SCPDURL>/scpd/{typ}.xml</SCPDURL>\
+             <controlURL>{control}</controlURL><eventSubURL>/upnp/event/{typ}</eventSubURL></service>"
+        )
+    };
+    let avtransport_control = if options.control_without_slash {
+        "upnp/control/AVTransport"
+    } else {
+        "/upnp/control/AVTransport"
+    };
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0"><specVersion><major>1</major><minor>0</minor></specVersion>
+<device><deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType><friendlyName>{name}</friendlyName>
+<manufacturer>ktv-casting</manufacturer><modelName>MockRenderer</modelName><UDN>{udn}</UDN>
+<serviceList>{avt}{rc}{cm}</serviceList></device></root>"#,
+        name = xml_escape(&options.friendly_name),
+        udn = state.udn,
+        avt = service("AVTransport", 1, avtransport_control),
+        rc = service(
+            "RenderingControl",
+            options.rendering_control_version,
+            "/upnp/control/RenderingControl"
+        ),
+        cm = service("ConnectionManager", 1, "/upnp/control/ConnectionManager"),
+    );
+    HttpResponse::Ok()
+        .content_type(r#"text/xml; charset="utf-8""#)
+        .body(body)
+}
+
+async fn control(req: HttpRequest, body: String, state: web::Data<Mutex<MockState>>) -> HttpResponse {
+    let soap_action = req
+        .headers()
+        .get("SOAPAction")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .trim()
+        .trim_matches('"')
+        .to_string();
+    let Some((service_type, action)) = soap_action.split_once('#') else {
+        return soap_fault(401, "Invalid Action");
+    };
+    let args: HashMap<String, String> = with_xml(&body, |doc| {
+        find_element(doc.root(), "Body")
+            .and_then(|b| b.children().find(|n| n.is_element()))
+            .map(|request| {
+                request
+                    .children()
+                    .filter(|n| n.is_element())
+                    .map(|n| (n.tag_name().name().to_string(), element_text(n, doc.input_text())))
+                    .collect()
+            })
+    })
+    .flatten()
+    .unwrap_or_default();
+
+    if action == "Play" {
+        let delay = state.lock().unwrap().options.play_delay;
+        tokio::time::sleep(delay).await;
+    }
+    let mut state = state.lock().unwrap();
+    state.advance();
+    state.actions.push(match action {
+        "Seek" => format!("Seek:{}", args.get("Unit").map(String::as_str).unwrap_or_default()),
+        _ => action.to_string(),
+    });
+    match state.handle(action, &args) {
+        Ok(outputs) => soap_response(service_type, action, &outputs),
+        Err((code, description)) => soap_fault(code, description),
+    }
+}
+
+fn soap_response(service_type: &str, action: &str, outputs: &[(&str, String)]) -> HttpResponse {
+    let args: String = outputs
+        .iter()
+        .map(|(k, v)| format!("<{k}>{}</{k}>", xml_escape(v)))
+        .collect();
+    HttpResponse::Ok()
+        .content_type(r#"text/xml; charset="utf-8""#)
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><u:{action}Response xmlns:u="{service_type}">{args}</u:{action}Response></s:Body></s:Envelope>"#
+        ))
+}
+
+fn soap_fault(code: u16, description: &str) -> HttpResponse {
+    HttpResponse::InternalServerError()
+        .content_type(r#"text/xml; charset="utf-8""#)
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/"><s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{code}</errorCode><errorDescription>{description}</errorDescription></UPnPError></detail></s:Fault></s:Body></s:Envelope>"#
+        ))
+}
+
+/// 测试中学到的设备配置写到临时目录，不污染工作目录
+pub(crate) fn use_temp_data_dir() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("ktv-casting-test-{}", std::process::id()));
+        let _ = std::fs::create_dir_all(&dir);
+        crate::quirks::store().load_from(&dir);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EngineEvent;
+    use crate::dlna_controller::{DlnaController, DlnaRenderer, MediaMetadata, TransportState};
+    use crate::playback_monitor::{self, PositionTracker};
+    use crate::renderer_group::RendererGroup;
+    use std::sync::Arc;
+    use tokio::sync::broadcast;
+
+    const LOCALHOST: [u8; 4] = [127, 0, 0, 1];
+
+    async fn next_matching(rx: &mut broadcast::Receiver<EngineEvent>, want: impl Fn(&EngineEvent) -> bool) {
+        loop {
+            match rx.recv().await {
+                Ok(event) if want(&event) => return,
+                Ok(_) => {}
+                Err(e) => panic!("引擎事件通道异常: {}", e),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_renderer_compat_and_seek_fallback() {
+        use_temp_data_dir();
+        let mock = MockRenderer::start(MockOptions {
+            control_without_slash: true,
+            rendering_control_version: 2,
+            reject_rel_time: true,
+            ignored_seeks: 1,
+            ..Default::default()
+        })
+        .await;
+        let controller = DlnaController::new();
+        let device = crate::load_device(&controller, &mock.location).await.unwrap();
+        assert_eq!(device.friendly_name, "Mock TV");
+        assert_eq!(device.device.udn(), mock.udn());
+        assert_eq!(device.sink_protocols.len(), 2);
+
+        // controlURL 缺少前导斜杠，由 avtransport_action_compat 修正地址
+        let metadata = MediaMetadata {
+            title: Some("晴天 & 七里香".to_string()),
+            ..Default::default()
+        };
+        controller
+            .set_avtransport_uri(&device, "song-a", &metadata, None, LOCALHOST.into(), 8080)
+            .await
+            .unwrap();
+        controller.play(&device).await.unwrap();
+        let info = controller.get_transport_info(&device).await.unwrap();
+        assert_eq!(info.state, TransportState::Playing);
+        let media = controller.get_media_info(&device).await.unwrap();
+        assert_eq!(media.current_uri, "http://127.0.0.1:8080/song-a");
+        let item = parse_didl_lite(&media.current_uri_metadata).unwrap();
+        assert_eq!(item.metadata.title.as_deref(), Some("晴天 & 七里香"));
+
+        // RenderingControl:2 同样可用
+        controller.set_volume(&device, 30).await.unwrap();
+        assert_eq!(mock.volume(), 30);
+        assert_eq!(controller.get_volume(&device).await.unwrap(), 30);
+
+        // REL_TIME 被拒绝后改用 ABS_TIME；第一次 Seek 未生效，校验后重试
+        controller.seek(&device, 5).await.unwrap();
+        let seeks: Vec<String> = mock
+            .actions()
+            .into_iter()
+            .filter(|a| a.starts_with("Seek"))
+            .collect();
+        assert_eq!(seeks, ["Seek:REL_TIME", "Seek:ABS_TIME", "Seek:ABS_TIME"]);
+        assert_eq!(controller.quirks(&device).seek_units, ["ABS_TIME"]);
+    }
+
+    #[tokio::test]
+    async fn test_mock_renderer_auto_next() {
+        use_temp_data_dir();
+        let mock = MockRenderer::start(MockOptions {
+            track_secs: 2,
+            ..Default::default()
+        })
+        .await;
+        let controller = DlnaController::new();
+        let device = crate::load_device(&controller, &mock.location).await.unwrap();
+        let group = Arc::new(RendererGroup::new(Arc::new(DlnaRenderer::new(
+            controller.clone(),
+            device.clone(),
+        ))));
+        let tracker = Arc::new(PositionTracker::new());
+        let (engine_tx, mut engine_rx) = broadcast::channel(64);
+        let (_renderer_tx, renderer_rx) = broadcast::channel(16);
+        // 模拟设备不支持订阅，监控回退到每秒轮询
+        tokio::spawn(playback_monitor::run(
+            group,
+            tracker,
+            "http://127.0.0.1:9".to_string(),
+            renderer_rx,
+            engine_tx,
+        ));
+
+        let metadata = MediaMetadata::default();
+        controller
+            .set_avtransport_uri(&device, "song-a", &metadata, None, LOCALHOST.into(), 8080)
+            .await
+            .unwrap();
+        controller.play(&device).await.unwrap();
+        controller
+            .set_next_avtransport_uri(&device, "song-b", &metadata, None, LOCALHOST.into(), 8080)
+            .await
+            .unwrap();
+
+        // song-a 播完后设备自动切到 NextURI，再播完后停止
+        let changed = next_matching(&mut engine_rx, |e| {
+            matches!(e, EngineEvent::TrackChanged(uri) if uri.ends_with("song-b"))
+        });
+        tokio::time::timeout(Duration::from_secs(8), changed).await.unwrap();
+        let ended = next_matching(&mut engine_rx, |e| matches!(e, EngineEvent::TrackEnded));
+        tokio::time::timeout(Duration::from_secs(8), ended).await.unwrap();
+        assert_eq!(mock.transport_state(), "STOPPED");
+        assert_eq!(mock.actions().iter().filter(|a| *a == "Play").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_mock_renderer_switch_with_fade() {
+        use crate::SharedState;
+        use crate::duration_resolver::DurationResolver;
+        use crate::gapless::GaplessPreloader;
+        use crate::media_server::UpstreamInfo;
+        use crate::playlist_manager::PlaylistManager;
+        use crate::volume_policy::{self, VolumePolicy};
+
+        use_temp_data_dir();
+        let mock = MockRenderer::start(MockOptions {
+            play_delay: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .await;
+        let controller = DlnaController::new();
+        let device = crate::load_device(&controller, &mock.location).await.unwrap();
+        let group = Arc::new(RendererGroup::new(Arc::new(DlnaRenderer::new(
+            controller.clone(),
+            device.clone(),
+        ))));
+        let tracker = Arc::new(PositionTracker::new());
+        let (engine_tx, mut engine_rx) = broadcast::channel(256);
+        let (renderer_tx, renderer_rx) = broadcast::channel(16);
+        tokio::spawn(playback_monitor::run(
+            group.clone(),
+            tracker.clone(),
+            "http://127.0.0.1:9".to_string(),
+            renderer_rx,
+            engine_tx,
+        ));
+
+        let shared_state = web::Data::new(SharedState {
+            duration_cache: Arc::new(tokio::sync::Mutex::new(DurationResolver::new())),
+            auth_sessions: Default::default(),
+            renderer_events: renderer_tx,
+            upstream_info: Default::default(),
+        });
+        // 上游格式已知，不必探测
+        for song in ["song-a", "song-b"] {
+            shared_state.upstream_info.lock().await.insert(
+                song.to_string(),
+                UpstreamInfo {
+                    content_type: "video/mp4".to_string(),
+                    content_length: None,
+                },
+            );
+        }
+        let preloader = GaplessPreloader::new(
+            group,
+            PlaylistManager::new("http://127.0.0.1:9", "test".to_string()),
+            shared_state,
+            LOCALHOST.into(),
+            8080,
+            tracker,
+        );
+
+        let metadata = MediaMetadata::default();
+        controller
+            .set_avtransport_uri(&device, "song-a", &metadata, None, LOCALHOST.into(), 8080)
+            .await
+            .unwrap();
+        controller.play(&device).await.unwrap();
+        let playing = next_matching(&mut engine_rx, |e| {
+            matches!(e, EngineEvent::TransportStateChanged(TransportState::Playing))
+        });
+        tokio::time::timeout(Duration::from_secs(8), playing).await.unwrap();
+
+        // 淡出 5 秒后才 Stop，Play 又慢 2 秒：整个切歌过程中的 STOPPED 都不能算播放结束
+        volume_policy::set_policy(Some(VolumePolicy {
+            fade_ms: 5000,
+            ..Default::default()
+        }));
+        preloader.switch_to("song-b", None).await;
+        volume_policy::set_policy(None);
+        // 等监控再轮询一次，确认切歌后的状态已被处理
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        let mut saw_stopped = false;
+        while let Ok(event) = engine_rx.try_recv() {
+            assert!(!matches!(event, EngineEvent::TrackEnded), "切歌被误判为播放结束");
+            saw_stopped |= matches!(event, EngineEvent::TransportStateChanged(TransportState::Stopped));
+        }
+        assert!(saw_stopped);
+        assert_eq!(mock.transport_state(), "PLAYING");
+        assert_eq!(mock.volume(), 20);
+        assert_eq!(preloader.current().await.as_deref(), Some("song-b"));
+    }
+}
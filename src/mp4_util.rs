@@ -1,10 +1,13 @@
-use anyhow::{Result, anyhow};
+use crate::error::{Error, Result};
 use reqwest::Client;
 use std::io::Cursor;
 use std::time::Duration;
 
 pub async fn get_mp4_duration(url: &str) -> Result<Duration> {
-    let client = Client::builder().use_rustls_tls().build()?;
+    let client = Client::builder()
+        .use_rustls_tls()
+        .build()
+        .map_err(|e| Error::Network(e.to_string()))?;
 
     // 1. 先尝试获取前 2MB 数据，这通常足以包含大部分视频的 moov 块
     let response = client.get(url)
@@ -12,13 +15,14 @@ pub async fn get_mp4_duration(url: &str) -> Result<Duration> {
         .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/118.0.0.0 Safari/537.36")
         .header("Referer", "https://www.bilibili.com/")
         .send()
-        .await?;
+        .await
+        .map_err(|e| Error::upstream(None, format!("Failed to fetch video header: {}", e)))?;
 
     if !response.status().is_success() && response.status() != reqwest::StatusCode::PARTIAL_CONTENT
     {
-        return Err(anyhow!(
-            "Failed to fetch video header: status {}",
-            response.status()
+        return Err(Error::upstream(
+            None,
+            format!("Failed to fetch video header: status {}", response.status()),
         ));
     }
 
@@ -28,7 +32,7 @@ pub async fn get_mp4_duration(url: &str) -> Result<Duration> {
         .headers()
         .get("content-range")
         .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.split('/').last())
+        .and_then(|s| s.split('/').next_back())
         .and_then(|s| s.parse::<u64>().ok())
         .or_else(|| {
             response
@@ -39,7 +43,10 @@ pub async fn get_mp4_duration(url: &str) -> Result<Duration> {
         })
         .unwrap_or(2097152); // 回退值
 
-    let bytes = response.bytes().await?;
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| Error::upstream(None, format!("Failed to read video header: {}", e)))?;
     let mut cursor = Cursor::new(&bytes);
 
     // 关键点：传入总文件大小 total_size，而不是缓冲区大小 bytes.len()
@@ -50,11 +57,10 @@ pub async fn get_mp4_duration(url: &str) -> Result<Duration> {
         Err(e) => {
             // 如果 2MB 还是不够（例如 moov 非常大），且报错是 UnexpectedEof，可以考虑在这里增加重试逻辑
             // 但对于一般 B 站视频，2MB 配合正确的 total_size 参数应该足够解决问题。
-            Err(anyhow!(
+            Err(Error::Parse(format!(
                 "Failed to parse MP4 header (total_size={}): {}",
-                total_size,
-                e
-            ))
+                total_size, e
+            )))
         }
     }
 }
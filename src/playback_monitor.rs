@@ -0,0 +1,298 @@
+use crate::EngineEvent;
+use crate::dlna_controller::TransportState;
+use crate::dlna_events::{RendererEvent, StateChange};
+use crate::renderer::{Renderer, Subscription};
+use crate::renderer_group::{RendererGroup, RendererHealth};
+use log::{debug, info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// 订阅生效时，仅每隔这么久用 GetPositionInfo 校准一次本地时钟
+const EVENTED_RESYNC_INTERVAL: Duration = Duration::from_secs(15);
+// 设备不支持事件时的轮询间隔（与原先 CLI/Android 的 1 秒一致）
+const POLLING_INTERVAL: Duration = Duration::from_secs(1);
+// 轮询模式下进度走势正常时，每隔这么多次才用 GetTransportInfo 确认一次状态
+const STATE_POLL_EVERY: u32 = 5;
+
+/// 本地播放时钟：记录最近一次校准的进度，播放中按流逝时间外推，
+/// 这样查询进度不必每次都向设备发 SOAP。
+pub struct PositionTracker {
+    inner: Mutex<TrackerState>,
+}
+
+struct TrackerState {
+    position_secs: u32,
+    total_secs: Option<u32>,
+    synced_at: Instant,
+    state: Option<TransportState>,
+    synced_once: bool,
+    // 引擎正在主动切歌（淡出 → Stop → SetAVTransportURI → Play），期间的 STOPPED 不算播放结束
+    switching: bool,
+}
+
+impl Default for PositionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PositionTracker {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(TrackerState {
+                position_secs: 0,
+                total_secs: None,
+                synced_at: Instant::now(),
+                state: None,
+                synced_once: false,
+                switching: false,
+            }),
+        }
+    }
+
+    /// 用设备返回的进度校准；total_secs 为设备报告的 TrackDuration
+    pub fn sync(&self, position_secs: u32, total_secs: Option<u32>) {
+        let mut s = self.inner.lock().unwrap();
+        s.position_secs = position_secs;
+        s.total_secs = total_secs;
+        s.synced_at = Instant::now();
+        s.synced_once = true;
+    }
+
+    /// 标记引擎开始主动切歌，直到 `end_switch` 之前的停止状态都不视为歌曲播放结束
+    pub fn begin_switch(&self) {
+        let mut s = self.inner.lock().unwrap();
+        s.switching = true;
+        s.position_secs = 0;
+        s.synced_at = Instant::now();
+    }
+
+    /// 主动切歌完成（Play 已返回，无论成败）
+    pub fn end_switch(&self) {
+        self.inner.lock().unwrap().switching = false;
+    }
+
+    /// 设备自动切到下一首，进度从头计
+    pub fn restart(&self) {
+        let mut s = self.inner.lock().unwrap();
+        s.position_secs = 0;
+        s.synced_at = Instant::now();
+    }
+
+    /// 更新状态；若是 PLAYING → STOPPED/NO_MEDIA_PRESENT 且不是引擎主动切歌导致的，返回 true
+    pub fn set_state(&self, state: TransportState) -> bool {
+        let mut s = self.inner.lock().unwrap();
+        let ended = s.state == Some(TransportState::Playing)
+            && matches!(
+                state,
+                TransportState::Stopped | TransportState::NoMediaPresent
+            )
+            && !s.switching;
+
+        // 先把外推的进度固化下来，再切换状态
+        s.position_secs = current_secs(&s);
+        s.synced_at = Instant::now();
+        if state == TransportState::Stopped || state == TransportState::NoMediaPresent {
+            s.position_secs = 0;
+        }
+        s.state = Some(state);
+        ended
+    }
+
+    pub fn state(&self) -> Option<TransportState> {
+        self.inner.lock().unwrap().state.clone()
+    }
+
+    /// 返回 (当前秒, 设备报告的总秒)；尚未校准过时返回 None
+    pub fn snapshot(&self) -> Option<(u32, Option<u32>)> {
+        let s = self.inner.lock().unwrap();
+        if !s.synced_once {
+            return None;
+        }
+        Some((current_secs(&s), s.total_secs))
+    }
+
+    /// 在 `at` 时刻的进度（设备掉线后用最后一次成功通信的时间估计断点）
+    pub fn position_at(&self, at: Instant) -> Option<u32> {
+        let s = self.inner.lock().unwrap();
+        if !s.synced_once {
+            return None;
+        }
+        if s.state == Some(TransportState::Playing) {
+            Some(s.position_secs + at.saturating_duration_since(s.synced_at).as_secs() as u32)
+        } else {
+            Some(s.position_secs)
+        }
+    }
+}
+
+fn current_secs(s: &TrackerState) -> u32 {
+    if s.state == Some(TransportState::Playing) {
+        s.position_secs + s.synced_at.elapsed().as_secs() as u32
+    } else {
+        s.position_secs
+    }
+}
+
+// retry 为轮询期间的定期重试，失败时不再重复告警
+async fn subscribe(device: &dyn Renderer, callback_base: &str, retry: bool) -> Option<Box<dyn Subscription>> {
+    match device.subscribe(callback_base).await {
+        Ok(s) => {
+            info!("设备支持事件订阅，进度查询改为事件驱动");
+            Some(s)
+        }
+        Err(e) if retry => {
+            debug!("重新订阅事件失败，继续轮询: {}", e);
+            None
+        }
+        Err(e) => {
+            warn!("设备不支持事件订阅，回退到轮询: {}", e);
+            None
+        }
+    }
+}
+
+/// 监听主设备状态：优先使用 GENA 事件，设备拒绝订阅时回退到每秒轮询 GetPositionInfo。
+/// 查询结果计入投屏组的健康状态；主设备重连（地址变化）后重新订阅，
+/// 轮询期间（订阅失效，如设备重启）也定期尝试重新订阅。
+pub(crate) async fn run(
+    group: Arc<RendererGroup>,
+    tracker: Arc<PositionTracker>,
+    callback_base: String,
+    mut renderer_events: broadcast::Receiver<RendererEvent>,
+    engine_events: broadcast::Sender<EngineEvent>,
+) {
+    let mut device = group.primary();
+    let mut subscriber = subscribe(&*device, &callback_base, false).await;
+
+    let mut last_resync: Option<Instant> = None;
+    let mut last_subscribe = Instant::now();
+    let mut last_track_uri = String::new();
+    let mut last_position: Option<u32> = None;
+    let mut polls_since_state = 0u32;
+    let mut ticker = tokio::time::interval(POLLING_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            event = renderer_events.recv() => {
+                match event {
+                    // 只处理当前主设备订阅的通知；旧主设备（重连、换主设备前）迟到的通知丢弃
+                    Ok(event) if subscriber.as_ref().is_some_and(|s| s.owns(&event.sid)) => {
+                        apply_event(&event, &tracker, &engine_events)
+                    }
+                    Ok(event) => debug!("忽略不属于当前订阅的事件 sid={}", event.sid),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        debug!("事件处理落后，丢弃 {} 条", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            _ = ticker.tick() => {
+                let primary = group.primary();
+                if primary.id() != device.id() {
+                    info!("主设备地址已变化，重新订阅事件: {}", primary.id());
+                    device = primary;
+                    // 旧订阅随 Drop 停止续订
+                    subscriber = subscribe(&*device, &callback_base, false).await;
+                    last_subscribe = Instant::now();
+                    last_resync = None;
+                    last_track_uri.clear();
+                    last_position = None;
+                }
+                let mut evented = subscriber.as_ref().is_some_and(|s| s.is_active());
+                if !evented && last_subscribe.elapsed() >= EVENTED_RESYNC_INTERVAL {
+                    last_subscribe = Instant::now();
+                    subscriber = subscribe(&*device, &callback_base, true).await;
+                    evented = subscriber.is_some();
+                }
+                // 设备出现失败后每秒探测，尽快判定离线
+                let healthy = group.health(device.id()) == Some(RendererHealth::Healthy);
+                let due = !evented
+                    || !healthy
+                    || last_resync.is_none_or(|t| t.elapsed() >= EVENTED_RESYNC_INTERVAL);
+                if !due {
+                    continue;
+                }
+                last_resync = Some(Instant::now());
+                let result = device.position().await;
+                group.record(device.id(), result.as_ref().map(|_| ()));
+                let track = match result {
+                    Ok(track) => track,
+                    Err(e) => {
+                        debug!("查询播放进度失败: {}", e);
+                        // 设备没响应时不再查询状态，以免把掉线误判为播放结束
+                        continue;
+                    }
+                };
+                tracker.sync(track.position_secs, track.duration_secs);
+                let advanced = last_position.is_some_and(|p| track.position_secs > p);
+                last_position = Some(track.position_secs);
+                // 轮询模式下没有 CurrentTrackURI 事件，靠 TrackURI 的变化识别自动切歌
+                let track_changed = !track.track_uri.is_empty() && track.track_uri != last_track_uri;
+                if track_changed {
+                    last_track_uri = track.track_uri.clone();
+                    let _ = engine_events.send(EngineEvent::TrackChanged(track.track_uri));
+                }
+                // 轮询模式下靠 GetTransportInfo 获得状态变化，但只在进度走势与已知状态不符
+                // （播放中进度不动、停着进度在走）、曲目变化时查询，另每隔几次兜底一次；
+                // 事件模式下每次校准都顺带兜底漏掉的通知
+                polls_since_state += 1;
+                let known = tracker.state();
+                let check_state = evented
+                    || track_changed
+                    || known.is_none()
+                    || advanced != (known == Some(TransportState::Playing))
+                    || polls_since_state >= STATE_POLL_EVERY;
+                if !check_state {
+                    continue;
+                }
+                polls_since_state = 0;
+                match device.transport_state().await {
+                    Ok(state) => observe_state(state, &tracker, &engine_events),
+                    Err(e) => debug!("查询传输状态失败: {}", e),
+                }
+            }
+        }
+    }
+}
+
+fn apply_event(
+    event: &RendererEvent,
+    tracker: &PositionTracker,
+    engine_events: &broadcast::Sender<EngineEvent>,
+) {
+    for change in &event.changes {
+        let engine_event = match change {
+            StateChange::TransportState(state) => {
+                observe_state(state.clone(), tracker, engine_events);
+                continue;
+            }
+            StateChange::Volume(v) => EngineEvent::VolumeChanged(*v),
+            StateChange::Mute(m) => EngineEvent::MuteChanged(*m),
+            StateChange::CurrentTrackUri(uri) => EngineEvent::TrackChanged(uri.clone()),
+            _ => continue,
+        };
+        debug!("引擎事件: {:?}", engine_event);
+        let _ = engine_events.send(engine_event);
+    }
+}
+
+/// 处理设备状态变化，并在自然播放结束时上报 TrackEnded
+fn observe_state(
+    state: TransportState,
+    tracker: &PositionTracker,
+    engine_events: &broadcast::Sender<EngineEvent>,
+) {
+    if tracker.state().as_ref() == Some(&state) {
+        return;
+    }
+    let ended = tracker.set_state(state.clone());
+    debug!("设备状态变化: {}", state);
+    let _ = engine_events.send(EngineEvent::TransportStateChanged(state));
+    if ended {
+        info!("设备从播放转为停止，判定歌曲播放结束");
+        let _ = engine_events.send(EngineEvent::TrackEnded);
+    }
+}
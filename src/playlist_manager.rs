@@ -1,18 +1,68 @@
+use crate::error::{Error, Result};
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use reqwest::Client;
 use serde_json::json;
+use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use std::{env, future::Future};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, watch};
 #[cfg(test)]
 use tokio::time::sleep;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
 use url::Url;
 
+// 显式指定的同步模式（如恢复上次会话时），未设置时读取环境变量 KTV_SYNC_MODE
+static SYNC_MODE: RwLock<Option<SyncMode>> = RwLock::new(None);
+
+/// 歌单同步方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMode {
+    /// WebSocket 推送（默认）
+    #[default]
+    Ws,
+    /// 每 300ms 轮询 songListInfo
+    Polling,
+}
+
+impl SyncMode {
+    /// "WS" 或 "POLLING"（不区分大小写）
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "WS" => Some(Self::Ws),
+            "POLLING" => Some(Self::Polling),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ws => "WS",
+            Self::Polling => "POLLING",
+        }
+    }
+}
+
+/// 指定歌单同步方式，None 表示按环境变量决定
+pub fn set_sync_mode(mode: Option<SyncMode>) {
+    if let Ok(mut guard) = SYNC_MODE.write() {
+        *guard = mode;
+    }
+}
+
+/// 当前生效的同步方式：显式指定 > 环境变量 KTV_SYNC_MODE > WS
+pub fn sync_mode() -> SyncMode {
+    SYNC_MODE
+        .read()
+        .ok()
+        .and_then(|m| *m)
+        .or_else(|| env::var("KTV_SYNC_MODE").ok().and_then(|v| SyncMode::parse(&v)))
+        .unwrap_or_default()
+}
+
 #[derive(Clone)]
 pub struct PlaylistManager {
     url: String,
@@ -21,6 +71,20 @@ pub struct PlaylistManager {
     hash: Arc<Mutex<Option<String>>>,
     song_playing: Arc<Mutex<Option<String>>>,
     song_title: Arc<Mutex<Option<String>>>,
+    // 队列中的下一首（list.queued[0]），用于 SetNextAVTransportURI 预加载
+    song_next: Arc<watch::Sender<Option<String>>>,
+    // 排队中的全部歌曲（list.queued，按顺序），用于预先下载到本地缓存
+    song_queue: Arc<watch::Sender<Vec<String>>>,
+    // 正在演唱与排队中的歌曲信息，key 为代理路径，用于投屏元数据
+    songs: Arc<Mutex<HashMap<String, SongInfo>>>,
+}
+
+/// 歌单中一首歌的展示信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongInfo {
+    pub title: String,
+    /// 点歌人
+    pub added_by: Option<String>,
 }
 
 impl PlaylistManager {
@@ -37,6 +101,9 @@ impl PlaylistManager {
             hash: Arc::new(Mutex::new(None)),
             song_playing: Arc::new(Mutex::new(None)),
             song_title: Arc::new(Mutex::new(None)),
+            song_next: Arc::new(watch::channel(None).0),
+            song_queue: Arc::new(watch::channel(Vec::new()).0),
+            songs: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -47,7 +114,7 @@ impl PlaylistManager {
     //   hash: string
     // }
     // Song { id, title, url, addedBy? }
-    async fn fetch_playlist(&self) -> Result<Option<String>, String> {
+    async fn fetch_playlist(&self) -> Result<Option<String>> {
         let last_hash = self
             .hash
             .lock()
@@ -67,15 +134,15 @@ impl PlaylistManager {
             .get(&url)
             .send()
             .await
-            .map_err(|e| format!("发送请求失败: {}", e))?;
+            .map_err(|e| Error::PlaylistServer(format!("发送请求失败: {}", e)))?;
         if !resp.status().is_success() {
-            return Err(format!("请求失败，状态码: {}", resp.status()));
+            return Err(Error::PlaylistServer(format!("请求失败，状态码: {}", resp.status())));
         }
 
         let resp_json: serde_json::Value = resp
             .json()
             .await
-            .map_err(|e| format!("解析JSON失败: {}", e))?;
+            .map_err(|e| Error::PlaylistServer(format!("解析JSON失败: {}", e)))?;
         let changed: bool = resp_json["changed"].as_bool().unwrap_or(false);
 
         if !changed {
@@ -110,28 +177,72 @@ impl PlaylistManager {
             .as_str()
             .map(extract_bv_function);
 
+        // 下一首：list.queued[0].url
+        let next_url: Option<String> = resp_json["list"]["queued"][0]["url"]
+            .as_str()
+            .map(extract_bv_function);
+
+        let queued_urls: Vec<String> = resp_json["list"]["queued"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|song| song["url"].as_str().map(extract_bv_function))
+            .collect();
+
+        let songs: HashMap<String, SongInfo> = std::iter::once(&resp_json["list"]["singing"])
+            .chain(resp_json["list"]["queued"].as_array().into_iter().flatten())
+            .filter_map(|song| {
+                let key = extract_bv_function(song["url"].as_str()?);
+                let info = SongInfo {
+                    title: song["title"].as_str()?.to_string(),
+                    added_by: song["addedBy"]
+                        .as_str()
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_string),
+                };
+                Some((key, info))
+            })
+            .collect();
+
         info!("新的hash: {}", new_hash);
 
         // 更新状态
         *self.song_playing.lock().await = singing_url.clone();
         *self.song_title.lock().await = singing_title; // 更新标题
         *self.hash.lock().await = Some(new_hash);
+        *self.songs.lock().await = songs;
+        self.song_next.send_if_modified(|next| {
+            let changed = *next != next_url;
+            *next = next_url;
+            changed
+        });
+        self.song_queue.send_if_modified(|queue| {
+            let changed = *queue != queued_urls;
+            *queue = queued_urls;
+            changed
+        });
 
         Ok(singing_url)
     }
 
-    // 根据环境变量切换同步驱动（WS / POLLING）
-    // 环境变量：KTV_SYNC_MODE = "WS" 或 "POLLING"（不区分大小写），默认为 WS
+    pub fn base_url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    // 根据同步模式切换同步驱动（WS / POLLING），见 sync_mode()
     pub fn start_sync<F>(&self, f_on_update: F)
     where
         F: Fn(String) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + 'static,
     {
-        let mode = env::var("KTV_SYNC_MODE").unwrap_or_else(|_| "WS".to_string());
-        info!("播放列表同步模式: {}", mode);
-        if mode.to_uppercase() != "POLLING" {
-            self.start_ws_update(f_on_update);
-        } else {
-            self.start_periodic_update(f_on_update);
+        let mode = sync_mode();
+        info!("播放列表同步模式: {}", mode.as_str());
+        match mode {
+            SyncMode::Ws => self.start_ws_update(f_on_update),
+            SyncMode::Polling => self.start_periodic_update(f_on_update),
         }
     }
 
@@ -339,13 +450,13 @@ impl PlaylistManager {
                                     if incoming_hash == current_hash { continue; }
 
                                     debug!("[WS UPDATE]: {} -> {}", current_hash, incoming_hash);
-                                    if let Ok(song_playing_new) = self_clone.fetch_playlist().await {
-                                        if song_playing_new != song_playing_cached {
-                                            if let Some(url) = song_playing_new.clone() {
-                                                f_on_update(url).await;
-                                            }
-                                            song_playing_cached = song_playing_new;
+                                    if let Ok(song_playing_new) = self_clone.fetch_playlist().await
+                                        && song_playing_new != song_playing_cached
+                                    {
+                                        if let Some(url) = song_playing_new.clone() {
+                                            f_on_update(url).await;
                                         }
+                                        song_playing_cached = song_playing_new;
                                     }
                                 }
                                 Message::Ping(p) => {
@@ -390,7 +501,7 @@ impl PlaylistManager {
         });
     }
 
-    pub async fn next_song(&mut self) -> Result<(), String> {
+    pub async fn next_song(&mut self) -> Result<()> {
         let url = format!("{}/api/nextSong?roomId={}", self.url, self.room_id);
         let temp_hash = self
             .hash
@@ -404,14 +515,14 @@ impl PlaylistManager {
             .json(&json!({"idArrayHash": temp_hash}))
             .send()
             .await
-            .map_err(|e| format!("发送请求失败: {}", e))?;
+            .map_err(|e| Error::PlaylistServer(format!("发送请求失败: {}", e)))?;
         let resp_json: serde_json::Value = resp
             .json()
             .await
-            .map_err(|e| format!("解析JSON失败: {}", e))?;
+            .map_err(|e| Error::PlaylistServer(format!("解析JSON失败: {}", e)))?;
 
         if !resp_json["success"].as_bool().unwrap_or(false) {
-            return Err(format!("请求失败: {}", resp_json));
+            return Err(Error::PlaylistServer(format!("请求失败: {}", resp_json)));
         }
         self.fetch_playlist().await?;
 
@@ -425,6 +536,21 @@ impl PlaylistManager {
     pub async fn get_song_title(&self) -> Option<String> {
         self.song_title.lock().await.clone()
     }
+
+    /// 歌单中某首歌（按代理路径）的标题与点歌人；歌曲已不在歌单中时返回 None
+    pub async fn song_info(&self, key: &str) -> Option<SongInfo> {
+        self.songs.lock().await.get(key).cloned()
+    }
+
+    /// 订阅队列中下一首歌曲的变化
+    pub fn subscribe_song_next(&self) -> watch::Receiver<Option<String>> {
+        self.song_next.subscribe()
+    }
+
+    /// 订阅排队歌曲（代理路径，按播放顺序）的变化
+    pub fn subscribe_queue(&self) -> watch::Receiver<Vec<String>> {
+        self.song_queue.subscribe()
+    }
 }
 
 #[tokio::test]
@@ -0,0 +1,343 @@
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+// 运行时学到的设备配置（自动写入）
+const LEARNED_FILE: &str = "renderer_profiles.json";
+// 手写的设备兼容配置（随程序分发或由用户编辑，只读）
+const HAND_WRITTEN_FILE: &str = "renderer_quirks.json";
+
+/// AVTransport 控制动作的发送方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlEndpoint {
+    /// rupnp 按设备描述里的 controlURL 发送
+    Native,
+    /// 直接 POST 到该路径（或完整 URL），跳过原生尝试与路径猜测
+    Path(String),
+}
+
+impl ControlEndpoint {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "" => None,
+            v if v.eq_ignore_ascii_case("native") => Some(Self::Native),
+            v => Some(Self::Path(v.to_string())),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Native => "native",
+            Self::Path(p) => p,
+        }
+    }
+}
+
+/// SetAVTransportURI 中 CurrentURIMetaData 的写法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataStyle {
+    /// 完整的 DIDL-Lite（默认）
+    #[default]
+    Didl,
+    /// 只保留 title / res / class，部分设备遇到多余字段会拒绝
+    Minimal,
+    /// 不发送元数据，部分设备解析 DIDL-Lite 出错时只能这样
+    Empty,
+}
+
+impl MetadataStyle {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "didl" => Some(Self::Didl),
+            "minimal" => Some(Self::Minimal),
+            "empty" | "none" => Some(Self::Empty),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Didl => "didl",
+            Self::Minimal => "minimal",
+            Self::Empty => "empty",
+        }
+    }
+}
+
+/// 单台设备（或某一型号）的兼容配置，未填写的字段沿用默认行为
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuirkProfile {
+    /// 设备 UDN，如 "uuid:xxxx"；手写配置可以改用厂商/型号匹配
+    pub udn: Option<String>,
+    /// 厂商名包含该字符串（不区分大小写）时匹配
+    pub manufacturer: Option<String>,
+    /// 型号名包含该字符串（不区分大小写）时匹配
+    pub model_name: Option<String>,
+    /// 仅用于阅读配置文件时辨认设备
+    pub friendly_name: Option<String>,
+    pub control: Option<ControlEndpoint>,
+    pub metadata_style: Option<MetadataStyle>,
+    /// 切歌时是否先发送 Stop，默认发送
+    pub stop_before_set: Option<bool>,
+    /// 设备支持的 Seek Unit，按优先级排列，如 ["REL_TIME", "ABS_TIME"]
+    pub seek_units: Vec<String>,
+    /// 该设备允许的最大音量（0~100），与音量策略中的上限取较小值
+    pub max_volume: Option<u32>,
+}
+
+impl QuirkProfile {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let text = |k: &str| value.get(k).and_then(Value::as_str).map(str::to_string);
+        let profile = Self {
+            udn: text("udn"),
+            manufacturer: text("manufacturer"),
+            model_name: text("model_name"),
+            friendly_name: text("friendly_name"),
+            control: text("control_path").and_then(|v| ControlEndpoint::parse(&v)),
+            metadata_style: text("metadata_style").and_then(|v| MetadataStyle::parse(&v)),
+            stop_before_set: value.get("stop_before_set").and_then(Value::as_bool),
+            seek_units: value
+                .get("seek_units")
+                .and_then(Value::as_array)
+                .map(|units| {
+                    units
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|u| u.trim().to_ascii_uppercase())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            max_volume: value
+                .get("max_volume")
+                .and_then(Value::as_u64)
+                .map(|v| v.min(100) as u32),
+        };
+        // 没有任何匹配条件的配置无法生效
+        if profile.udn.is_none() && profile.manufacturer.is_none() && profile.model_name.is_none() {
+            return None;
+        }
+        Some(profile)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut obj = serde_json::Map::new();
+        let mut put = |k: &str, v: Option<Value>| {
+            if let Some(v) = v {
+                obj.insert(k.to_string(), v);
+            }
+        };
+        put("udn", self.udn.clone().map(Value::from));
+        put("manufacturer", self.manufacturer.clone().map(Value::from));
+        put("model_name", self.model_name.clone().map(Value::from));
+        put("friendly_name", self.friendly_name.clone().map(Value::from));
+        put("control_path", self.control.as_ref().map(|c| json!(c.as_str())));
+        put("metadata_style", self.metadata_style.map(|m| json!(m.as_str())));
+        put("stop_before_set", self.stop_before_set.map(Value::from));
+        if !self.seek_units.is_empty() {
+            put("seek_units", Some(json!(self.seek_units)));
+        }
+        put("max_volume", self.max_volume.map(Value::from));
+        Value::Object(obj)
+    }
+
+    fn matches_model(&self, manufacturer: &str, model_name: &str) -> bool {
+        if self.manufacturer.is_none() && self.model_name.is_none() {
+            return false;
+        }
+        let contains = |pattern: &Option<String>, actual: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|p| actual.to_lowercase().contains(&p.to_lowercase()))
+        };
+        contains(&self.manufacturer, manufacturer) && contains(&self.model_name, model_name)
+    }
+
+    // 用 other 中填写了的字段覆盖自身
+    fn overlay(&mut self, other: &QuirkProfile) {
+        if other.control.is_some() {
+            self.control = other.control.clone();
+        }
+        if other.metadata_style.is_some() {
+            self.metadata_style = other.metadata_style;
+        }
+        if other.stop_before_set.is_some() {
+            self.stop_before_set = other.stop_before_set;
+        }
+        if !other.seek_units.is_empty() {
+            self.seek_units = other.seek_units.clone();
+        }
+        if other.max_volume.is_some() {
+            self.max_volume = other.max_volume;
+        }
+    }
+}
+
+/// 设备兼容配置：手写配置 + 运行时学到的配置（按 UDN 持久化）。
+///
+/// 合并优先级（后者覆盖前者）：按厂商/型号匹配的手写配置 → 学到的配置 → 按 UDN 匹配的手写配置。
+pub struct QuirkStore {
+    inner: RwLock<StoreState>,
+}
+
+#[derive(Default)]
+struct StoreState {
+    learned_path: PathBuf,
+    hand_written: Vec<QuirkProfile>,
+    learned: HashMap<String, QuirkProfile>,
+}
+
+static STORE: LazyLock<QuirkStore> = LazyLock::new(|| {
+    let store = QuirkStore {
+        inner: RwLock::new(StoreState::default()),
+    };
+    store.load_from(&crate::data_dir());
+    store
+});
+
+/// 全局的设备兼容配置
+pub fn store() -> &'static QuirkStore {
+    &STORE
+}
+
+fn read_profiles(path: &Path) -> Vec<QuirkProfile> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Array(items)) => items.iter().filter_map(QuirkProfile::from_json).collect(),
+        Ok(_) => {
+            warn!("设备配置文件格式错误（应为数组）: {}", path.display());
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("设备配置文件解析失败 {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+impl QuirkStore {
+    /// 从数据目录重新加载配置（Android 设置数据目录后调用）
+    pub fn load_from(&self, dir: &Path) {
+        let hand_written = read_profiles(&dir.join(HAND_WRITTEN_FILE));
+        let learned_path = dir.join(LEARNED_FILE);
+        let learned: HashMap<String, QuirkProfile> = read_profiles(&learned_path)
+            .into_iter()
+            .filter_map(|p| Some((p.udn.clone()?, p)))
+            .collect();
+        info!(
+            "已加载设备配置: 手写 {} 条, 已学习 {} 条",
+            hand_written.len(),
+            learned.len()
+        );
+        if let Ok(mut state) = self.inner.write() {
+            *state = StoreState {
+                learned_path,
+                hand_written,
+                learned,
+            };
+        }
+    }
+
+    /// 合并后的生效配置
+    pub fn resolve(&self, udn: &str, manufacturer: &str, model_name: &str) -> QuirkProfile {
+        let mut profile = QuirkProfile {
+            udn: Some(udn.to_string()),
+            ..Default::default()
+        };
+        let Ok(state) = self.inner.read() else {
+            return profile;
+        };
+        for p in state.hand_written.iter().filter(|p| p.udn.is_none()) {
+            if p.matches_model(manufacturer, model_name) {
+                profile.overlay(p);
+            }
+        }
+        if let Some(learned) = state.learned.get(udn) {
+            profile.overlay(learned);
+        }
+        for p in state.hand_written.iter().filter(|p| p.udn.as_deref() == Some(udn)) {
+            profile.overlay(p);
+        }
+        profile
+    }
+
+    /// 记录学到的配置并写回文件；与已有记录相同时不写文件
+    pub fn learn(&self, udn: &str, friendly_name: &str, update: impl FnOnce(&mut QuirkProfile)) {
+        let Ok(mut state) = self.inner.write() else {
+            return;
+        };
+        let entry = state.learned.entry(udn.to_string()).or_insert_with(|| QuirkProfile {
+            udn: Some(udn.to_string()),
+            ..Default::default()
+        });
+        let before = entry.clone();
+        update(entry);
+        entry.friendly_name = Some(friendly_name.to_string());
+        if *entry == before {
+            return;
+        }
+        info!("记录设备配置 {}: {}", friendly_name, entry.to_json());
+
+        let mut profiles: Vec<Value> = state.learned.values().map(QuirkProfile::to_json).collect();
+        profiles.sort_by_key(|p| p["udn"].as_str().unwrap_or_default().to_string());
+        let text = serde_json::to_string_pretty(&profiles).unwrap_or_default();
+        if let Err(e) = std::fs::write(&state.learned_path, text) {
+            warn!("写入设备配置失败 {}: {}", state.learned_path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_merge_order() {
+        let shipped = QuirkProfile::from_json(&json!({
+            "manufacturer": "Xiaomi",
+            "metadata_style": "minimal",
+            "stop_before_set": false,
+            "seek_units": ["abs_time"]
+        }))
+        .unwrap();
+        let pinned = QuirkProfile::from_json(&json!({
+            "udn": "uuid:tv-1",
+            "control_path": "/upnp/control/AVTransport1",
+            "max_volume": 60
+        }))
+        .unwrap();
+        let learned = QuirkProfile {
+            udn: Some("uuid:tv-1".to_string()),
+            control: Some(ControlEndpoint::Native),
+            ..Default::default()
+        };
+        // 没有匹配条件的配置被忽略
+        assert!(QuirkProfile::from_json(&json!({ "stop_before_set": true })).is_none());
+
+        let store = QuirkStore {
+            inner: RwLock::new(StoreState {
+                learned_path: PathBuf::new(),
+                hand_written: vec![shipped, pinned.clone()],
+                learned: HashMap::from([("uuid:tv-1".to_string(), learned)]),
+            }),
+        };
+
+        let p = store.resolve("uuid:tv-1", "Xiaomi Inc.", "MiTV");
+        assert_eq!(p.control, pinned.control);
+        assert_eq!(p.metadata_style, Some(MetadataStyle::Minimal));
+        assert_eq!(p.stop_before_set, Some(false));
+        assert_eq!(p.seek_units, vec!["ABS_TIME"]);
+        assert_eq!(p.max_volume, Some(60));
+
+        let other = store.resolve("uuid:tv-2", "Sony", "BRAVIA");
+        assert_eq!(other.control, None);
+        assert_eq!(other.stop_before_set, None);
+        assert_eq!(other.max_volume, None);
+
+        let round_trip = QuirkProfile::from_json(&p.to_json()).unwrap();
+        assert_eq!(round_trip, p);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::discovery;
+use crate::gapless::GaplessPreloader;
+use crate::playback_monitor::PositionTracker;
+use crate::playlist_manager::PlaylistManager;
+use crate::renderer::Renderer;
+use crate::renderer_group::{RendererGroup, RendererHealth};
+use crate::{EngineEvent, Result};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+// 检查离线设备的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// 每次重新发现最多等待多久
+const REDISCOVER_TIMEOUT: Duration = Duration::from_secs(10);
+const REDISCOVER_POLL: Duration = Duration::from_millis(500);
+// 重连失败后的等待时间，逐次翻倍
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+struct Attempt {
+    next_try: Instant,
+    backoff: Duration,
+}
+
+/// 监控投屏组：设备连续失败被标记为离线后（例如电视重启、描述文件端口变化），
+/// 重新连接同一台设备（DLNA 按 UDN / 名称重新发现），重新投当前歌曲并跳回断线时的进度。
+pub(crate) async fn run(
+    group: Arc<RendererGroup>,
+    gapless: Arc<GaplessPreloader>,
+    position: Arc<PositionTracker>,
+    playlist_manager: PlaylistManager,
+    events: broadcast::Sender<EngineEvent>,
+) {
+    let mut attempts: HashMap<String, Attempt> = HashMap::new();
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let offline: Vec<Arc<dyn Renderer>> = group
+            .devices()
+            .into_iter()
+            .filter(|d| group.health(d.id()) == Some(RendererHealth::Offline))
+            .collect();
+        // 已恢复或被移出组的设备不再跟踪
+        attempts.retain(|id, _| offline.iter().any(|d| d.id() == id));
+
+        for device in offline {
+            let attempt = attempts.entry(device.id().to_string()).or_insert_with(|| {
+                warn!("设备失去响应，开始尝试重连: {}", device.name());
+                let _ = events.send(EngineEvent::RendererLost {
+                    friendly_name: device.name().to_string(),
+                    location: device.id().to_string(),
+                });
+                Attempt {
+                    next_try: Instant::now(),
+                    backoff: INITIAL_BACKOFF,
+                }
+            });
+            if Instant::now() < attempt.next_try {
+                continue;
+            }
+
+            // 断线前最后一次成功通信时的进度；非主设备跟随主设备当前进度
+            let is_primary = group.primary().id() == device.id();
+            let resume_at = if is_primary {
+                group
+                    .last_ok(device.id())
+                    .and_then(|t| position.position_at(t))
+            } else {
+                position.snapshot().map(|(secs, _)| secs)
+            };
+
+            match reconnect(&group, &*device).await {
+                Ok(new_device) => {
+                    attempts.remove(device.id());
+                    let fallback = playlist_manager.get_song_playing().await;
+                    if let Err(e) = gapless
+                        .resume(new_device.clone(), fallback, is_primary, resume_at)
+                        .await
+                    {
+                        warn!("重连后恢复播放失败: {}", e);
+                    }
+                    let _ = events.send(EngineEvent::RendererReconnected {
+                        friendly_name: new_device.name().to_string(),
+                        location: new_device.id().to_string(),
+                    });
+                }
+                Err(e) => {
+                    debug!("重连 {} 失败: {}，{:?} 后重试", device.name(), e, attempt.backoff);
+                    attempt.next_try = Instant::now() + attempt.backoff;
+                    attempt.backoff = (attempt.backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+// 重新连接设备并替换组内的旧设备
+async fn reconnect(group: &RendererGroup, old: &dyn Renderer) -> Result<Arc<dyn Renderer>> {
+    let device = old.reconnect().await?;
+    group.replace(old.id(), device.clone());
+    info!("设备 {} 已重新连接: {}", device.name(), device.id());
+    Ok(device)
+}
+
+/// 在发现服务中查找同一台设备的当前地址，找不到时返回 None（由调用方再试原地址）
+pub(crate) async fn rediscover(udn: &str, friendly_name: &str, old_location: &str) -> Option<String> {
+    let service = discovery::service();
+    service.search_now();
+
+    let started = std::time::Instant::now();
+    let deadline = Instant::now() + REDISCOVER_TIMEOUT;
+    loop {
+        let renderers = service.renderers();
+        let candidates: Vec<(String, String, String)> = renderers
+            .iter()
+            .map(|r| (r.udn.clone(), r.device.friendly_name.clone(), r.device.location.clone()))
+            .collect();
+        if let Some(idx) = find_same_device(&candidates, udn, friendly_name) {
+            let location = candidates[idx].2.clone();
+            // 注册表里的旧地址可能已经过时，等到本次搜索中再次出现（或超时）才采用
+            if location != old_location
+                || renderers[idx].last_seen >= started
+                || Instant::now() >= deadline
+            {
+                return Some(location);
+            }
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        // 已知设备的响应只刷新注册表、不产生事件，因此定时检查
+        tokio::time::sleep(REDISCOVER_POLL).await;
+    }
+}
+
+/// 候选项为 (udn, friendly_name, location)：优先 UDN 相同；
+/// UDN 变了（部分设备重启后重新生成）时，只有名称唯一匹配才认为是同一台
+fn find_same_device(candidates: &[(String, String, String)], udn: &str, friendly_name: &str) -> Option<usize> {
+    if let Some(idx) = candidates.iter().position(|(u, _, _)| !udn.is_empty() && u == udn) {
+        return Some(idx);
+    }
+    let mut by_name = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, name, _))| name == friendly_name);
+    match (by_name.next(), by_name.next()) {
+        (Some((idx, _)), None) => Some(idx),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(udn: &str, name: &str, location: &str) -> (String, String, String) {
+        (udn.to_string(), name.to_string(), location.to_string())
+    }
+
+    #[test]
+    fn test_find_same_device() {
+        let candidates = vec![
+            candidate("uuid:tv-1", "客厅电视", "http://192.168.1.5:49152/desc.xml"),
+            candidate("uuid:box-1", "KTV 盒子", "http://192.168.1.8:1400/dd.xml"),
+            candidate("uuid:box-2", "KTV 盒子", "http://192.168.1.9:1400/dd.xml"),
+        ];
+        // UDN 优先，即使名称已被用户改掉
+        assert_eq!(find_same_device(&candidates, "uuid:tv-1", "旧名字"), Some(0));
+        // UDN 变了，名称唯一时按名称匹配
+        assert_eq!(find_same_device(&candidates, "uuid:tv-9", "客厅电视"), Some(0));
+        // 同名设备有多台时无法确定
+        assert_eq!(find_same_device(&candidates, "uuid:box-9", "KTV 盒子"), None);
+        assert_eq!(find_same_device(&candidates, "uuid:box-2", "KTV 盒子"), Some(2));
+        assert_eq!(find_same_device(&candidates, "", "卧室电视"), None);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::dlna_controller::{MediaMetadata, TrackPosition, TransportState};
+use crate::error::Result;
+use crate::media_server;
+use crate::quirks::QuirkProfile;
+use futures::future::BoxFuture;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// 要加载到播放目标上的媒体
+#[derive(Debug, Clone, Copy)]
+pub struct MediaLoad<'a> {
+    /// 本地代理路径（不含前导斜杠）
+    pub uri_path: &'a str,
+    pub metadata: &'a MediaMetadata,
+    /// 协商出的 protocolInfo，未知时由实现自行决定
+    pub protocol_info: Option<&'a str>,
+    /// 目标拉取媒体时使用的本机地址与端口
+    pub server_ip: IpAddr,
+    pub server_port: u16,
+}
+
+impl MediaLoad<'_> {
+    /// 目标拉取媒体的完整地址
+    pub fn url(&self) -> String {
+        format!("{}/{}", media_server::base_url(self.server_ip, self.server_port), self.uri_path)
+    }
+}
+
+/// 事件订阅句柄，Drop 时停止订阅
+pub trait Subscription: Send + Sync {
+    /// 订阅是否仍然有效，失效后调用方回退到轮询
+    fn is_active(&self) -> bool;
+
+    /// 该事件是否属于本订阅（按 NOTIFY 的 SID）；已取消的旧订阅仍在路上的通知据此丢弃
+    fn owns(&self, sid: &str) -> bool;
+}
+
+/// 播放目标。引擎、投屏组与各 `*_core` 函数只通过它控制设备，
+/// `DlnaRenderer`（`DlnaController` 绑定到一台设备）是第一个实现；
+/// 新的目标实现该 trait 即可接入，不必改动歌单同步与 CLI。
+pub trait Renderer: Send + Sync {
+    /// 组内唯一的标识（DLNA 为描述文件地址），重连后可能变化
+    fn id(&self) -> &str;
+
+    fn name(&self) -> &str;
+
+    /// 跨重连不变的标识（DLNA 为 UDN），用于恢复会话时重新找到同一目标
+    fn udn(&self) -> &str;
+
+    /// 目标所在网卡的本机地址，未知时由引擎按默认地址提供媒体
+    fn local_addr(&self) -> Option<IpAddr> {
+        None
+    }
+
+    /// 兼容配置（手写配置与运行时学到的发送方式合并后）
+    fn quirks(&self) -> QuirkProfile {
+        QuirkProfile::default()
+    }
+
+    /// 按上游的 Content-Type 选择 protocolInfo；目标不能播放时返回 Error::UnsupportedFormat
+    fn negotiate(&self, content_type: &str) -> Result<Option<String>>;
+
+    /// 加载媒体（不自动开始播放）
+    fn load<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>>;
+
+    /// 预加载下一首，当前歌曲结束后目标自动切换；不支持时返回错误
+    fn load_next<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>>;
+
+    fn play(&self) -> BoxFuture<'_, Result<()>>;
+
+    fn pause(&self) -> BoxFuture<'_, Result<()>>;
+
+    fn stop(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// 跳转到 secs 秒，目标确认进度后才返回
+    fn seek(&self, secs: u32) -> BoxFuture<'_, Result<()>>;
+
+    fn set_volume(&self, volume: u32) -> BoxFuture<'_, Result<()>>;
+
+    fn get_volume(&self) -> BoxFuture<'_, Result<u32>>;
+
+    /// 当前曲目与进度
+    fn position(&self) -> BoxFuture<'_, Result<TrackPosition>>;
+
+    fn transport_state(&self) -> BoxFuture<'_, Result<TransportState>>;
+
+    /// 订阅状态事件，事件以 `RendererEvent` 发到 `SharedState::renderer_events`。
+    /// callback_base 为本机媒体服务器地址；不支持时返回错误，由调用方回退到轮询
+    fn subscribe<'a>(&'a self, callback_base: &'a str) -> BoxFuture<'a, Result<Box<dyn Subscription>>>;
+
+    /// 目标失去响应后重新连接（地址可能已变化），返回新的实例
+    fn reconnect(&self) -> BoxFuture<'_, Result<Arc<dyn Renderer>>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::renderer_group::{RendererGroup, RendererHealth};
+    use std::sync::Mutex;
+
+    // 只记录收到的动作的测试替身；offline 时所有动作都失败
+    struct FakeRenderer {
+        id: String,
+        offline: bool,
+        actions: Mutex<Vec<String>>,
+    }
+
+    impl FakeRenderer {
+        fn new(id: &str, offline: bool) -> Arc<Self> {
+            Arc::new(Self {
+                id: id.to_string(),
+                offline,
+                actions: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn act(&self, action: String) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move {
+                if self.offline {
+                    return Err(Error::Network("offline".to_string()));
+                }
+                self.actions.lock().unwrap().push(action);
+                Ok(())
+            })
+        }
+    }
+
+    impl Renderer for FakeRenderer {
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn name(&self) -> &str {
+            &self.id
+        }
+
+        fn udn(&self) -> &str {
+            &self.id
+        }
+
+        fn negotiate(&self, _content_type: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+        fn load<'a>(&'a self, media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>> {
+            self.act(format!("load {}", media.url()))
+        }
+
+        fn load_next<'a>(&'a self, _media: MediaLoad<'a>) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async { Err(Error::UnsupportedService("AVTransport")) })
+        }
+
+        fn play(&self) -> BoxFuture<'_, Result<()>> {
+            self.act("play".to_string())
+        }
+
+        fn pause(&self) -> BoxFuture<'_, Result<()>> {
+            self.act("pause".to_string())
+        }
+
+        fn stop(&self) -> BoxFuture<'_, Result<()>> {
+            self.act("stop".to_string())
+        }
+
+        fn seek(&self, secs: u32) -> BoxFuture<'_, Result<()>> {
+            self.act(format!("seek {}", secs))
+        }
+
+        fn set_volume(&self, volume: u32) -> BoxFuture<'_, Result<()>> {
+            self.act(format!("volume {}", volume))
+        }
+
+        fn get_volume(&self) -> BoxFuture<'_, Result<u32>> {
+            Box::pin(async { Ok(0) })
+        }
+
+        fn position(&self) -> BoxFuture<'_, Result<TrackPosition>> {
+            Box::pin(async { Ok(TrackPosition::default()) })
+        }
+
+        fn transport_state(&self) -> BoxFuture<'_, Result<TransportState>> {
+            Box::pin(async { Ok(TransportState::Stopped) })
+        }
+
+        fn subscribe<'a>(&'a self, _callback_base: &'a str) -> BoxFuture<'a, Result<Box<dyn Subscription>>> {
+            Box::pin(async { Err(Error::UnsupportedService("events")) })
+        }
+
+        fn reconnect(&self) -> BoxFuture<'_, Result<Arc<dyn Renderer>>> {
+            Box::pin(async { Err(Error::Network("offline".to_string())) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_with_test_double() {
+        let tv = FakeRenderer::new("tv", false);
+        let speaker = FakeRenderer::new("speaker", true);
+        let group = RendererGroup::new(tv.clone());
+        assert!(group.add(speaker.clone()));
+        assert!(!group.add(FakeRenderer::new("speaker", false)));
+
+        let metadata = MediaMetadata::default();
+        let media = MediaLoad {
+            uri_path: "BV1xx411c7mD-p1",
+            metadata: &metadata,
+            protocol_info: None,
+            server_ip: [192, 168, 1, 2].into(),
+            server_port: 8080,
+        };
+        // 只要有一台成功就算成功，失败的设备计入健康状态
+        group.fan_out("Load", |d| async move { d.load(media).await }).await.unwrap();
+        group.fan_out("Seek", |d| async move { d.seek(42).await }).await.unwrap();
+        assert_eq!(
+            *tv.actions.lock().unwrap(),
+            ["load http://192.168.1.2:8080/BV1xx411c7mD-p1", "seek 42"]
+        );
+        assert_eq!(group.health("speaker"), Some(RendererHealth::Degraded));
+        group.fan_out("Play", |d| async move { d.play().await }).await.unwrap();
+        assert_eq!(group.health("speaker"), Some(RendererHealth::Offline));
+        assert_eq!(group.health("tv"), Some(RendererHealth::Healthy));
+
+        // 全部失败时返回错误
+        let result = group
+            .fan_out_to(group.secondaries(), "Stop", |d| async move { d.stop().await })
+            .await;
+        assert!(matches!(result, Err(Error::Network(_))));
+        assert!(group.remove("tv").is_err());
+        assert_eq!(group.remove("speaker").unwrap().id(), "speaker");
+    }
+}
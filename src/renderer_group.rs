@@ -0,0 +1,271 @@
+use crate::error::{Error, Result};
+use crate::renderer::Renderer;
+use futures::future::join_all;
+use log::{info, warn};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// 连续失败达到该次数视为离线
+const OFFLINE_AFTER_FAILURES: u32 = 3;
+
+/// 设备组中单个渲染器的健康状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RendererHealth {
+    Healthy,
+    /// 最近有失败，但还没到离线的程度
+    Degraded,
+    Offline,
+}
+
+impl RendererHealth {
+    /// 给 Android 侧使用的整数编码
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Healthy => 0,
+            Self::Degraded => 1,
+            Self::Offline => 2,
+        }
+    }
+}
+
+/// 对外暴露的设备状态快照
+#[derive(Debug, Clone)]
+pub struct RendererStatus {
+    pub friendly_name: String,
+    pub location: String,
+    pub health: RendererHealth,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// 距离上次成功操作的秒数，从未成功过时为 None
+    pub last_ok_secs_ago: Option<u64>,
+}
+
+struct Member {
+    device: Arc<dyn Renderer>,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+    last_ok: Option<Instant>,
+}
+
+impl Member {
+    fn health(&self) -> RendererHealth {
+        health_for(self.consecutive_failures)
+    }
+}
+
+fn health_for(consecutive_failures: u32) -> RendererHealth {
+    match consecutive_failures {
+        0 => RendererHealth::Healthy,
+        n if n < OFFLINE_AFTER_FAILURES => RendererHealth::Degraded,
+        _ => RendererHealth::Offline,
+    }
+}
+
+/// 派对模式：同一房间投到多个渲染器。
+///
+/// 第一个设备是主设备，进度、事件订阅和无缝切歌都以它为准；
+/// 播放控制会并发下发到组内所有设备，并按设备记录成功/失败。
+pub struct RendererGroup {
+    members: RwLock<Vec<Member>>,
+}
+
+impl RendererGroup {
+    pub fn new(primary: Arc<dyn Renderer>) -> Self {
+        Self {
+            members: RwLock::new(vec![Member {
+                device: primary,
+                consecutive_failures: 0,
+                last_error: None,
+                last_ok: None,
+            }]),
+        }
+    }
+
+    /// 主设备
+    pub fn primary(&self) -> Arc<dyn Renderer> {
+        self.members.read().unwrap()[0].device.clone()
+    }
+
+    /// 组内所有设备（主设备在前）
+    pub fn devices(&self) -> Vec<Arc<dyn Renderer>> {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .map(|m| m.device.clone())
+            .collect()
+    }
+
+    /// 除主设备外的其他设备
+    pub fn secondaries(&self) -> Vec<Arc<dyn Renderer>> {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .skip(1)
+            .map(|m| m.device.clone())
+            .collect()
+    }
+
+    /// 组内是否有多于一台设备
+    pub fn is_party(&self) -> bool {
+        self.members.read().unwrap().len() > 1
+    }
+
+    /// 加入设备；同一 id 已在组内时返回 false
+    pub fn add(&self, device: Arc<dyn Renderer>) -> bool {
+        let mut members = self.members.write().unwrap();
+        if members.iter().any(|m| m.device.id() == device.id()) {
+            return false;
+        }
+        info!("设备加入投屏组: {} ({})", device.name(), device.id());
+        members.push(Member {
+            device,
+            consecutive_failures: 0,
+            last_error: None,
+            last_ok: None,
+        });
+        true
+    }
+
+    /// 移出设备。主设备承载了进度与事件订阅，不能移除
+    pub fn remove(&self, id: &str) -> Result<Arc<dyn Renderer>> {
+        let mut members = self.members.write().unwrap();
+        let idx = members
+            .iter()
+            .position(|m| m.device.id() == id)
+            .ok_or(Error::EngineState("设备不在投屏组中"))?;
+        if idx == 0 {
+            return Err(Error::EngineState("不能移除主设备"));
+        }
+        let member = members.remove(idx);
+        info!("设备移出投屏组: {}", member.device.name());
+        Ok(member.device)
+    }
+
+    /// 设备重连后用新的描述（地址可能已变化）替换旧设备，位置与主设备身份不变
+    pub fn replace(&self, id: &str, device: Arc<dyn Renderer>) -> bool {
+        let mut members = self.members.write().unwrap();
+        let Some(member) = members.iter_mut().find(|m| m.device.id() == id) else {
+            return false;
+        };
+        info!("设备已重连: {} ({} -> {})", device.name(), id, device.id());
+        *member = Member {
+            device,
+            consecutive_failures: 0,
+            last_error: None,
+            last_ok: Some(Instant::now()),
+        };
+        true
+    }
+
+    pub fn health(&self, id: &str) -> Option<RendererHealth> {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.device.id() == id)
+            .map(Member::health)
+    }
+
+    /// 最近一次成功操作的时间，用于估计设备掉线时的播放进度
+    pub fn last_ok(&self, id: &str) -> Option<Instant> {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.device.id() == id)
+            .and_then(|m| m.last_ok)
+    }
+
+    pub fn status(&self) -> Vec<RendererStatus> {
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .map(|m| RendererStatus {
+                friendly_name: m.device.name().to_string(),
+                location: m.device.id().to_string(),
+                health: m.health(),
+                consecutive_failures: m.consecutive_failures,
+                last_error: m.last_error.clone(),
+                last_ok_secs_ago: m.last_ok.map(|t| t.elapsed().as_secs()),
+            })
+            .collect()
+    }
+
+    /// 记录一次针对某设备的操作结果
+    pub fn record(&self, id: &str, result: std::result::Result<(), &Error>) {
+        let mut members = self.members.write().unwrap();
+        let Some(member) = members.iter_mut().find(|m| m.device.id() == id) else {
+            return;
+        };
+        match result {
+            Ok(()) => {
+                if member.consecutive_failures >= OFFLINE_AFTER_FAILURES {
+                    info!("设备恢复响应: {}", member.device.name());
+                }
+                member.consecutive_failures = 0;
+                member.last_ok = Some(Instant::now());
+            }
+            Err(e) => {
+                member.consecutive_failures += 1;
+                member.last_error = Some(e.to_string());
+                if member.consecutive_failures == OFFLINE_AFTER_FAILURES {
+                    warn!("设备连续 {} 次操作失败，标记为离线: {}", OFFLINE_AFTER_FAILURES, member.device.name());
+                }
+            }
+        }
+    }
+
+    /// 把同一个动作并发下发到所有设备。
+    /// 只要有一台成功就返回 Ok，全部失败时返回主设备（或第一个）的错误。
+    pub async fn fan_out<F, Fut>(&self, action: &str, op: F) -> Result<()>
+    where
+        F: Fn(Arc<dyn Renderer>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        self.fan_out_to(self.devices(), action, op).await
+    }
+
+    /// 同 `fan_out`，但只下发给指定的设备
+    pub async fn fan_out_to<F, Fut>(&self, devices: Vec<Arc<dyn Renderer>>, action: &str, op: F) -> Result<()>
+    where
+        F: Fn(Arc<dyn Renderer>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let results = join_all(devices.iter().map(|d| op(d.clone()))).await;
+
+        let mut first_error = None;
+        let mut any_ok = false;
+        for (device, result) in devices.iter().zip(results) {
+            self.record(device.id(), result.as_ref().map(|_| ()));
+            match result {
+                Ok(()) => any_ok = true,
+                Err(e) => {
+                    warn!("{} 在设备 {} 上失败: {}", action, device.name(), e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !any_ok => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_for() {
+        assert_eq!(health_for(0), RendererHealth::Healthy);
+        assert_eq!(health_for(1), RendererHealth::Degraded);
+        assert_eq!(health_for(OFFLINE_AFTER_FAILURES - 1), RendererHealth::Degraded);
+        assert_eq!(health_for(OFFLINE_AFTER_FAILURES), RendererHealth::Offline);
+        assert_eq!(RendererHealth::Offline.code(), 2);
+    }
+}
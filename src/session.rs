@@ -0,0 +1,197 @@
+use crate::EngineEvent;
+use crate::gapless::GaplessPreloader;
+use crate::playback_monitor::PositionTracker;
+use crate::playlist_manager::{self, PlaylistManager, SyncMode};
+use crate::renderer_group::RendererGroup;
+use log::{debug, info, warn};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const SESSION_FILE: &str = "last_session.json";
+// 播放中每隔这么久记录一次进度
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+// 当前会话（已写入文件的内容），引擎未启动时为 None
+static CURRENT: Mutex<Option<Session>> = Mutex::new(None);
+
+/// 上次投屏的房间、设备与播放进度，进程重启后据此自动恢复
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub base_url: String,
+    pub room_id: String,
+    /// 主设备 UDN，重新发现时优先按它匹配（设备重启后地址可能变化）
+    pub device_udn: String,
+    pub device_location: String,
+    pub device_name: String,
+    pub sync_mode: SyncMode,
+    pub volume: Option<u32>,
+    /// 正在播放的歌曲（代理路径）及其进度
+    pub song: Option<String>,
+    pub position_secs: u32,
+}
+
+impl Session {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let text = |k: &str| value.get(k).and_then(Value::as_str).map(str::to_string);
+        Some(Self {
+            base_url: text("base_url")?,
+            room_id: text("room_id")?,
+            device_udn: text("device_udn").unwrap_or_default(),
+            device_location: text("device_location")?,
+            device_name: text("device_name").unwrap_or_default(),
+            sync_mode: text("sync_mode")
+                .and_then(|m| SyncMode::parse(&m))
+                .unwrap_or_default(),
+            volume: value.get("volume").and_then(Value::as_u64).map(|v| v.min(100) as u32),
+            song: text("song"),
+            position_secs: value.get("position_secs").and_then(Value::as_u64).unwrap_or(0) as u32,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "base_url": self.base_url,
+            "room_id": self.room_id,
+            "device_udn": self.device_udn,
+            "device_location": self.device_location,
+            "device_name": self.device_name,
+            "sync_mode": self.sync_mode.as_str(),
+            "volume": self.volume,
+            "song": self.song,
+            "position_secs": self.position_secs,
+        })
+    }
+}
+
+fn session_path() -> PathBuf {
+    crate::data_dir().join(SESSION_FILE)
+}
+
+/// 读取上次保存的会话
+pub fn load() -> Option<Session> {
+    let text = std::fs::read_to_string(session_path()).ok()?;
+    match serde_json::from_str::<Value>(&text).ok().as_ref().and_then(Session::from_json) {
+        Some(session) => Some(session),
+        None => {
+            warn!("会话文件格式错误，忽略: {}", session_path().display());
+            None
+        }
+    }
+}
+
+/// 删除保存的会话（用户主动重新选择房间/设备时）
+pub fn clear() {
+    if let Ok(mut current) = CURRENT.lock() {
+        *current = None;
+    }
+    let _ = std::fs::remove_file(session_path());
+}
+
+fn write(session: &Session) {
+    let path = session_path();
+    let text = serde_json::to_string_pretty(&session.to_json()).unwrap_or_default();
+    if let Err(e) = std::fs::write(&path, text) {
+        warn!("保存会话失败 {}: {}", path.display(), e);
+    }
+}
+
+/// 引擎连接成功后开始记录新的会话
+pub(crate) fn begin(session: Session) {
+    info!("记录会话: 房间 {}，设备 {}", session.room_id, session.device_name);
+    write(&session);
+    if let Ok(mut current) = CURRENT.lock() {
+        *current = Some(session);
+    }
+}
+
+/// 修改当前会话，内容有变化时写回文件
+pub(crate) fn update(f: impl FnOnce(&mut Session)) {
+    let Ok(mut current) = CURRENT.lock() else {
+        return;
+    };
+    let Some(session) = current.as_mut() else {
+        return;
+    };
+    let before = session.clone();
+    f(session);
+    if *session != before {
+        debug!("更新会话: {:?}", session);
+        write(session);
+    }
+}
+
+/// 在引擎 Runtime 上定期记录主设备（重连后地址会变）、当前歌曲与进度，并跟随设备上报的音量
+pub(crate) async fn run(
+    group: Arc<RendererGroup>,
+    gapless: Arc<GaplessPreloader>,
+    position: Arc<PositionTracker>,
+    playlist_manager: PlaylistManager,
+    mut engine_events: broadcast::Receiver<EngineEvent>,
+) {
+    let mut ticker = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let primary = group.primary();
+                let song = match gapless.current().await {
+                    Some(song) => Some(song),
+                    None => playlist_manager.get_song_playing().await,
+                };
+                let secs = position.snapshot().map(|(secs, _)| secs).unwrap_or(0);
+                update(|s| {
+                    s.device_udn = primary.udn().to_string();
+                    s.device_location = primary.id().to_string();
+                    s.device_name = primary.name().to_string();
+                    s.sync_mode = playlist_manager::sync_mode();
+                    s.song = song;
+                    s.position_secs = secs;
+                });
+            }
+            event = engine_events.recv() => {
+                match event {
+                    Ok(EngineEvent::VolumeChanged(v)) => update(|s| s.volume = Some(v)),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_round_trip() {
+        let session = Session {
+            base_url: "https://ktv.example.com".to_string(),
+            room_id: "1234".to_string(),
+            device_udn: "uuid:tv-1".to_string(),
+            device_location: "http://192.168.1.5:49152/desc.xml".to_string(),
+            device_name: "客厅电视".to_string(),
+            sync_mode: SyncMode::Polling,
+            volume: Some(35),
+            song: Some("BV1xx411c7mD-p1".to_string()),
+            position_secs: 83,
+        };
+        assert_eq!(Session::from_json(&session.to_json()), Some(session));
+
+        // 旧版本或手写的文件缺少可选字段时使用默认值
+        let minimal = Session::from_json(&json!({
+            "base_url": "https://ktv.example.com",
+            "room_id": "1234",
+            "device_location": "http://192.168.1.5:49152/desc.xml",
+            "sync_mode": "bogus"
+        }))
+        .unwrap();
+        assert_eq!(minimal.sync_mode, SyncMode::Ws);
+        assert_eq!(minimal.volume, None);
+        assert_eq!(minimal.position_secs, 0);
+        // 缺少房间时无法恢复
+        assert!(Session::from_json(&json!({ "base_url": "x", "device_location": "y" })).is_none());
+    }
+}
@@ -0,0 +1,435 @@
+use crate::error::{Error, Result};
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+// 手写的签名规则（可选，数据目录下），不存在时使用内置规则
+const RULES_FILE: &str = "signed_url_rules.json";
+const DEFAULT_TTL_SECS: u64 = 6 * 3600;
+
+// 显式指定的规则（Android 传入），优先于规则文件
+static RULES: RwLock<Option<Vec<AuthRule>>> = RwLock::new(None);
+// 从规则文件读到的规则，数据目录变化后重新读取
+static FILE_RULES: RwLock<Option<(PathBuf, Option<Vec<AuthRule>>)>> = RwLock::new(None);
+
+/// 一类签名地址（CloudFront、Akamai 等）的鉴权传递规则：
+/// 从匹配 capture_from 的请求（通常是 m3u8）上记下签名参数/Cookie，
+/// 再补给同一主机上匹配 inject_into、自身没有签名的子请求（分片、密钥）。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthRule {
+    /// 仅用于日志
+    pub name: String,
+    /// 主机名："*" 匹配所有主机，"*.example.com" 匹配子域名，否则须完全相同
+    pub host: String,
+    /// 从路径以这些后缀结尾的请求上记录签名（不区分大小写），为空时不限
+    pub capture_from: Vec<String>,
+    /// 要记录并补全的查询参数
+    pub params: Vec<String>,
+    /// 请求上带齐这些参数才算签名地址；为空时带任意一个 params 即可
+    pub required: Vec<String>,
+    /// 要记录的上游 Set-Cookie，之后作为 Cookie 发给子请求
+    pub cookies: Vec<String>,
+    /// 补给路径以这些后缀结尾的请求，为空时不限
+    pub inject_into: Vec<String>,
+    /// 记录的签名多久后失效
+    pub ttl: Duration,
+    /// 保存过期时间（Unix 秒）的参数，如 CloudFront 的 Expires；比 ttl 早时以它为准
+    pub expires_param: Option<String>,
+}
+
+impl AuthRule {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let list = |k: &str| -> Vec<String> {
+            value
+                .get(k)
+                .and_then(Value::as_array)
+                .map(|items| items.iter().filter_map(Value::as_str).map(str::to_string).collect())
+                .unwrap_or_default()
+        };
+        let rule = Self {
+            name: value.get("name").and_then(Value::as_str).unwrap_or("custom").to_string(),
+            host: value.get("host").and_then(Value::as_str)?.trim().to_ascii_lowercase(),
+            capture_from: list("capture_from"),
+            params: list("params"),
+            required: list("required"),
+            cookies: list("cookies"),
+            inject_into: list("inject_into"),
+            ttl: Duration::from_secs(value.get("ttl_secs").and_then(Value::as_u64).unwrap_or(DEFAULT_TTL_SECS)),
+            expires_param: value.get("expires_param").and_then(Value::as_str).map(str::to_string),
+        };
+        // 既不记录参数也不记录 Cookie 的规则没有意义
+        (!rule.host.is_empty() && (!rule.params.is_empty() || !rule.cookies.is_empty())).then_some(rule)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "host": self.host,
+            "capture_from": self.capture_from,
+            "params": self.params,
+            "required": self.required,
+            "cookies": self.cookies,
+            "inject_into": self.inject_into,
+            "ttl_secs": self.ttl.as_secs(),
+            "expires_param": self.expires_param,
+        })
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if self.host == "*" {
+            return true;
+        }
+        match self.host.strip_prefix("*.") {
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == self.host,
+        }
+    }
+
+    // 该请求自身是否带着签名
+    fn is_signed(&self, params: &[(String, String)]) -> bool {
+        let has = |name: &String| params.iter().any(|(k, _)| k == name);
+        if self.required.is_empty() {
+            self.params.iter().any(has)
+        } else {
+            self.required.iter().all(has)
+        }
+    }
+}
+
+fn path_matches(suffixes: &[String], path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    suffixes.is_empty() || suffixes.iter().any(|s| path.ends_with(&s.to_ascii_lowercase()))
+}
+
+/// 内置规则：CloudFront（Policy/Signature/Key-Pair-Id，或 CloudFront-* Cookie）与 Akamai（hdnts/hdnea 令牌，hdntl Cookie）
+pub fn default_rules() -> Vec<AuthRule> {
+    let to_vec = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let children = to_vec(&[".m3u8", ".ts", ".m4s", ".mp4", ".m4a", ".aac", ".key", ".vtt"]);
+    vec![
+        AuthRule {
+            name: "cloudfront".to_string(),
+            host: "*".to_string(),
+            capture_from: to_vec(&[".m3u8"]),
+            params: to_vec(&["Policy", "Signature", "Key-Pair-Id", "Expires"]),
+            required: to_vec(&["Signature", "Key-Pair-Id"]),
+            cookies: to_vec(&["CloudFront-Policy", "CloudFront-Signature", "CloudFront-Key-Pair-Id"]),
+            inject_into: children.clone(),
+            ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+            expires_param: Some("Expires".to_string()),
+        },
+        AuthRule {
+            name: "akamai".to_string(),
+            host: "*".to_string(),
+            capture_from: to_vec(&[".m3u8"]),
+            params: to_vec(&["hdnts", "hdnea", "__hdnea__"]),
+            required: Vec::new(),
+            cookies: to_vec(&["hdntl"]),
+            inject_into: children,
+            ttl: Duration::from_secs(DEFAULT_TTL_SECS),
+            expires_param: None,
+        },
+    ]
+}
+
+/// 解析规则列表（JSON 数组），格式与 signed_url_rules.json 相同
+pub fn parse_rules(text: &str) -> Result<Vec<AuthRule>> {
+    match serde_json::from_str::<Value>(text) {
+        Ok(Value::Array(items)) => Ok(items.iter().filter_map(AuthRule::from_json).collect()),
+        Ok(_) => Err(Error::Parse("签名规则应为数组".to_string())),
+        Err(e) => Err(Error::Parse(format!("签名规则解析失败: {}", e))),
+    }
+}
+
+/// 指定签名规则，None 表示使用数据目录下的 signed_url_rules.json（不存在时用内置规则）
+pub fn set_rules(rules: Option<Vec<AuthRule>>) {
+    if let Ok(mut guard) = RULES.write() {
+        *guard = rules;
+    }
+}
+
+/// 当前生效的规则：显式指定 > 数据目录下的 signed_url_rules.json > 内置规则
+pub fn rules() -> Vec<AuthRule> {
+    if let Some(rules) = RULES.read().ok().and_then(|r| r.clone()) {
+        return rules;
+    }
+    let path = crate::data_dir().join(RULES_FILE);
+    if let Some((loaded_path, rules)) = FILE_RULES.read().ok().and_then(|r| r.clone())
+        && loaded_path == path
+    {
+        return rules.unwrap_or_else(default_rules);
+    }
+    let rules = std::fs::read_to_string(&path).ok().and_then(|text| match parse_rules(&text) {
+        Ok(rules) => {
+            info!("已加载签名规则 {} 条: {}", rules.len(), path.display());
+            Some(rules)
+        }
+        Err(e) => {
+            warn!("{}，使用内置规则: {}", e, path.display());
+            None
+        }
+    });
+    if let Ok(mut guard) = FILE_RULES.write() {
+        *guard = Some((path, rules.clone()));
+    }
+    rules.unwrap_or_else(default_rules)
+}
+
+/// 给子请求补上的鉴权
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Injection {
+    /// 追加到查询串的参数（已编码，如 "Policy=...&Signature=..."）
+    pub params: Option<String>,
+    /// 发给上游的 Cookie 头
+    pub cookie: Option<String>,
+}
+
+// 一个媒体会话（同一主机同一目录下的播放列表）记录到的签名
+#[derive(Debug, Clone)]
+struct AuthSession {
+    rule: String,
+    host: String,
+    // 记录签名的请求所在目录，目录下的子请求优先使用它
+    scope: String,
+    // 原样保存的 "名=值"，避免重新编码破坏签名
+    params: Vec<String>,
+    cookies: Vec<String>,
+    captured_at: Instant,
+    expires: Instant,
+}
+
+/// 按媒体会话保存的签名：同一 CDN 上同时播放的不同直播各自使用自己的签名
+#[derive(Debug, Default)]
+pub struct AuthSessions {
+    sessions: Vec<AuthSession>,
+}
+
+impl AuthSessions {
+    fn session_mut(&mut self, rule: &AuthRule, host: &str, scope: &str) -> &mut AuthSession {
+        let now = Instant::now();
+        self.sessions.retain(|s| s.expires > now);
+        let index = match self
+            .sessions
+            .iter()
+            .position(|s| s.rule == rule.name && s.host == host && s.scope == scope)
+        {
+            Some(index) => index,
+            None => {
+                self.sessions.push(AuthSession {
+                    rule: rule.name.clone(),
+                    host: host.to_string(),
+                    scope: scope.to_string(),
+                    params: Vec::new(),
+                    cookies: Vec::new(),
+                    captured_at: now,
+                    expires: now,
+                });
+                self.sessions.len() - 1
+            }
+        };
+        let session = &mut self.sessions[index];
+        session.captured_at = now;
+        session.expires = now + rule.ttl;
+        session
+    }
+
+    // 目录匹配时取最深的，否则取该主机上最近记录的
+    fn find(&self, rule: &AuthRule, host: &str, path: &str) -> Option<&AuthSession> {
+        let now = Instant::now();
+        let candidates = || {
+            self.sessions
+                .iter()
+                .filter(move |s| s.rule == rule.name && s.host == host && s.expires > now)
+        };
+        candidates()
+            .filter(|s| path.starts_with(&s.scope))
+            .max_by_key(|s| (s.scope.len(), s.captured_at))
+            .or_else(|| candidates().max_by_key(|s| s.captured_at))
+    }
+
+    /// 记录请求地址上的签名参数（播放列表等），返回是否记录了
+    pub fn capture_url(&mut self, rules: &[AuthRule], url: &Url) -> bool {
+        let (Some(host), Some(query)) = (url.host_str(), url.query()) else {
+            return false;
+        };
+        let decoded: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let mut captured = false;
+        for rule in rules.iter().filter(|r| r.matches_host(host) && path_matches(&r.capture_from, url.path())) {
+            if !rule.is_signed(&decoded) {
+                continue;
+            }
+            let params: Vec<String> = query
+                .split('&')
+                .filter(|pair| rule.params.iter().any(|p| pair.split('=').next() == Some(p.as_str())))
+                .map(str::to_string)
+                .collect();
+            let expires_at = rule
+                .expires_param
+                .as_ref()
+                .and_then(|name| decoded.iter().find(|(k, _)| k == name))
+                .and_then(|(_, v)| v.parse::<u64>().ok())
+                .map(instant_from_unix);
+            let session = self.session_mut(rule, host, scope_of(url.path()));
+            session.params = params;
+            if let Some(expires_at) = expires_at {
+                session.expires = session.expires.min(expires_at);
+            }
+            info!("签名({}): 记录 {} 的鉴权参数", rule.name, url.path());
+            captured = true;
+        }
+        captured
+    }
+
+    /// 记录上游响应中的签名 Cookie
+    pub fn capture_cookies(&mut self, rules: &[AuthRule], url: &Url, headers: &reqwest::header::HeaderMap) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+        let set_cookies: Vec<&str> = headers
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(|v| v.split(';').next())
+            .map(str::trim)
+            .collect();
+        if set_cookies.is_empty() {
+            return;
+        }
+        for rule in rules.iter().filter(|r| r.matches_host(host) && path_matches(&r.capture_from, url.path())) {
+            let cookies: Vec<String> = set_cookies
+                .iter()
+                .filter(|c| rule.cookies.iter().any(|name| c.split('=').next() == Some(name.as_str())))
+                .map(|c| c.to_string())
+                .collect();
+            if cookies.is_empty() {
+                continue;
+            }
+            let session = self.session_mut(rule, host, scope_of(url.path()));
+            for cookie in cookies {
+                let name = cookie.split('=').next().unwrap_or_default().to_string();
+                session.cookies.retain(|c| c.split('=').next() != Some(name.as_str()));
+                session.cookies.push(cookie);
+            }
+            info!("签名({}): 记录 {} 的鉴权 Cookie", rule.name, url.path());
+        }
+    }
+
+    /// 子请求（分片、密钥等）自身没有签名时，补上同一会话记录到的参数与 Cookie
+    pub fn inject(&self, rules: &[AuthRule], url: &Url) -> Injection {
+        let mut injection = Injection::default();
+        let Some(host) = url.host_str() else {
+            return injection;
+        };
+        let decoded: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let mut params: Vec<&str> = Vec::new();
+        let mut cookies: Vec<&str> = Vec::new();
+        for rule in rules.iter().filter(|r| r.matches_host(host) && path_matches(&r.inject_into, url.path())) {
+            let Some(session) = self.find(rule, host, url.path()) else {
+                continue;
+            };
+            if !rule.is_signed(&decoded) {
+                params.extend(session.params.iter().map(String::as_str));
+            }
+            cookies.extend(session.cookies.iter().map(String::as_str));
+        }
+        if !params.is_empty() {
+            injection.params = Some(params.join("&"));
+        }
+        if !cookies.is_empty() {
+            injection.cookie = Some(cookies.join("; "));
+        }
+        injection
+    }
+
+    /// 改写播放列表时补给子地址的签名参数（见 hls::rewrite）
+    pub fn query_for(&self, rules: &[AuthRule], url: &Url) -> Option<String> {
+        let host = url.host_str()?;
+        let params: Vec<&str> = rules
+            .iter()
+            .filter(|r| r.matches_host(host))
+            .filter_map(|r| self.find(r, host, url.path()))
+            .flat_map(|s| s.params.iter().map(String::as_str))
+            .collect();
+        (!params.is_empty()).then(|| params.join("&"))
+    }
+}
+
+// "/live/abc/index.m3u8" -> "/live/abc/"
+fn scope_of(path: &str) -> &str {
+    &path[..path.rfind('/').map_or(0, |i| i + 1)]
+}
+
+fn instant_from_unix(secs: u64) -> Instant {
+    let now_unix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    Instant::now() + Duration::from_secs(secs.saturating_sub(now_unix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_and_inject() {
+        let rules = default_rules();
+        let mut sessions = AuthSessions::default();
+        let url = |s: &str| Url::parse(s).unwrap();
+        let far_future = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 3600;
+
+        // 未签名的播放列表不记录
+        assert!(!sessions.capture_url(&rules, &url("https://cdn.example.com/a/index.m3u8?x=1")));
+        let signed_a = format!(
+            "https://cdn.example.com/a/index.m3u8?Policy=eyJ~a_&Signature=s1~x&Key-Pair-Id=K1&Expires={}&x=1",
+            far_future
+        );
+        assert!(sessions.capture_url(&rules, &url(&signed_a)));
+        assert!(sessions.capture_url(
+            &rules,
+            &url("https://cdn.example.com/b/index.m3u8?Signature=s2&Key-Pair-Id=K2")
+        ));
+
+        // 各自目录下的分片用各自的签名，参数原样保留
+        let a = sessions.inject(&rules, &url("https://cdn.example.com/a/seg1.ts"));
+        assert_eq!(
+            a.params.as_deref(),
+            Some(format!("Policy=eyJ~a_&Signature=s1~x&Key-Pair-Id=K1&Expires={}", far_future).as_str())
+        );
+        let b = sessions.inject(&rules, &url("https://cdn.example.com/b/hd/seg1.ts"));
+        assert_eq!(b.params.as_deref(), Some("Signature=s2&Key-Pair-Id=K2"));
+        // 目录外的分片用最近记录的，其他主机、已签名的请求、非子请求不补
+        let other = sessions.inject(&rules, &url("https://cdn.example.com/c/seg.ts"));
+        assert_eq!(other.params, b.params);
+        assert_eq!(sessions.inject(&rules, &url("https://other.example.com/a/seg.ts")), Injection::default());
+        assert_eq!(
+            sessions.inject(&rules, &url("https://cdn.example.com/a/seg.ts?Signature=z&Key-Pair-Id=K")),
+            Injection::default()
+        );
+        assert_eq!(sessions.inject(&rules, &url("https://cdn.example.com/a/cover.jpg")), Injection::default());
+        assert_eq!(
+            sessions.query_for(&rules, &url("https://cdn.example.com/b/index.m3u8")).as_deref(),
+            Some("Signature=s2&Key-Pair-Id=K2")
+        );
+
+        // Akamai Cookie
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.append(reqwest::header::SET_COOKIE, "hdntl=exp=1~acl=%2f*~hmac=ab; path=/; HttpOnly".parse().unwrap());
+        headers.append(reqwest::header::SET_COOKIE, "other=1".parse().unwrap());
+        sessions.capture_cookies(&rules, &url("https://akamai.example.net/live/master.m3u8"), &headers);
+        let injected = sessions.inject(&rules, &url("https://akamai.example.net/live/1080/seg.ts"));
+        assert_eq!(injected.cookie.as_deref(), Some("hdntl=exp=1~acl=%2f*~hmac=ab"));
+        assert_eq!(injected.params, None);
+
+        // 自定义规则：只匹配某个域名，已过期的签名不再使用
+        let custom = parse_rules(
+            r#"[{"name":"edge","host":"*.example.org","params":["token"],"ttl_secs":0},{"host":"x"}]"#,
+        )
+        .unwrap();
+        assert_eq!(custom.len(), 1);
+        assert!(custom[0].matches_host("v.example.org") && !custom[0].matches_host("example.com"));
+        assert_eq!(AuthRule::from_json(&custom[0].to_json()).as_ref(), Some(&custom[0]));
+        assert!(sessions.capture_url(&custom, &url("https://v.example.org/p/list?token=t")));
+        assert_eq!(sessions.inject(&custom, &url("https://v.example.org/p/seg")), Injection::default());
+        assert!(parse_rules("{}").is_err());
+    }
+}
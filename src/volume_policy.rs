@@ -0,0 +1,254 @@
+use crate::EngineEvent;
+use crate::renderer::Renderer;
+use crate::renderer_group::RendererGroup;
+use chrono::{Local, Timelike};
+use log::{debug, info};
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// 淡入/淡出分成多少步（每步一次 SetVolume）
+const FADE_STEPS: u32 = 10;
+// 检查音量是否超过上限（进入安静时段、用遥控器调大）的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// 设置了安静时段但没有指定上限时使用
+const DEFAULT_QUIET_MAX_VOLUME: u32 = 30;
+
+// 由界面设置的策略，未设置时读取环境变量
+static POLICY: RwLock<Option<VolumePolicy>> = RwLock::new(None);
+
+/// 安静时段：每天 start..end（当天的第几分钟，可跨零点）内音量不超过 max_volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start_min: u32,
+    pub end_min: u32,
+    pub max_volume: u32,
+}
+
+impl QuietHours {
+    /// 解析 "22:00-07:30" 形式的时段
+    pub fn parse(value: &str, max_volume: u32) -> Option<Self> {
+        let (start, end) = value.trim().split_once('-')?;
+        let minutes = |s: &str| -> Option<u32> {
+            let (h, m) = s.trim().split_once(':').unwrap_or((s.trim(), "0"));
+            let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+            (h < 24 && m < 60).then_some(h * 60 + m)
+        };
+        Some(Self {
+            start_min: minutes(start)?,
+            end_min: minutes(end)?,
+            max_volume: max_volume.min(100),
+        })
+    }
+
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        if self.start_min <= self.end_min {
+            (self.start_min..self.end_min).contains(&minute_of_day)
+        } else {
+            minute_of_day >= self.start_min || minute_of_day < self.end_min
+        }
+    }
+}
+
+/// 音量策略：切歌淡入淡出、开始投屏时的音量与最大音量限制。
+/// 设备配置（renderer_quirks.json）里的 max_volume 按设备进一步限制
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VolumePolicy {
+    /// Stop 前淡出、Play 后淡入的时长（毫秒），0 表示直接切
+    pub fade_ms: u32,
+    /// 开始投屏时设置的音量，None 表示保持设备当前音量
+    pub start_volume: Option<u32>,
+    /// 所有设备的最大音量
+    pub max_volume: Option<u32>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl VolumePolicy {
+    /// 读取 KTV_VOLUME_FADE_MS、KTV_START_VOLUME、KTV_MAX_VOLUME、
+    /// KTV_QUIET_HOURS（如 "22:00-07:00"）与 KTV_QUIET_MAX_VOLUME
+    pub fn from_env() -> Self {
+        let number = |k: &str| env::var(k).ok().and_then(|v| v.trim().parse::<u32>().ok());
+        let quiet_max = number("KTV_QUIET_MAX_VOLUME").unwrap_or(DEFAULT_QUIET_MAX_VOLUME);
+        Self {
+            fade_ms: number("KTV_VOLUME_FADE_MS").unwrap_or(0),
+            start_volume: number("KTV_START_VOLUME").map(|v| v.min(100)),
+            max_volume: number("KTV_MAX_VOLUME").map(|v| v.min(100)),
+            quiet_hours: env::var("KTV_QUIET_HOURS")
+                .ok()
+                .and_then(|v| QuietHours::parse(&v, quiet_max)),
+        }
+    }
+
+    /// 设备在一天中第 minute_of_day 分钟允许的最大音量；device_max 为设备配置中的 max_volume
+    pub fn cap(&self, device_max: Option<u32>, minute_of_day: u32) -> u32 {
+        let quiet = self
+            .quiet_hours
+            .filter(|q| q.contains(minute_of_day))
+            .map(|q| q.max_volume);
+        [self.max_volume, device_max, quiet]
+            .into_iter()
+            .flatten()
+            .fold(100, u32::min)
+    }
+
+    fn fade(&self) -> Option<Duration> {
+        (self.fade_ms > 0).then(|| Duration::from_millis(self.fade_ms as u64))
+    }
+}
+
+/// 设置音量策略，None 表示改回读取环境变量
+pub fn set_policy(policy: Option<VolumePolicy>) {
+    if let Ok(mut guard) = POLICY.write() {
+        *guard = policy;
+    }
+}
+
+/// 当前生效的音量策略
+pub fn policy() -> VolumePolicy {
+    POLICY
+        .read()
+        .ok()
+        .and_then(|p| p.clone())
+        .unwrap_or_else(VolumePolicy::from_env)
+}
+
+/// 设备此刻允许的最大音量
+pub fn cap_for(device: &dyn Renderer) -> u32 {
+    let now = Local::now();
+    policy().cap(device.quirks().max_volume, now.hour() * 60 + now.minute())
+}
+
+/// 开始投屏时设置策略中的起始音量（不超过上限）
+pub(crate) async fn apply_start_volume(device: &dyn Renderer) {
+    let Some(volume) = policy().start_volume else {
+        return;
+    };
+    let volume = volume.min(cap_for(device));
+    info!("设置起始音量 {}: {}", volume, device.name());
+    if let Err(e) = device.set_volume(volume).await {
+        debug!("设置起始音量失败: {}", e);
+    }
+}
+
+/// 切歌 Stop 之前调用：正在播放时在淡出时长内把音量降到 0，否则直接静音；
+/// 返回 Play 之后要淡入到的音量（不超过上限）。
+/// 未开启淡入淡出或设备不支持音量控制时返回 None
+pub(crate) async fn fade_out(device: &dyn Renderer, playing: bool) -> Option<u32> {
+    let fade = policy().fade()?;
+    let current = device
+        .get_volume()
+        .await
+        .inspect_err(|e| debug!("读取音量失败，跳过淡出: {}", e))
+        .ok()?;
+    let target = current.min(cap_for(device));
+    if playing {
+        ramp(device, current, 0, fade).await;
+    } else {
+        let _ = device.set_volume(0).await;
+    }
+    Some(target)
+}
+
+/// Play 之后调用：从 0 淡入到 target
+pub(crate) async fn fade_in(device: &dyn Renderer, target: u32) {
+    let fade = policy().fade().unwrap_or_default();
+    ramp(device, 0, target, fade).await;
+}
+
+async fn ramp(device: &dyn Renderer, from: u32, to: u32, duration: Duration) {
+    let step_delay = duration / FADE_STEPS;
+    for step in 1..=FADE_STEPS {
+        if let Err(e) = device.set_volume(fade_step(from, to, step)).await {
+            debug!("淡入淡出中断: {}", e);
+            return;
+        }
+        if step < FADE_STEPS {
+            tokio::time::sleep(step_delay).await;
+        }
+    }
+}
+
+// 第 step 步（1..=FADE_STEPS）的音量，最后一步正好等于 to
+fn fade_step(from: u32, to: u32, step: u32) -> u32 {
+    let delta = (to as i64 - from as i64) * step as i64 / FADE_STEPS as i64;
+    (from as i64 + delta) as u32
+}
+
+/// 在引擎 Runtime 上定期检查各设备音量，超过上限时（进入安静时段、用遥控器调大）调回上限
+pub(crate) async fn run(
+    group: Arc<RendererGroup>,
+    mut engine_events: broadcast::Receiver<EngineEvent>,
+) {
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                for device in group.devices() {
+                    // 没有限制时不必查询
+                    if cap_for(&*device) >= 100 {
+                        continue;
+                    }
+                    match device.get_volume().await {
+                        Ok(volume) => enforce_cap(&*device, volume).await,
+                        Err(e) => debug!("查询音量失败: {}", e),
+                    }
+                }
+            }
+            event = engine_events.recv() => {
+                match event {
+                    // 事件来自主设备的 RenderingControl 订阅
+                    Ok(EngineEvent::VolumeChanged(v)) => enforce_cap(&*group.primary(), v).await,
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+}
+
+async fn enforce_cap(device: &dyn Renderer, volume: u32) {
+    let cap = cap_for(device);
+    if volume <= cap {
+        return;
+    }
+    info!("{} 的音量 {} 超过上限 {}，调回上限", device.name(), volume, cap);
+    if let Err(e) = device.set_volume(cap).await {
+        debug!("调低音量失败: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_cap_and_fade_steps() {
+        let quiet = QuietHours::parse("22:00-07:30", 20).unwrap();
+        assert!(quiet.contains(23 * 60));
+        assert!(quiet.contains(7 * 60 + 29));
+        assert!(!quiet.contains(7 * 60 + 30));
+        assert!(!quiet.contains(12 * 60));
+        // 不跨零点的时段
+        assert!(QuietHours::parse("13:00-14:00", 20).unwrap().contains(13 * 60 + 30));
+        assert!(QuietHours::parse("25:00-07:00", 20).is_none());
+        assert!(QuietHours::parse("22:00", 20).is_none());
+
+        let policy = VolumePolicy {
+            max_volume: Some(80),
+            quiet_hours: Some(quiet),
+            ..Default::default()
+        };
+        // 取全局上限、设备上限与安静时段上限中最小的
+        assert_eq!(policy.cap(None, 12 * 60), 80);
+        assert_eq!(policy.cap(Some(60), 12 * 60), 60);
+        assert_eq!(policy.cap(Some(60), 23 * 60), 20);
+        assert_eq!(VolumePolicy::default().cap(None, 0), 100);
+
+        let steps: Vec<u32> = (1..=FADE_STEPS).map(|s| fade_step(35, 0, s)).collect();
+        assert_eq!(steps.first(), Some(&32));
+        assert_eq!(steps.last(), Some(&0));
+        assert!(steps.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(fade_step(0, 42, FADE_STEPS), 42);
+    }
+}